@@ -0,0 +1,121 @@
+//! Throughput of `process_records` over synthetic in-memory inputs, run with `cargo bench`.
+//!
+//! Criterion isn't a dependency, so this is a plain `harness = false` binary timing a few
+//! iterations of each scenario and reporting the best rows/second.
+//!
+//! Baseline (release build, single core Xeon):
+//! - single hot client, 50k rows: ~1.7M rows/s, since balances are cached on `User` (it was
+//!   ~23k rows/s while `User::available` scanned the whole history on each withdrawal)
+//! - 1000 clients, 200k rows: ~1.5M rows/s
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use csv::ReaderBuilder;
+use csv_ledger::{PaymentEngine, ProcessOptions, process_records};
+
+const ITERATIONS: usize = 5;
+
+/// Share of each transaction type in a synthetic input, in percent
+struct Mix {
+    deposits: u64,
+    withdrawals: u64,
+    disputes: u64,
+    resolves: u64,
+    chargebacks: u64,
+}
+
+const DEFAULT_MIX: Mix = Mix {
+    deposits: 60,
+    withdrawals: 30,
+    disputes: 6,
+    resolves: 3,
+    chargebacks: 1,
+};
+
+/// without chargebacks, which would lock the only client early on and skip the rest of the input
+const HOT_CLIENT_MIX: Mix = Mix {
+    deposits: 62,
+    withdrawals: 30,
+    disputes: 5,
+    resolves: 3,
+    chargebacks: 0,
+};
+
+/// xorshift, so every run generates the same input
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// `rows` csv rows spread over `clients`, disputes and their follow ups referencing earlier deposits
+fn synthetic(clients: u16, rows: usize, mix: &Mix) -> String {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut input = String::from("type,client,tx,amount\n");
+    // (tx, client) of deposits, to reference from disputes
+    let mut deposits: Vec<(u32, u16)> = Vec::new();
+    for tx in 1..=rows as u32 {
+        let client = (rng.next() % clients as u64) as u16 + 1;
+        let roll = rng.next() % 100;
+        let amount = format!("{}.{:04}", rng.next() % 100 + 1, rng.next() % 10_000);
+        let referenced = deposits
+            .get((rng.next() % deposits.len().max(1) as u64) as usize)
+            .copied();
+        let withdrawals = mix.deposits + mix.withdrawals;
+        let disputes = withdrawals + mix.disputes;
+        let resolves = disputes + mix.resolves;
+        let chargebacks = resolves + mix.chargebacks;
+        let row = match (roll, referenced) {
+            (roll, _) if roll < mix.deposits => {
+                deposits.push((tx, client));
+                format!("deposit,{},{},{}", client, tx, amount)
+            }
+            (roll, _) if roll < withdrawals => format!("withdrawal,{},{},{}", client, tx, amount),
+            (roll, Some((tx, client))) if roll < disputes => format!("dispute,{},{},", client, tx),
+            (roll, Some((tx, client))) if roll < resolves => format!("resolve,{},{},", client, tx),
+            (roll, Some((tx, client))) if roll < chargebacks => {
+                format!("chargeback,{},{},", client, tx)
+            }
+            // nothing to reference yet, or rounding the mix up to 100%
+            _ => {
+                deposits.push((tx, client));
+                format!("deposit,{},{},{}", client, tx, amount)
+            }
+        };
+        input.push_str(&row);
+        input.push('\n');
+    }
+    input
+}
+
+fn bench(name: &str, clients: u16, rows: usize, mix: &Mix) {
+    let input = synthetic(clients, rows, mix);
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let mut engine = PaymentEngine::new();
+        let start = Instant::now();
+        process_records(&mut reader, &mut engine, &ProcessOptions::default()).unwrap();
+        best = best.min(start.elapsed());
+        black_box(engine);
+    }
+    println!(
+        "{:<28} {:>8} rows  {:>10.0} rows/s",
+        name,
+        rows,
+        rows as f64 / best.as_secs_f64()
+    );
+}
+
+fn main() {
+    bench("single hot client", 1, 50_000, &HOT_CLIENT_MIX);
+    bench("many clients", 1_000, 200_000, &DEFAULT_MIX);
+}
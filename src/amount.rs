@@ -0,0 +1,138 @@
+use std::fmt::{Debug, Display, Formatter, Result as FormatResult};
+use std::hash::Hash;
+use std::ops::{Add, Neg, Sub};
+use std::str::FromStr;
+
+use crate::{AppError, ParseOptions, TickInt, format_ticks, parse_ticks};
+
+/// Integer type ticks are counted in, `TickInt` by default.
+///
+/// Narrower ones halve the memory of every balance and transaction, wider ones push back the
+/// overflow of balances summing many transactions. Either way a single transaction still carries
+/// `i32` ticks, which every width holds, and reports widen to `i128`, which holds every width.
+pub trait Ticks:
+    Copy
+    + Debug
+    + Default
+    + Ord
+    + Hash
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Neg<Output = Self>
+    + From<i32>
+    + Into<i128>
+    + TryFrom<i128>
+{
+    const ZERO: Self;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn checked_sub(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_ticks {
+    ($($int:ty),*) => {
+        $(
+            impl Ticks for $int {
+                const ZERO: Self = 0;
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$int>::checked_add(self, other)
+                }
+
+                fn checked_sub(self, other: Self) -> Option<Self> {
+                    <$int>::checked_sub(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_ticks!(i32, i64, i128);
+
+/// Money as a whole number of ticks (see `TICK_SIZE`), so it never goes through floating point.
+///
+/// Counted in `T`, by default `TickInt`, wider than the `i32` ticks of a single transaction, so
+/// balances summing many of them can't overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount<T = TickInt>(T);
+
+impl<T: Ticks> Amount<T> {
+    pub const ZERO: Amount<T> = Amount(T::ZERO);
+
+    pub const fn from_ticks(ticks: T) -> Self {
+        Self(ticks)
+    }
+
+    pub const fn to_ticks(self) -> T {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < T::ZERO
+    }
+
+    /// the same amount counted in `i128`, which holds every `Ticks` width
+    pub fn widen(self) -> Amount<i128> {
+        Amount(self.0.into())
+    }
+
+    /// `amount` counted in `T`, `None` when it doesn't fit
+    pub fn narrow(amount: Amount<i128>) -> Option<Self> {
+        T::try_from(amount.0).ok().map(Amount)
+    }
+}
+
+impl<T: Ticks> From<i32> for Amount<T> {
+    fn from(ticks: i32) -> Self {
+        Self(ticks.into())
+    }
+}
+
+impl<T: Ticks> Add for Amount<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl<T: Ticks> Sub for Amount<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl<T: Ticks> Neg for Amount<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Amount(-self.0)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AppError;
+
+    /// parses with the default `ParseOptions`, see `parse_amount`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ticks(s, &ParseOptions::default()).map(Amount)
+    }
+}
+
+impl<T: Ticks> Display for Amount<T> {
+    /// exact decimal with `DECIMALS` places, see `format_ticks`
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        write!(f, "{}", format_ticks(self.0))
+    }
+}
@@ -0,0 +1,93 @@
+use csv::Reader;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::io::Read;
+
+use crate::{AppError, TransactionStatus, TransactionType, column_map};
+
+/// A single state transition of a transaction, as recorded in `--audit` mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub tx_id: u32,
+    /// input line that caused the transition, absent for programmatic inputs
+    pub line: Option<usize>,
+    /// `None` when the transaction was created
+    pub from: Option<TransactionStatus>,
+    pub to: TransactionStatus,
+}
+
+impl Display for AuditEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "line -: ")?,
+        }
+        match self.from {
+            Some(from) => write!(f, "tx {} {:?} -> {:?}", self.tx_id, from, self.to),
+            None => write!(f, "tx {} created as {:?}", self.tx_id, self.to),
+        }
+    }
+}
+
+/// A resolve or chargeback found by `audit_control_flow` without an open dispute for its tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlFlowFinding {
+    pub line: usize,
+    pub tx_type: TransactionType,
+    pub tx_id: u32,
+}
+
+impl Display for ControlFlowFinding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        write!(
+            f,
+            "line {}: {:?} of tx {} has no open dispute",
+            self.line, self.tx_type, self.tx_id
+        )
+    }
+}
+
+/// Static scan over the record stream, independent of the engine state: reports every resolve
+/// or chargeback whose tx has no dispute opened before it (and not yet closed by another
+/// resolve or chargeback).
+///
+/// Rows that can't be parsed are skipped, as reporting them is left to processing itself.
+pub fn audit_control_flow<R: Read>(
+    reader: &mut Reader<R>,
+) -> Result<Vec<ControlFlowFinding>, AppError> {
+    let columns = column_map(reader)?.unwrap_or_default();
+    let mut open_disputes: HashSet<u32> = HashSet::new();
+    let mut findings = Vec::new();
+    for (i, result) in reader.records().enumerate() {
+        let record = result?;
+        let line = record
+            .position()
+            .map_or(i + 1, |position| position.line() as usize);
+        let tx_type = columns
+            .get(&record, 0)
+            .map(|s| s.trim().to_lowercase().parse());
+        let tx_id = columns.get(&record, 2).map(|s| s.trim().parse::<u32>());
+        let (Some(Ok(tx_type)), Some(Ok(tx_id))) = (tx_type, tx_id) else {
+            continue;
+        };
+        match tx_type {
+            TransactionType::Dispute => {
+                open_disputes.insert(tx_id);
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                if !open_disputes.remove(&tx_id) {
+                    findings.push(ControlFlowFinding {
+                        line,
+                        tx_type,
+                        tx_id,
+                    });
+                }
+            }
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Authorization
+            | TransactionType::Capture => {}
+        }
+    }
+    Ok(findings)
+}
@@ -0,0 +1,453 @@
+use serde_core::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_core::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt::{Formatter, Result as FormatResult};
+use std::io::Write;
+
+use crate::{AppError, OutputFormat, ParseOptions, Units, parse_ticks, ticks_to_decimal_string};
+
+const FIELDS: &[&str] = &["client", "available", "held", "total", "locked"];
+
+const SUMMARY_FIELDS: &[&str] = &["clients", "locked", "total", "held"];
+
+/// Aggregates over every client printed instead of their rows, see `PaymentEngine::summary`.
+/// Money fields are kept as ticks, as in `ClientBalance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub clients: usize,
+    pub locked: usize,
+    /// grand total of every client, see `PaymentEngine::grand_total_ticks`
+    pub total: i128,
+    pub held: i128,
+    /// inputs skipped for their type, see `ProcessOptions::types`, only rendered when set
+    pub filtered: Option<usize>,
+}
+
+impl Summary {
+    pub fn to_csv_row(&self) -> String {
+        self.to_csv_row_in(Units::Currency)
+    }
+
+    /// same as `to_csv_row`, through `Serialize`
+    pub fn to_csv_row_in(&self, units: Units) -> String {
+        csv_row(&SummaryRow {
+            summary: self,
+            units,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        self.to_json_in(Units::Currency)
+    }
+
+    pub fn to_json_in(&self, units: Units) -> String {
+        let filtered = self
+            .filtered
+            .map(|filtered| format!(r#","filtered":{}"#, filtered))
+            .unwrap_or_default();
+        format!(
+            r#"{{"clients":{},"locked":{},"total":{},"held":{}{}}}"#,
+            self.clients,
+            self.locked,
+            units.render(self.total),
+            units.render(self.held),
+            filtered
+        )
+    }
+}
+
+/// `Summary` as serialized, with its money fields in `units`
+struct SummaryRow<'a> {
+    summary: &'a Summary,
+    units: Units,
+}
+
+impl Serialize for SummaryRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let summary = self.summary;
+        let mut state = serializer.serialize_struct("Summary", SUMMARY_FIELDS.len() + 1)?;
+        state.serialize_field("clients", &summary.clients)?;
+        state.serialize_field("locked", &summary.locked)?;
+        state.serialize_field("total", &self.units.render(summary.total))?;
+        state.serialize_field("held", &self.units.render(summary.held))?;
+        match summary.filtered {
+            Some(filtered) => state.serialize_field("filtered", &filtered)?,
+            None => state.skip_field("filtered")?,
+        }
+        state.end()
+    }
+}
+
+impl Serialize for Summary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SummaryRow {
+            summary: self,
+            units: Units::Currency,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Currency a client transacts in, see `ParseOptions::currency_precision`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Currency {
+    /// uppercase code, e.g. `JPY`
+    pub code: String,
+    /// decimal places amounts of this currency have, at most `DECIMALS`
+    pub decimals: u32,
+}
+
+/// Output row of a client. Money fields are kept as ticks and only rendered
+/// with 4 decimals when serialized, so every output format shares the same formatting.
+///
+/// Ticks are widened to `i128`, so rows of engines of every `Ticks` width compare equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientBalance {
+    pub client: u16,
+    pub available: i128,
+    pub held: i128,
+    pub total: i128,
+    pub locked: bool,
+    /// ids of the transactions contributing to `held`, only listed in json output when requested
+    pub held_tx_ids: Option<Vec<u32>>,
+    /// number of transactions still disputed, listed in csv and json output when requested
+    pub open_disputes: Option<usize>,
+    /// currency of the client in multi-currency inputs, rendering money fields with its decimals
+    /// and listed in csv and json output
+    pub currency: Option<Currency>,
+}
+
+impl ClientBalance {
+    /// `ticks` in `units`, with the decimals of the client's currency if any
+    fn render(&self, units: Units, ticks: i128) -> String {
+        match (units, &self.currency) {
+            (Units::Currency, Some(currency)) => ticks_to_decimal_string(ticks, currency.decimals),
+            _ => units.render(ticks),
+        }
+    }
+
+    pub fn to_csv_row(&self) -> String {
+        self.to_csv_row_in(Units::Currency)
+    }
+
+    /// money fields rendered in `units`, see `Units::render`, without the optional columns
+    pub fn to_csv_row_in(&self, units: Units) -> String {
+        csv_row(&BalanceRow {
+            balance: self,
+            units,
+            columns: Some(Columns::default()),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        self.to_json_in(Units::Currency)
+    }
+
+    /// money fields rendered in `units`, see `Units::render`
+    pub fn to_json_in(&self, units: Units) -> String {
+        let held_tx_ids = match &self.held_tx_ids {
+            Some(ids) => {
+                let ids: Vec<String> = ids.iter().map(u32::to_string).collect();
+                format!(r#","held_tx_ids":[{}]"#, ids.join(","))
+            }
+            None => String::new(),
+        };
+        let open_disputes = self
+            .open_disputes
+            .map(|count| format!(r#","open_disputes":{}"#, count))
+            .unwrap_or_default();
+        let currency = match &self.currency {
+            Some(currency) => format!(r#","currency":"{}""#, currency.code),
+            None => String::new(),
+        };
+        // decimals and ticks are valid json numbers, so they're written unquoted
+        format!(
+            r#"{{"client":{},"available":{},"held":{},"total":{},"locked":{}{}{}{}}}"#,
+            self.client,
+            self.render(units, self.available),
+            self.render(units, self.held),
+            self.render(units, self.total),
+            self.locked,
+            held_tx_ids,
+            open_disputes,
+            currency
+        )
+    }
+}
+
+/// Optional csv columns, listed on every row once any row holds them, as rows share the header
+#[derive(Debug, Clone, Copy, Default)]
+struct Columns {
+    open_disputes: bool,
+    currency: bool,
+}
+
+impl Columns {
+    fn of(balances: &[ClientBalance]) -> Self {
+        Self {
+            open_disputes: balances
+                .iter()
+                .any(|balance| balance.open_disputes.is_some()),
+            currency: balances.iter().any(|balance| balance.currency.is_some()),
+        }
+    }
+
+    fn header(self) -> Vec<&'static str> {
+        let mut header = FIELDS.to_vec();
+        header.extend(self.open_disputes.then_some("open_disputes"));
+        header.extend(self.currency.then_some("currency"));
+        header
+    }
+}
+
+/// `ClientBalance` as serialized, with its money fields in `units`, followed by `columns` in csv,
+/// or by the optional fields it holds when `None`
+struct BalanceRow<'a> {
+    balance: &'a ClientBalance,
+    units: Units,
+    columns: Option<Columns>,
+}
+
+impl Serialize for BalanceRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (balance, units) = (self.balance, self.units);
+        let mut state = serializer.serialize_struct("ClientBalance", FIELDS.len() + 3)?;
+        state.serialize_field("client", &balance.client)?;
+        state.serialize_field("available", &balance.render(units, balance.available))?;
+        state.serialize_field("held", &balance.render(units, balance.held))?;
+        state.serialize_field("total", &balance.render(units, balance.total))?;
+        state.serialize_field("locked", &balance.locked)?;
+        match self.columns {
+            Some(columns) => {
+                if columns.open_disputes {
+                    state.serialize_field(
+                        "open_disputes",
+                        &balance.open_disputes.unwrap_or_default(),
+                    )?;
+                }
+                if columns.currency {
+                    let code = balance.currency.as_ref().map(|currency| &currency.code);
+                    state.serialize_field("currency", code.map_or("", String::as_str))?;
+                }
+            }
+            None => {
+                if let Some(ids) = &balance.held_tx_ids {
+                    state.serialize_field("held_tx_ids", ids)?;
+                }
+                if let Some(open_disputes) = balance.open_disputes {
+                    state.serialize_field("open_disputes", &open_disputes)?;
+                }
+                if let Some(currency) = &balance.currency {
+                    state.serialize_field("currency", &currency.code)?;
+                }
+            }
+        }
+        state.end()
+    }
+}
+
+/// `row` as a csv line, without header nor terminator
+fn csv_row<T: Serialize>(row: &T) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(row).expect("output rows serialize to csv");
+    let mut line = String::from_utf8(writer.into_inner().expect("written into memory"))
+        .expect("output rows are utf-8");
+    line.pop();
+    line
+}
+
+impl Serialize for ClientBalance {
+    /// money fields with 4 decimals, or with those of `currency`, followed by the optional fields
+    /// the row holds
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BalanceRow {
+            balance: self,
+            units: Units::Currency,
+            columns: None,
+        }
+        .serialize(serializer)
+    }
+}
+
+struct ClientBalanceVisitor;
+
+fn parse_money<E: de::Error>(raw: &str) -> Result<i128, E> {
+    parse_ticks(raw, &ParseOptions::default())
+        .map(i128::from)
+        .map_err(E::custom)
+}
+
+impl<'de> Visitor<'de> for ClientBalanceVisitor {
+    type Value = ClientBalance;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> FormatResult {
+        write!(f, "a client balance row")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut next_field = |index: usize| -> Result<String, A::Error> {
+            seq.next_element::<String>()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))
+        };
+        let client = next_field(0)?
+            .parse()
+            .map_err(|_| de::Error::custom("invalid client id"))?;
+        let available = parse_money(&next_field(1)?)?;
+        let held = parse_money(&next_field(2)?)?;
+        let total = parse_money(&next_field(3)?)?;
+        let locked = next_field(4)?
+            .parse()
+            .map_err(|_| de::Error::custom("invalid locked flag"))?;
+        Ok(ClientBalance {
+            client,
+            available,
+            held,
+            total,
+            locked,
+            held_tx_ids: None,
+            open_disputes: None,
+            currency: None,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut client, mut available, mut held, mut total, mut locked) =
+            (None, None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "client" => client = Some(map.next_value::<u16>()?),
+                "available" => available = Some(parse_money(&map.next_value::<String>()?)?),
+                "held" => held = Some(parse_money(&map.next_value::<String>()?)?),
+                "total" => total = Some(parse_money(&map.next_value::<String>()?)?),
+                "locked" => locked = Some(map.next_value::<bool>()?),
+                other => return Err(de::Error::unknown_field(other, FIELDS)),
+            }
+        }
+        Ok(ClientBalance {
+            client: client.ok_or_else(|| de::Error::missing_field("client"))?,
+            available: available.ok_or_else(|| de::Error::missing_field("available"))?,
+            held: held.ok_or_else(|| de::Error::missing_field("held"))?,
+            total: total.ok_or_else(|| de::Error::missing_field("total"))?,
+            locked: locked.ok_or_else(|| de::Error::missing_field("locked"))?,
+            held_tx_ids: None,
+            open_disputes: None,
+            currency: None,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientBalance {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("ClientBalance", FIELDS, ClientBalanceVisitor)
+    }
+}
+
+pub fn write_balances<W: Write>(
+    writer: &mut W,
+    balances: impl Iterator<Item = ClientBalance>,
+    format: OutputFormat,
+) -> Result<(), AppError> {
+    write_balances_with(writer, balances, format, Units::Currency)
+}
+
+/// same as `write_balances`, rendering money fields in `units`
+pub fn write_balances_with<W: Write>(
+    writer: &mut W,
+    balances: impl Iterator<Item = ClientBalance>,
+    format: OutputFormat,
+    units: Units,
+) -> Result<(), AppError> {
+    match format {
+        OutputFormat::Csv => {
+            let balances: Vec<ClientBalance> = balances.collect();
+            let columns = Columns::of(&balances);
+            // the header is written by hand, so it's there even without any row
+            let mut csv = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut *writer);
+            csv.write_record(columns.header())?;
+            for balance in &balances {
+                csv.serialize(BalanceRow {
+                    balance,
+                    units,
+                    columns: Some(columns),
+                })?;
+            }
+            csv.flush()?;
+        }
+        OutputFormat::Json => {
+            let rows: Vec<String> = balances.map(|balance| balance.to_json_in(units)).collect();
+            writeln!(writer, "[{}]", rows.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-parses balances written as csv by `write_balances_with`, returning how many rows they
+/// hold, see `Config::self_check`.
+///
+/// Errors with `AppError::InvalidFormat` when the header isn't one `write_balances_with` writes,
+/// a row has another number of fields, or a field doesn't parse back, e.g. a `NaN` amount.
+pub fn self_check_csv(output: &[u8]) -> Result<usize, AppError> {
+    let invalid = |reason: String| AppError::InvalidFormat(format!("self-check: {}", reason));
+    let mut reader = csv::Reader::from_reader(output);
+    let headers = reader
+        .headers()
+        .map_err(|err| invalid(err.to_string()))?
+        .clone();
+    let columns: Vec<&str> = headers.iter().collect();
+    let optional = columns.get(FIELDS.len()..).unwrap_or_default();
+    if columns.len() < FIELDS.len()
+        || columns[..FIELDS.len()] != FIELDS[..]
+        || !matches!(
+            optional,
+            [] | ["open_disputes"] | ["currency"] | ["open_disputes", "currency"]
+        )
+    {
+        return Err(invalid(format!("unexpected header {}", columns.join(","))));
+    }
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record.map_err(|err| invalid(err.to_string()))?;
+        // the header is line 1
+        let line = rows + 2;
+        for (field, raw) in columns.iter().zip(&record) {
+            let parses = match *field {
+                "client" => raw.parse::<u16>().is_ok(),
+                "open_disputes" => raw.parse::<usize>().is_ok(),
+                "currency" => true,
+                "locked" => raw.parse::<bool>().is_ok(),
+                _ => parse_ticks(raw, &ParseOptions::default()).is_ok(),
+            };
+            if !parses {
+                return Err(invalid(format!(
+                    "line {}: {} \"{}\" doesn't parse back",
+                    line, field, raw
+                )));
+            }
+        }
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+pub fn write_summary<W: Write>(
+    writer: &mut W,
+    summary: Summary,
+    format: OutputFormat,
+    units: Units,
+) -> Result<(), AppError> {
+    match format {
+        OutputFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut *writer);
+            csv.serialize(SummaryRow {
+                summary: &summary,
+                units,
+            })?;
+            csv.flush()?;
+        }
+        OutputFormat::Json => writeln!(writer, "{}", summary.to_json_in(units))?,
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::{
+    Amount, AppError, DECIMALS, ProcessOptions, STDIN_PATH, TickInt, TransactionType, TxRules,
+    TypeFilter, format_ticks, parse_tick_tolerance,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(AppError::InvalidArgument(format!("unknown format {}", s))),
+        }
+    }
+}
+
+/// Layout of the input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// `type,client,tx,amount` rows, with an optional header
+    #[default]
+    Csv,
+    /// one `{"type","client","tx","amount"}` json object per line
+    Ndjson,
+}
+
+impl FromStr for InputFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(AppError::InvalidArgument(format!(
+                "unknown input format {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// How money fields of the output are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    /// decimals with `DECIMALS` places, see `format_ticks`
+    #[default]
+    Currency,
+    /// raw tick counts, e.g. to debug rounding
+    Ticks,
+}
+
+impl Units {
+    pub fn render(self, ticks: i128) -> String {
+        match self {
+            Self::Currency => format_ticks(ticks),
+            Self::Ticks => ticks.to_string(),
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "currency" => Ok(Self::Currency),
+            "ticks" => Ok(Self::Ticks),
+            _ => Err(AppError::InvalidArgument(format!("unknown units {}", s))),
+        }
+    }
+}
+
+/// CLI options, parsed from `cargo run -- [options] <input_file>`
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub input_path: String,
+    pub input_format: InputFormat,
+    pub output_format: OutputFormat,
+    /// renders money fields as decimals or raw ticks
+    pub units: Units,
+    /// clients, both ends included, always given an output row, zeroed for the ones without
+    /// any activity
+    pub client_range: Option<RangeInclusive<u16>>,
+    /// writes the output into this path instead of stdout, see `run_to_file`
+    pub output: Option<String>,
+    /// only parses every record, reporting the first invalid one, without printing balances
+    pub check: bool,
+    /// rejects inputs that would otherwise be ignored, see `PaymentEngine::with_strict`
+    pub strict: bool,
+    /// processes each input at most once, see `PaymentEngine::with_global_dedupe`
+    pub dedupe_global: bool,
+    pub rules: TxRules,
+    /// errors instead of processing a headerless file positionally
+    pub require_header: bool,
+    /// records every transaction state transition and dumps it to stderr
+    pub audit: bool,
+    /// reports resolves and chargebacks lacking an open dispute on stderr, before processing
+    pub audit_control_flow: bool,
+    /// writes every parsed input as a canonical csv row on stderr, before processing
+    pub echo_normalized: bool,
+    /// prints per transaction type counters on stderr, see `Metrics`
+    pub metrics: bool,
+    /// prints how long reading, parsing and applying records took on stderr, see `Profile`
+    pub profile: bool,
+    /// checks the ledger invariants after processing, failing instead of writing balances that
+    /// break any, see `PaymentEngine::verify_invariants`
+    pub verify_invariants: bool,
+    /// re-parses the balances csv in memory before writing it, failing on malformed output, see
+    /// `self_check_csv`
+    pub self_check: bool,
+    /// prints every withdrawal ignored for insufficient funds on stderr, with its shortfall
+    pub report_rejected_withdrawals: bool,
+    /// silences every diagnostic otherwise written on stderr, see `run_with_diagnostics`
+    pub quiet: bool,
+    /// lists the disputed tx ids of every client in json output
+    pub held_tx_ids: bool,
+    /// adds the number of still disputed transactions of every client to csv and json output
+    pub open_disputes: bool,
+    /// only outputs locked clients
+    pub locked_only: bool,
+    /// prints a `Summary` instead of every client's balance
+    pub summary_only: bool,
+    /// skips every invalid record, failing once the others are processed, see
+    /// `AppError::SkippedRecords`
+    pub continue_on_error: bool,
+    /// saves the engine state into this path once processing stops, even on failure
+    pub snapshot: Option<String>,
+    /// restores the engine state from a snapshot and skips the records it had applied
+    pub resume: Option<String>,
+    /// seeds clients from a `client,available` csv before processing, see
+    /// `PaymentEngine::load_opening_balances`
+    pub opening_balances: Option<String>,
+    pub processing: ProcessOptions,
+}
+
+impl Config {
+    /// expects the program name as first argument, as in `env::args()`
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, AppError> {
+        let mut args = args.into_iter().skip(1);
+        let mut input_path = None;
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input-format" => config.input_format = parse_flag(&mut args, &arg)?,
+                "-o" | "--output" => config.output = Some(flag_value(&mut args, &arg)?),
+                "--format" => config.output_format = parse_flag(&mut args, &arg)?,
+                "--units" => config.units = parse_flag(&mut args, &arg)?,
+                "--client-range" => {
+                    config.client_range = Some(parse_client_range(&flag_value(&mut args, &arg)?)?)
+                }
+                "--require-header" => config.require_header = true,
+                "--strict" => config.strict = true,
+                "--dedupe-global" => config.dedupe_global = true,
+                "--check" => config.check = true,
+                "--allow-redispute" => config.rules.allow_redispute = true,
+                "--chargeback-overrides-resolve" => {
+                    config.rules.chargeback_overrides_resolve = true
+                }
+                "--dispute-withdrawals" => config.rules.dispute_withdrawals = true,
+                "--on-duplicate" => config.rules.on_duplicate = parse_flag(&mut args, &arg)?,
+                "--on-conflicting-duplicate" => {
+                    config.rules.on_conflicting_duplicate = parse_flag(&mut args, &arg)?
+                }
+                "--max-tx-per-client" => {
+                    config.rules.max_tx_per_client = Some(parse_flag(&mut args, &arg)?)
+                }
+                "--authorization-expiry" => {
+                    let expiry: usize = parse_flag(&mut args, &arg)?;
+                    if expiry == 0 {
+                        return Err(AppError::InvalidArgument(
+                            "--authorization-expiry must be at least 1".to_string(),
+                        ));
+                    }
+                    config.rules.authorization_expiry = Some(expiry);
+                }
+                "--on-tx-limit" => config.rules.on_tx_limit = parse_flag(&mut args, &arg)?,
+                "--min-balance" => {
+                    let ticks: TickInt = parse_flag(&mut args, &arg)?;
+                    if ticks < 0 {
+                        return Err(AppError::InvalidArgument(format!(
+                            "--min-balance can't be negative, got {}",
+                            ticks
+                        )));
+                    }
+                    config.rules.min_balance = Amount::from_ticks(ticks);
+                }
+                "--freeze-policy" => config.rules.freeze_policy = parse_flag(&mut args, &arg)?,
+                "--audit" => config.audit = true,
+                "--audit-control-flow" => config.audit_control_flow = true,
+                "--held-tx-ids" => config.held_tx_ids = true,
+                "--open-disputes" => config.open_disputes = true,
+                "--locked-only" => config.locked_only = true,
+                "--summary-only" => config.summary_only = true,
+                "--metrics" => config.metrics = true,
+                "--profile" => config.profile = true,
+                "--verify-invariants" => config.verify_invariants = true,
+                "--self-check" => config.self_check = true,
+                "--report-rejected-withdrawals" => config.report_rejected_withdrawals = true,
+                "-q" | "--quiet" => config.quiet = true,
+                "--echo-normalized" => config.echo_normalized = true,
+                "--snapshot" => config.snapshot = Some(flag_value(&mut args, &arg)?),
+                "--resume" => config.resume = Some(flag_value(&mut args, &arg)?),
+                "--opening-balances" => {
+                    config.opening_balances = Some(flag_value(&mut args, &arg)?)
+                }
+                "--reject-excess-precision" => {
+                    config.processing.parse.reject_excess_precision = true
+                }
+                "--sort-by-timestamp" => config.processing.sort_by_timestamp = true,
+                "--no-trim" => config.processing.parse.no_trim = true,
+                "--strict-types" => config.processing.parse.strict_types = true,
+                "--ignore-dispute-amount" => config.processing.parse.ignore_dispute_amount = true,
+                "--reject-zero-tx-id" => config.processing.parse.reject_zero_tx_id = true,
+                "--amount-units" => {
+                    config.processing.parse.amount_units = parse_flag(&mut args, &arg)?
+                }
+                "--rounding" => config.processing.parse.rounding = parse_flag(&mut args, &arg)?,
+                "--tick-tolerance" => {
+                    config.processing.parse.tick_tolerance =
+                        parse_tick_tolerance(&flag_value(&mut args, &arg)?)?
+                }
+                "--continue-on-error" => config.continue_on_error = true,
+                "--max-line-length" => {
+                    let max: usize = parse_flag(&mut args, &arg)?;
+                    if max == 0 {
+                        return Err(AppError::InvalidArgument(
+                            "--max-line-length must be at least 1".to_string(),
+                        ));
+                    }
+                    config.processing.parse.max_line_length = Some(max);
+                }
+                "--clients" => {
+                    config.processing.clients = Some(parse_clients(&flag_value(&mut args, &arg)?)?)
+                }
+                "--allow-types" | "--deny-types" => {
+                    if matches!(
+                        (&config.processing.types, arg.as_str()),
+                        (Some(TypeFilter::Allow(_)), "--deny-types")
+                            | (Some(TypeFilter::Deny(_)), "--allow-types")
+                    ) {
+                        return Err(AppError::InvalidArgument(
+                            "--allow-types is not supported with --deny-types".to_string(),
+                        ));
+                    }
+                    let types = parse_types(&flag_value(&mut args, &arg)?, &arg)?;
+                    config.processing.types = Some(match arg.as_str() {
+                        "--allow-types" => TypeFilter::Allow(types),
+                        _ => TypeFilter::Deny(types),
+                    });
+                }
+                "--currency-precision" => {
+                    config.processing.parse.currency_precision =
+                        parse_currency_precision(&flag_value(&mut args, &arg)?)?
+                }
+                "--buffer-disputes" => {
+                    let capacity: usize = parse_flag(&mut args, &arg)?;
+                    if capacity == 0 {
+                        return Err(AppError::InvalidArgument(
+                            "--buffer-disputes must be at least 1".to_string(),
+                        ));
+                    }
+                    config.processing.buffer_disputes = Some(capacity);
+                }
+                "--max-errors" => config.processing.max_errors = Some(parse_flag(&mut args, &arg)?),
+                _ if arg.starts_with("--") => {
+                    return Err(AppError::InvalidArgument(format!("unknown option {}", arg)));
+                }
+                _ => input_path = Some(arg),
+            }
+        }
+        config.input_path = input_path.ok_or(AppError::MissingArgument)?;
+        if config.continue_on_error {
+            // still honors an explicit --max-errors
+            config.processing.max_errors.get_or_insert(usize::MAX);
+        }
+        if config.open_disputes && config.summary_only {
+            // summaries have nowhere to put the count
+            return Err(AppError::InvalidArgument(
+                "--open-disputes is not supported with --summary-only".to_string(),
+            ));
+        }
+        if config.self_check && (config.output_format != OutputFormat::Csv || config.summary_only) {
+            // only the balances csv is parsed back
+            return Err(AppError::InvalidArgument(
+                "--self-check is only supported with csv balances output".to_string(),
+            ));
+        }
+        if config.opening_balances.is_some() && config.resume.is_some() {
+            // snapshots already carry the opening balances of their clients
+            return Err(AppError::InvalidArgument(
+                "--opening-balances is not supported with --resume".to_string(),
+            ));
+        }
+        if config.processing.buffer_disputes.is_some() && config.snapshot.is_some() {
+            // held rows aren't part of a snapshot, see `ProcessOptions::buffer_disputes`
+            return Err(AppError::InvalidArgument(
+                "--buffer-disputes is not supported with --snapshot".to_string(),
+            ));
+        }
+        if config.dedupe_global && (config.snapshot.is_some() || config.resume.is_some()) {
+            // processed inputs aren't part of a snapshot, so resumed runs would apply them again
+            return Err(AppError::InvalidArgument(
+                "--dedupe-global is not supported with --snapshot or --resume".to_string(),
+            ));
+        }
+        if config.rules.authorization_expiry.is_some()
+            && (config.snapshot.is_some() || config.resume.is_some())
+        {
+            // expiry deadlines aren't part of a snapshot, so resumed authorizations never expire
+            return Err(AppError::InvalidArgument(
+                "--authorization-expiry is not supported with --snapshot or --resume".to_string(),
+            ));
+        }
+        if config.summary_only && !config.processing.parse.currency_precision.is_empty() {
+            // totals would add up amounts of different currencies
+            return Err(AppError::InvalidArgument(
+                "--summary-only is not supported with --currency-precision".to_string(),
+            ));
+        }
+        if config.input_path == STDIN_PATH {
+            // both read the input ahead of processing, and stdin can only be read once
+            for (enabled, flag) in [
+                (config.audit_control_flow, "--audit-control-flow"),
+                (config.echo_normalized, "--echo-normalized"),
+            ] {
+                if enabled {
+                    return Err(AppError::InvalidArgument(format!(
+                        "{} is not supported with stdin input",
+                        flag
+                    )));
+                }
+            }
+        }
+        if config.input_format == InputFormat::Ndjson {
+            // both only make sense for csv input
+            for (enabled, flag) in [
+                (config.require_header, "--require-header"),
+                (config.audit_control_flow, "--audit-control-flow"),
+            ] {
+                if enabled {
+                    return Err(AppError::InvalidArgument(format!(
+                        "{} is not supported with ndjson input",
+                        flag
+                    )));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// `A..B` client ids, both ends included, e.g. `1..5`
+fn parse_client_range(raw: &str) -> Result<RangeInclusive<u16>, AppError> {
+    let invalid = || {
+        AppError::InvalidArgument(format!(
+            "--client-range expects A..B client ids, A not past B, got {}",
+            raw
+        ))
+    };
+    let (start, end) = raw.split_once("..").ok_or_else(invalid)?;
+    let (start, end): (u16, u16) = (
+        start.trim().parse().map_err(|_| invalid())?,
+        end.trim().parse().map_err(|_| invalid())?,
+    );
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+/// comma separated `CODE=decimals` pairs, e.g. `JPY=0,USD=4`, codes being uppercased
+fn parse_currency_precision(raw: &str) -> Result<HashMap<String, u32>, AppError> {
+    let invalid = || {
+        AppError::InvalidArgument(format!(
+            "--currency-precision expects comma separated CODE=decimals pairs, with at most {} \
+             decimals, got {}",
+            DECIMALS, raw
+        ))
+    };
+    raw.split(',')
+        .map(|pair| {
+            let (code, decimals) = pair.split_once('=').ok_or_else(invalid)?;
+            let code = code.trim().to_uppercase();
+            let decimals: u32 = decimals.trim().parse().map_err(|_| invalid())?;
+            if code.is_empty() || decimals > DECIMALS {
+                return Err(invalid());
+            }
+            Ok((code, decimals))
+        })
+        .collect()
+}
+
+/// comma separated client ids, e.g. `1,5,9`
+fn parse_clients(raw: &str) -> Result<HashSet<u16>, AppError> {
+    raw.split(',')
+        .map(|client| {
+            client.trim().parse().map_err(|_| {
+                AppError::InvalidArgument(format!(
+                    "--clients expects comma separated client ids, got {}",
+                    raw
+                ))
+            })
+        })
+        .collect()
+}
+
+/// comma separated transaction types given to `flag`, e.g. `dispute,chargeback`
+fn parse_types(raw: &str, flag: &str) -> Result<HashSet<TransactionType>, AppError> {
+    raw.split(',')
+        .map(|tx_type| {
+            tx_type.trim().parse().map_err(|_| {
+                AppError::InvalidArgument(format!(
+                    "{} expects comma separated transaction types, got {}",
+                    flag, raw
+                ))
+            })
+        })
+        .collect()
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, AppError> {
+    args.next()
+        .ok_or_else(|| AppError::InvalidArgument(format!("{} expects a value", flag)))
+}
+
+/// value of `flag` parsed as `T`, failing as a usage error rather than as malformed input
+pub(crate) fn parse_flag<T: FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T, AppError>
+where
+    T::Err: Into<AppError>,
+{
+    let value = flag_value(args, flag)?;
+    value.parse().map_err(|err: T::Err| match err.into() {
+        err @ AppError::InvalidArgument(_) => err,
+        err => AppError::InvalidArgument(format!("{} {}: {}", flag, value, err)),
+    })
+}
@@ -1,15 +1,23 @@
 use csv::StringRecord;
 use std::collections::HashMap;
 
-use crate::{AppError, TICK_SIZE, trunc_decimals};
+use crate::{
+    Amount, AmountUnits, AppError, AuditEntry, ClientBalance, Currency, DECIMALS,
+    DEFAULT_TICK_TOLERANCE, InvariantViolation, RoundingMode, TickInt, Ticks, TxStore,
+    format_ticks, parse_amount, parse_id,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// withdrawal holding its funds until captured, see `TransactionStatus::Authorized`
+    Authorization,
+    /// settles an authorization into a withdrawal
+    Capture,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,6 +25,10 @@ pub enum TransactionStatus {
     Normal,
     Disputed,
     Solved(bool), // true if chargeback occurred
+    /// authorized withdrawal whose funds are held until captured, turning it `Normal`
+    Authorized,
+    /// authorization released uncaptured, see `TxRules::authorization_expiry`
+    Expired,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,92 +47,649 @@ impl std::str::FromStr for TransactionType {
             "dispute" => Ok(Self::Dispute),
             "resolve" => Ok(Self::Resolve),
             "chargeback" => Ok(Self::Chargeback),
+            "authorization" => Ok(Self::Authorization),
+            "capture" => Ok(Self::Capture),
             _ => Err(AppError::InvalidTxType(s.to_string())),
         }
     }
 }
 
+impl TransactionType {
+    /// whether it moves funds, creating a transaction, rather than referencing an existing one
+    pub fn is_monetary(&self) -> bool {
+        self.side().is_some()
+    }
+
+    /// side of the transaction it creates, `None` for disputes, resolves, chargebacks and
+    /// captures
+    pub fn side(&self) -> Option<TransactionSide> {
+        match self {
+            TransactionType::Deposit => Some(TransactionSide::Deposit),
+            TransactionType::Withdrawal | TransactionType::Authorization => {
+                Some(TransactionSide::Withdrawal)
+            }
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Capture => None,
+        }
+    }
+}
+
+/// Result of processing a `TransactionInput`, since most invalid inputs are ignored rather than errored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxOutcome {
+    Applied,
+    /// client is frozen and only settles disputes opened before the lock
+    IgnoredLocked,
+    /// deposit or withdrawal repeating an existing transaction: same tx id, client, type and amount
+    IgnoredDuplicate,
+    /// deposit or withdrawal reusing the tx id of a transaction of another client, type or amount
+    IgnoredConflictingDuplicate,
+    IgnoredInsufficientFunds,
+    /// dispute, resolve or chargeback referencing an absent tx
+    IgnoredMissingTx,
+    /// dispute, resolve or chargeback not allowed from the referenced tx status
+    IgnoredInvalidTransition,
+    /// deposit or withdrawal past `TxRules::max_tx_per_client`
+    IgnoredTxLimit,
+}
+
+/// What a locked client stops accepting. Resolves and chargebacks of disputes opened before the
+/// lock are settled under every policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FreezePolicy {
+    /// rejects deposits, withdrawals and disputes
+    #[default]
+    FreezeAll,
+    /// only rejects withdrawals
+    FreezeWithdrawalsOnly,
+    /// rejects nothing, the lock is only reported
+    FlagOnly,
+}
+
+impl std::str::FromStr for FreezePolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "freeze-all" => Ok(Self::FreezeAll),
+            "freeze-withdrawals-only" => Ok(Self::FreezeWithdrawalsOnly),
+            "flag-only" => Ok(Self::FlagOnly),
+            _ => Err(AppError::InvalidArgument(format!(
+                "unknown freeze policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl FreezePolicy {
+    /// whether a locked client rejects `tx`, captures settling authorizations like resolves do
+    /// disputes
+    pub fn rejects(self, tx: &TransactionInput) -> bool {
+        match self {
+            FreezePolicy::FreezeAll => !matches!(
+                tx,
+                TransactionInput::Resolve(..)
+                    | TransactionInput::Chargeback(..)
+                    | TransactionInput::Capture(..)
+            ),
+            FreezePolicy::FreezeWithdrawalsOnly => matches!(
+                tx,
+                TransactionInput::Withdrawal(..) | TransactionInput::Authorization(..)
+            ),
+            FreezePolicy::FlagOnly => false,
+        }
+    }
+}
+
+/// How an input breaking a rule that would otherwise be silently ignored is handled, e.g. a
+/// deposit reusing the id of an existing transaction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ViolationPolicy {
+    /// ignores it, as its `TxOutcome`
+    #[default]
+    Ignore,
+    /// fails with `AppError::InvalidRecord`, e.g. to surface replayed deposits
+    Error,
+    /// ignores it, recording a `TxWarning`, see `PaymentEngine::warnings`
+    Warn,
+}
+
+impl std::str::FromStr for ViolationPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            _ => Err(AppError::InvalidArgument(format!(
+                "unknown violation policy {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Rules transactions are applied with, where specs commonly differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxRules {
+    /// lets a resolved deposit be disputed again, while charged back ones stay final
+    pub allow_redispute: bool,
+    /// lets a chargeback override the resolve of a transaction, charging it back all the same
+    pub chargeback_overrides_resolve: bool,
+    /// lets withdrawals be disputed, see `User::process_tx_input_with`
+    pub dispute_withdrawals: bool,
+    pub freeze_policy: FreezePolicy,
+    /// deposits and withdrawals repeating an existing transaction
+    pub on_duplicate: ViolationPolicy,
+    /// deposits and withdrawals reusing the tx id of a different transaction, a data integrity
+    /// issue rather than a replayed row, so warned about by default
+    pub on_conflicting_duplicate: ViolationPolicy,
+    /// floor withdrawals can't take available funds below
+    pub min_balance: Amount,
+    /// bounds how many deposits and withdrawals a client may accumulate
+    pub max_tx_per_client: Option<usize>,
+    /// deposits and withdrawals past `max_tx_per_client`
+    pub on_tx_limit: ViolationPolicy,
+    /// Number of further inputs an authorization stays capturable for, after which the engine
+    /// releases its funds as `TransactionStatus::Expired`. Authorizations never expire when
+    /// `None`, nor do the ones restored from a snapshot or moved in by `PaymentEngine::merge`,
+    /// which the engine hasn't applied itself.
+    pub authorization_expiry: Option<usize>,
+}
+
+impl Default for TxRules {
+    fn default() -> Self {
+        Self {
+            allow_redispute: false,
+            chargeback_overrides_resolve: false,
+            dispute_withdrawals: false,
+            freeze_policy: FreezePolicy::default(),
+            on_duplicate: ViolationPolicy::Ignore,
+            on_conflicting_duplicate: ViolationPolicy::Warn,
+            min_balance: Amount::ZERO,
+            max_tx_per_client: None,
+            authorization_expiry: None,
+            on_tx_limit: ViolationPolicy::Error,
+        }
+    }
+}
+
+/// Options controlling how a `StringRecord` is parsed into a `TransactionInput`
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// whether amounts are decimals or integer tick counts, the latter bypassing rounding and
+    /// `reject_excess_precision`
+    pub amount_units: AmountUnits,
+    /// errors on amounts with more significant decimals than `DECIMALS`, instead of truncating them
+    pub reject_excess_precision: bool,
+    pub rounding: RoundingMode,
+    /// Billionths of a tick within which an amount is snapped to the nearest tick boundary
+    /// instead of `rounding`, absorbing float noise such as `0.29999999`. Defaults to
+    /// `DEFAULT_TICK_TOLERANCE`, see `parse_tick_tolerance`.
+    pub tick_tolerance: u32,
+    /// requires exact field values, instead of trimming surrounding whitespace
+    pub no_trim: bool,
+    /// ignores the amount of dispute, resolve and chargeback rows, instead of rejecting it
+    pub ignore_dispute_amount: bool,
+    /// Rejects deposits and withdrawals declaring tx id `0`, which some upstreams use as a
+    /// sentinel for no id. Disputes, resolves and chargebacks referencing it are then ignored as
+    /// `TxOutcome::IgnoredMissingTx`, as no such transaction can exist.
+    pub reject_zero_tx_id: bool,
+    /// matches the type field case sensitively, rejecting e.g. `Deposit` or `DEPOSIT`
+    pub strict_types: bool,
+    /// Bytes a single line may span, failing with `AppError::InvalidFormat` past it, so a
+    /// malformed input without line breaks isn't buffered whole, see
+    /// `BufferInfo::with_max_line_length`.
+    pub max_line_length: Option<usize>,
+    /// Decimals of every currency code a `currency` column may hold, uppercase, e.g. `JPY` to 0,
+    /// see `parse_currency`. Empty when inputs aren't multi-currency.
+    pub currency_precision: HashMap<String, u32>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            amount_units: AmountUnits::default(),
+            reject_excess_precision: false,
+            rounding: RoundingMode::default(),
+            tick_tolerance: DEFAULT_TICK_TOLERANCE,
+            no_trim: false,
+            ignore_dispute_amount: false,
+            reject_zero_tx_id: false,
+            strict_types: false,
+            max_line_length: None,
+            currency_precision: HashMap::new(),
+        }
+    }
+}
+
+/// Markers delimiting a batch of transactions that is applied atomically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchMarker {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+impl BatchMarker {
+    /// markers are rows whose first field is `begin`, `commit` or `rollback`, e.g. `begin,,,`
+    pub fn from_record(value: &StringRecord) -> Option<Self> {
+        match value.get(0)?.trim().to_lowercase().as_str() {
+            "begin" => Some(Self::Begin),
+            "commit" => Some(Self::Commit),
+            "rollback" => Some(Self::Rollback),
+            _ => None,
+        }
+    }
+}
+
+/// Position of each of the `[type, client, tx, amount]` fields in the input rows, so a header may
+/// reorder them or add extra columns, along with the optional `timestamp` and `currency` columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnMap {
+    indices: [usize; 4],
+    timestamp: Option<usize>,
+    currency: Option<usize>,
+    /// number of columns of the header, which no row may exceed
+    width: usize,
+}
+
+impl Default for ColumnMap {
+    /// positional order, used for headerless inputs, which may carry a timestamp as 5th field
+    fn default() -> Self {
+        Self {
+            indices: [0, 1, 2, 3],
+            timestamp: Some(Self::TIMESTAMP),
+            currency: None,
+            width: Self::TIMESTAMP + 1,
+        }
+    }
+}
+
+impl ColumnMap {
+    pub(crate) const NAMES: [&'static str; 4] = ["type", "client", "tx", "amount"];
+    /// position of the timestamp field, once a record is in positional order
+    pub const TIMESTAMP: usize = 4;
+    /// position of the currency field, once a record is in positional order, which is only
+    /// recognized by name as headerless inputs may not skip the timestamp
+    pub const CURRENCY: usize = 5;
+
+    /// maps column names to their index, `None` when any expected column is missing
+    pub fn from_headers(headers: &StringRecord) -> Option<Self> {
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name))
+        };
+        let mut indices = [0; 4];
+        for (index, name) in indices.iter_mut().zip(Self::NAMES) {
+            *index = position(name)?;
+        }
+        let timestamp = position("timestamp");
+        let currency = position("currency");
+        Some(Self {
+            indices,
+            timestamp,
+            currency,
+            width: headers.len(),
+        })
+    }
+
+    /// whether records are already in positional order, without extra columns to drop
+    pub fn is_positional(&self) -> bool {
+        let extra_columns = self.width
+            > Self::NAMES.len()
+                + usize::from(self.timestamp.is_some())
+                + usize::from(self.currency.is_some());
+        self.indices == Self::default().indices
+            && !extra_columns
+            && self.timestamp.is_none_or(|index| index == Self::TIMESTAMP)
+            && self
+                .currency
+                .is_none_or(|index| index == Self::CURRENCY && self.timestamp.is_some())
+    }
+
+    /// Errors on a ragged `record` with more fields than the header has columns, e.g. from a
+    /// stray unquoted comma, which would otherwise shift fields into the wrong columns.
+    ///
+    /// Rows may have fewer fields, as missing trailing fields are validated by the parser.
+    pub fn check_width(&self, record: &StringRecord) -> Result<(), AppError> {
+        if record.len() > self.width {
+            return Err(AppError::InvalidRecord(format!(
+                "{}: expected at most {} fields, got {}",
+                record.iter().collect::<Vec<_>>().join(","),
+                self.width,
+                record.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// field of `record` holding the `[type, client, tx, amount]` field at `position`
+    pub fn get<'r>(&self, record: &'r StringRecord, position: usize) -> Option<&'r str> {
+        record.get(self.indices[position])
+    }
+
+    /// rebuilds `record` in positional order, followed by its timestamp and currency if any,
+    /// dropping extra columns
+    pub fn reorder(&self, record: &StringRecord) -> StringRecord {
+        let mut reordered: StringRecord = (0..self.indices.len())
+            .map(|position| self.get(record, position).unwrap_or_default())
+            .collect();
+        let field = |index: Option<usize>| index.and_then(|index| record.get(index));
+        if self.timestamp.is_some() || self.currency.is_some() {
+            // an empty timestamp keeps the currency at its position
+            reordered.push_field(field(self.timestamp).unwrap_or_default());
+        }
+        if self.currency.is_some() {
+            reordered.push_field(field(self.currency).unwrap_or_default());
+        }
+        reordered.set_position(record.position().cloned());
+        reordered
+    }
+}
+
+/// Currency of a positional `record`, `None` without a currency field or when
+/// `ParseOptions::currency_precision` is empty.
+///
+/// Codes are matched case insensitively, erroring on ones missing from it.
+pub fn parse_currency(
+    record: &StringRecord,
+    options: &ParseOptions,
+) -> Result<Option<Currency>, AppError> {
+    if options.currency_precision.is_empty() {
+        return Ok(None);
+    }
+    let Some(code) = record
+        .get(ColumnMap::CURRENCY)
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+    else {
+        return Ok(None);
+    };
+    let code = code.to_uppercase();
+    match options.currency_precision.get(&code) {
+        Some(&decimals) => Ok(Some(Currency { code, decimals })),
+        None => Err(AppError::InvalidRecord(format!(
+            "unknown currency \"{}\", expected one of --currency-precision",
+            code
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionInput {
     Deposit(u32, u16, i32),
     Withdrawal(u32, u16, i32),
     Dispute(u32, u16),
     Resolve(u32, u16),
     Chargeback(u32, u16),
+    Authorization(u32, u16, i32),
+    Capture(u32, u16),
 }
 
 impl TransactionInput {
     /// assumes [type, client, tx, amount]
     pub fn try_from_string_record(value: StringRecord) -> Result<Self, AppError> {
-        let is_non_numeric_tx = value[3].is_empty();
-        // sanitize
-        let value: Vec<String> = value.iter().map(|s| s.trim().to_lowercase()).collect();
-        let tx_type: TransactionType = value[0]
-            .parse()
-            .unwrap_or_else(|_| panic!("{} to be parsed as tx_type", value[0]));
-        if let (true, TransactionType::Deposit | TransactionType::Withdrawal) =
-            (is_non_numeric_tx, tx_type)
-        {
-            return Err(AppError::InvalidRecord(value.join(",").to_string()));
-        }
-
-        let client_id = value[1].parse::<u16>()?;
-        let id = value[2].parse::<u32>()?;
-        match tx_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                let amount = if let Some(val) = value.get(3) {
-                    let value = trunc_decimals(val.parse::<f32>()?, 4);
-                    if !value.is_finite() {
-                        return Err(AppError::InvalidRecord(format!("{} is not finite", value)));
-                    }
-                    (value / TICK_SIZE).round() as i32
+        Self::try_from_string_record_with(value, &ParseOptions::default())
+    }
+
+    /// same as `try_from_string_record`, honoring `options`
+    pub fn try_from_string_record_with(
+        value: StringRecord,
+        options: &ParseOptions,
+    ) -> Result<Self, AppError> {
+        Self::try_from_string_record_claimed(value, options).map(|(input, _)| input)
+    }
+
+    /// Same as `try_from_string_record_with`, along with the amount a resolve or chargeback row
+    /// carries, if any, which the engine checks against the referenced transaction.
+    ///
+    /// Dispute rows still can't carry an amount, and `ParseOptions::ignore_dispute_amount`
+    /// discards the ones of resolves and chargebacks.
+    pub fn try_from_string_record_claimed(
+        value: StringRecord,
+        options: &ParseOptions,
+    ) -> Result<(Self, Option<Amount>), AppError> {
+        let currency = parse_currency(&value, options)?;
+        // sanitize, keeping the case of the type under `strict_types`
+        let value: Vec<String> = value
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let s = if options.no_trim { s } else { s.trim() };
+                if i == 0 && options.strict_types {
+                    s.to_string()
                 } else {
-                    return Err(AppError::InvalidRecord(
-                        "Deposit | Withdrawal transactions must have amount".to_string(),
-                    ));
-                };
-                match tx_type {
-                    TransactionType::Deposit => Ok(Self::Deposit(id, client_id, amount)),
-                    TransactionType::Withdrawal => Ok(Self::Withdrawal(id, client_id, amount)),
-                    _ => unreachable!(),
+                    s.to_lowercase()
+                }
+            })
+            .collect();
+        if value.len() < 3 {
+            return Err(AppError::InvalidRecord(format!(
+                "{}: expected at least type, client and tx fields",
+                value.join(",")
+            )));
+        }
+        let tx_type: TransactionType = value[0].parse()?;
+        let client_id = parse_id::<u16>(&value[1], "client id", "u16")?;
+        let id = parse_id::<u32>(&value[2], "tx id", "u32")?;
+        // the amount column may be absent altogether on dispute, resolve and chargeback rows
+        let amount = value.get(3).filter(|amount| !amount.is_empty());
+        if let Some(side) = tx_type.side() {
+            if id == 0 && options.reject_zero_tx_id {
+                return Err(AppError::InvalidRecord(format!(
+                    "{:?} can't use the reserved tx id 0",
+                    tx_type
+                )));
+            }
+            let amount = amount.ok_or_else(|| AppError::InvalidRecord(value.join(",")))?;
+            let ticks = parse_amount(amount, options)?;
+            if !options.currency_precision.is_empty() {
+                let currency = currency.ok_or_else(|| {
+                    AppError::InvalidRecord(format!("{:?} of tx {} lacks a currency", tx_type, id))
+                })?;
+                if ticks % 10_i32.pow(DECIMALS - currency.decimals) != 0 {
+                    return Err(AppError::InvalidRecord(format!(
+                        "{:?} of tx {}: {} amounts have at most {} decimals, got \"{}\"",
+                        tx_type, id, currency.code, currency.decimals, amount
+                    )));
                 }
             }
-            TransactionType::Dispute => Ok(Self::Dispute(id, client_id)),
-            TransactionType::Resolve => Ok(Self::Resolve(id, client_id)),
-            TransactionType::Chargeback => Ok(Self::Chargeback(id, client_id)),
+            let input = match tx_type {
+                TransactionType::Authorization => Self::authorization(id, client_id, ticks)?,
+                _ => Self::monetary(side, id, client_id, ticks)?,
+            };
+            return Ok((input, None));
+        }
+        let amount = amount.filter(|_| !options.ignore_dispute_amount);
+        let claimed = match (tx_type, amount) {
+            (_, None) => None,
+            (TransactionType::Dispute, Some(amount)) => {
+                return Err(AppError::InvalidRecord(format!(
+                    "{:?} of tx {} can't have an amount, got \"{}\"",
+                    tx_type, id, amount
+                )));
+            }
+            (_, Some(amount)) => Some(Amount::from(parse_amount(amount, options)?)),
+        };
+        let input = match tx_type {
+            TransactionType::Dispute => Self::dispute(id, client_id),
+            TransactionType::Resolve => Self::resolve(id, client_id),
+            TransactionType::Chargeback => Self::chargeback(id, client_id),
+            TransactionType::Capture => Self::capture(id, client_id),
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Authorization => {
+                unreachable!("monetary types are parsed above")
+            }
+        };
+        Ok((input, claimed))
+    }
+
+    /// deposit or withdrawal of `amount` ticks, per `side`, which must be positive
+    pub fn monetary(
+        side: TransactionSide,
+        tx: u32,
+        client: u16,
+        amount: i32,
+    ) -> Result<Self, AppError> {
+        match side {
+            TransactionSide::Deposit => Self::deposit(tx, client, amount),
+            TransactionSide::Withdrawal => Self::withdrawal(tx, client, amount),
         }
     }
 
-    fn id(&self) -> u32 {
+    /// deposit of `amount` ticks, which must be positive
+    pub fn deposit(tx: u32, client: u16, amount: i32) -> Result<Self, AppError> {
+        Ok(Self::Deposit(tx, client, positive_amount(tx, amount)?))
+    }
+
+    /// withdrawal of `amount` ticks, which must be positive
+    pub fn withdrawal(tx: u32, client: u16, amount: i32) -> Result<Self, AppError> {
+        Ok(Self::Withdrawal(tx, client, positive_amount(tx, amount)?))
+    }
+
+    pub fn dispute(tx: u32, client: u16) -> Self {
+        Self::Dispute(tx, client)
+    }
+
+    pub fn resolve(tx: u32, client: u16) -> Self {
+        Self::Resolve(tx, client)
+    }
+
+    pub fn chargeback(tx: u32, client: u16) -> Self {
+        Self::Chargeback(tx, client)
+    }
+
+    /// authorization of a withdrawal of `amount` ticks, which must be positive
+    pub fn authorization(tx: u32, client: u16, amount: i32) -> Result<Self, AppError> {
+        Ok(Self::Authorization(
+            tx,
+            client,
+            positive_amount(tx, amount)?,
+        ))
+    }
+
+    pub fn capture(tx: u32, client: u16) -> Self {
+        Self::Capture(tx, client)
+    }
+
+    /// side and amount of a deposit, withdrawal or authorization, `None` for inputs referencing
+    /// a transaction
+    pub fn funds(&self) -> Option<(TransactionSide, i32)> {
         match self {
-            TransactionInput::Deposit(id, _, _) | TransactionInput::Withdrawal(id, _, _) => *id,
+            TransactionInput::Deposit(_, _, amount)
+            | TransactionInput::Withdrawal(_, _, amount)
+            | TransactionInput::Authorization(_, _, amount) => {
+                Some((self.tx_type().side()?, *amount))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            TransactionInput::Deposit(id, _, _)
+            | TransactionInput::Withdrawal(id, _, _)
+            | TransactionInput::Authorization(id, _, _) => *id,
             TransactionInput::Dispute(id, _)
             | TransactionInput::Resolve(id, _)
-            | TransactionInput::Chargeback(id, _) => *id,
+            | TransactionInput::Chargeback(id, _)
+            | TransactionInput::Capture(id, _) => *id,
+        }
+    }
+
+    /// canonical `type,client,tx,amount` row, with the amount at `DECIMALS` places
+    pub fn to_csv_row(&self) -> String {
+        let tx_type = format!("{:?}", self.tx_type()).to_lowercase();
+        match self {
+            TransactionInput::Deposit(id, client_id, amount)
+            | TransactionInput::Withdrawal(id, client_id, amount)
+            | TransactionInput::Authorization(id, client_id, amount) => {
+                format!("{},{},{},{}", tx_type, client_id, id, format_ticks(*amount))
+            }
+            TransactionInput::Dispute(id, client_id)
+            | TransactionInput::Resolve(id, client_id)
+            | TransactionInput::Chargeback(id, client_id)
+            | TransactionInput::Capture(id, client_id) => {
+                format!("{},{},{},", tx_type, client_id, id)
+            }
+        }
+    }
+
+    /// same input, filed under `client_id`
+    pub fn with_client_id(self, client_id: u16) -> Self {
+        match self {
+            TransactionInput::Deposit(id, _, amount) => {
+                TransactionInput::Deposit(id, client_id, amount)
+            }
+            TransactionInput::Withdrawal(id, _, amount) => {
+                TransactionInput::Withdrawal(id, client_id, amount)
+            }
+            TransactionInput::Dispute(id, _) => TransactionInput::Dispute(id, client_id),
+            TransactionInput::Resolve(id, _) => TransactionInput::Resolve(id, client_id),
+            TransactionInput::Chargeback(id, _) => TransactionInput::Chargeback(id, client_id),
+            TransactionInput::Authorization(id, _, amount) => {
+                TransactionInput::Authorization(id, client_id, amount)
+            }
+            TransactionInput::Capture(id, _) => TransactionInput::Capture(id, client_id),
+        }
+    }
+
+    pub fn tx_type(&self) -> TransactionType {
+        match self {
+            TransactionInput::Deposit(..) => TransactionType::Deposit,
+            TransactionInput::Withdrawal(..) => TransactionType::Withdrawal,
+            TransactionInput::Dispute(..) => TransactionType::Dispute,
+            TransactionInput::Resolve(..) => TransactionType::Resolve,
+            TransactionInput::Chargeback(..) => TransactionType::Chargeback,
+            TransactionInput::Authorization(..) => TransactionType::Authorization,
+            TransactionInput::Capture(..) => TransactionType::Capture,
         }
     }
 
     pub fn client_id(&self) -> u16 {
         match self {
             TransactionInput::Deposit(_, client_id, _)
-            | TransactionInput::Withdrawal(_, client_id, _) => *client_id,
+            | TransactionInput::Withdrawal(_, client_id, _)
+            | TransactionInput::Authorization(_, client_id, _) => *client_id,
             TransactionInput::Dispute(_, client_id)
             | TransactionInput::Resolve(_, client_id)
-            | TransactionInput::Chargeback(_, client_id) => *client_id,
+            | TransactionInput::Chargeback(_, client_id)
+            | TransactionInput::Capture(_, client_id) => *client_id,
         }
     }
 }
 
-pub struct Transaction {
+fn positive_amount(tx: u32, amount: i32) -> Result<i32, AppError> {
+    if amount <= 0 {
+        return Err(AppError::InvalidRecord(format!(
+            "tx {} amount {} must be positive",
+            tx,
+            format_ticks(amount)
+        )));
+    }
+    Ok(amount)
+}
+
+/// A deposit or withdrawal of a client, its amount counted in `T`
+#[derive(Debug, Clone)]
+pub struct Transaction<T = TickInt> {
     pub id: u32,
     pub client_id: u16,
     pub status: TransactionStatus,
     pub side: TransactionSide,
     /// since we're dealing only with add_sub ops, we can safely store amount as ticks
-    pub amount: i32,
+    pub amount: Amount<T>,
 }
 
-impl Transaction {
-    fn new(id: u32, client_id: u16, side: TransactionSide, amount: i32) -> Self {
+impl<T: Ticks> Transaction<T> {
+    pub(crate) fn new(id: u32, client_id: u16, side: TransactionSide, amount: Amount<T>) -> Self {
         Self {
             id,
             client_id,
@@ -131,116 +700,556 @@ impl Transaction {
     }
 }
 
-pub struct User {
+/// A client, keeping its transactions in `S`, in memory by default, its balances counted in the
+/// `Ticks` of `S`
+#[derive(Debug, Clone)]
+pub struct User<S: TxStore = HashMap<u32, Transaction>> {
     pub id: u16,
     pub locked: bool,
-    pub transactions: HashMap<u32, Transaction>,
+    /// Inserting or updating transactions directly, rather than through `process_tx_input`,
+    /// must be followed by `sync_balances`.
+    pub transactions: S,
+    /// balances kept up to date as transactions are applied, rather than summed on every read
+    available: Amount<S::Ticks>,
+    held: Amount<S::Ticks>,
+    /// transactions currently `Disputed`, kept alongside the balances, see `open_disputes`
+    open_disputes: usize,
+    /// funds the client started with, part of `available` but not of any transaction
+    opening: Amount<S::Ticks>,
+    /// only currency the client transacts in, see `PaymentEngine::tag_currency`
+    currency: Option<Currency>,
+    /// only allocated in audit mode, to avoid the overhead otherwise
+    audit: Option<Vec<AuditEntry>>,
+    first_seen_line: Option<usize>,
+    last_seen_line: Option<usize>,
 }
 
 impl User {
     pub fn new(id: u16) -> Self {
+        Self::with_store(id, HashMap::new())
+    }
+
+    /// creates a user recording every state transition of its transactions
+    pub fn with_audit(id: u16) -> Self {
+        Self::new(id).audited()
+    }
+
+    pub fn csv_header() -> &'static str {
+        "client,available,held,total,locked"
+    }
+}
+
+impl<S: TxStore> User<S> {
+    /// creates a user keeping its transactions in `transactions`, which must be empty
+    pub fn with_store(id: u16, transactions: S) -> Self {
         Self {
             id,
             locked: false,
-            transactions: HashMap::new(),
+            transactions,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            open_disputes: 0,
+            opening: Amount::ZERO,
+            currency: None,
+            audit: None,
+            first_seen_line: None,
+            last_seen_line: None,
         }
     }
 
-    pub fn csv_header() -> &'static str {
-        "client,available,held,total,locked"
+    /// records every state transition of its transactions from now on
+    pub(crate) fn audited(mut self) -> Self {
+        self.audit = Some(Vec::new());
+        self
+    }
+
+    /// transitions recorded so far, empty unless created `with_audit`
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        self.audit.as_deref().unwrap_or_default()
+    }
+
+    /// Funds the client was seeded with before any transaction, see
+    /// `PaymentEngine::open_account`. They can be withdrawn, but not disputed, as no transaction
+    /// backs them.
+    pub fn opening_balance(&self) -> Amount<S::Ticks> {
+        self.opening
+    }
+
+    /// adds `amount` to the opening balance, and so to the available funds
+    pub(crate) fn credit_opening(&mut self, amount: Amount<S::Ticks>) {
+        self.opening = self.opening + amount;
+        self.available = self.available + amount;
+    }
+
+    /// currency of the client's deposits and withdrawals, in multi-currency inputs
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    pub(crate) fn set_currency(&mut self, currency: Currency) {
+        self.currency = Some(currency);
+    }
+
+    /// input line of the first tx processed for this client, absent for programmatic inputs
+    pub fn first_seen_line(&self) -> Option<usize> {
+        self.first_seen_line
+    }
+
+    /// input line of the last tx processed for this client, absent for programmatic inputs
+    pub fn last_seen_line(&self) -> Option<usize> {
+        self.last_seen_line
+    }
+
+    /// records that the tx of input `line` was processed for this client, applied or not
+    pub(crate) fn seen_at(&mut self, line: Option<usize>) {
+        if let Some(line) = line {
+            self.first_seen_line.get_or_insert(line);
+            self.last_seen_line = Some(line);
+        }
     }
 
-    pub fn process_tx_input(&mut self, tx: TransactionInput) -> Result<(), AppError> {
+    /// `line` is the input line `tx` was parsed from, if any, and is only used for auditing and
+    /// `first_seen_line`/`last_seen_line`
+    pub fn process_tx_input(
+        &mut self,
+        tx: TransactionInput,
+        line: Option<usize>,
+    ) -> Result<TxOutcome, AppError> {
+        self.process_tx_input_with(tx, line, &TxRules::default())
+    }
+
+    /// Same as `process_tx_input`, applying `tx` under `rules`.
+    ///
+    /// Disputes move a transaction through `Normal -> Disputed -> Solved(chargeback)`, a
+    /// `Solved(false)` one only being disputed again under `TxRules::allow_redispute`, or charged
+    /// back under `TxRules::chargeback_overrides_resolve`:
+    ///
+    /// | side       | status          | available | held    |
+    /// | ---------- | --------------- | --------- | ------- |
+    /// | deposit    | `Normal`        | +amount   |         |
+    /// | deposit    | `Disputed`      |           | +amount |
+    /// | deposit    | `Solved(false)` | +amount   |         |
+    /// | deposit    | `Solved(true)`  |           |         |
+    /// | withdrawal | `Normal`        | -amount   |         |
+    /// | withdrawal | `Disputed`      | -amount   | +amount |
+    /// | withdrawal | `Solved(false)` | -amount   |         |
+    /// | withdrawal | `Solved(true)`  |           |         |
+    /// | withdrawal | `Authorized`    | -amount   | +amount |
+    /// | withdrawal | `Expired`       |           |         |
+    ///
+    /// So a charged back deposit is taken away, while a charged back withdrawal is reversed. Only
+    /// deposits are disputable unless `TxRules::dispute_withdrawals` is set.
+    ///
+    /// An authorization creates an `Authorized` withdrawal, its funds held until a capture turns
+    /// it `Normal`, or until the engine expires it, see `TxRules::authorization_expiry`.
+    pub fn process_tx_input_with(
+        &mut self,
+        tx: TransactionInput,
+        line: Option<usize>,
+        rules: &TxRules,
+    ) -> Result<TxOutcome, AppError> {
         assert!(
             tx.client_id() == self.id,
             "tx's client_id's must be the same as client.id"
         );
-        if self.locked {
-            // client is frozen and no longer accepts transactions
-            return Ok(());
+        self.seen_at(line);
+        if self.locked && rules.freeze_policy.rejects(&tx) {
+            // client is frozen and no longer accepts these transactions,
+            // though disputes opened before the lock can still be settled
+            return Ok(TxOutcome::IgnoredLocked);
         }
         let tx_id = tx.id();
-        match (tx, self.transactions.get_mut(&tx_id)) {
-            (TransactionInput::Deposit(id, client_id, amount), None) => {
-                self.transactions.insert(
-                    id,
-                    Transaction::new(id, client_id, TransactionSide::Deposit, amount),
-                );
-            }
-            (TransactionInput::Withdrawal(id, client_id, amount), None) => {
-                // if insufficient funds, ignore
-                if self.available() >= amount {
-                    self.transactions.insert(
-                        id,
-                        Transaction::new(id, client_id, TransactionSide::Withdrawal, amount),
-                    );
-                }
-            }
-            (TransactionInput::Dispute(_, _), Some(found_tx)) => {
-                if found_tx.side == TransactionSide::Deposit
-                    && found_tx.status == TransactionStatus::Normal
+        let previous_status = self.transactions.get(tx_id).map(|tx| tx.status);
+        let at_tx_limit = rules
+            .max_tx_per_client
+            .is_some_and(|max| self.transactions.len() >= max);
+        let outcome = match (tx.funds(), self.transactions.get_mut(tx_id)) {
+            (Some(_), None) if at_tx_limit => TxOutcome::IgnoredTxLimit,
+            (Some((side, amount)), None) => {
+                let amount = Amount::from(amount);
+                let available = match side {
+                    TransactionSide::Deposit => self.available + amount,
+                    TransactionSide::Withdrawal => self.available - amount,
+                };
+                // Insufficient funds are ignored. Withdrawals are only ever checked against
+                // `withdrawable`, the cached available funds: held funds count in `total` but
+                // back open disputes, so they must stay put whatever `total` would cover.
+                if side == TransactionSide::Withdrawal
+                    && self.withdrawable().widen() - amount.widen() < rules.min_balance.widen()
                 {
-                    found_tx.status = TransactionStatus::Disputed
+                    TxOutcome::IgnoredInsufficientFunds
+                } else {
+                    let mut transaction = Transaction::new(tx_id, self.id, side, amount);
+                    if let TransactionInput::Authorization(..) = tx {
+                        // leaves available right away, held until captured or expired
+                        transaction.status = TransactionStatus::Authorized;
+                        Self::hold(self.id, &mut self.held, amount)?;
+                    }
+                    self.transactions.insert(transaction);
+                    self.available = available;
+                    TxOutcome::Applied
                 }
             }
-            (TransactionInput::Resolve(_, _), Some(found_tx)) => {
-                if found_tx.status == TransactionStatus::Disputed {
-                    found_tx.status = TransactionStatus::Solved(false)
-                }
+            (Some((side, amount)), Some(found_tx))
+                if found_tx.side == side && found_tx.amount == Amount::from(amount) =>
+            {
+                TxOutcome::IgnoredDuplicate
             }
-            (TransactionInput::Chargeback(_, _), Some(found_tx)) => {
-                if found_tx.status == TransactionStatus::Disputed {
-                    found_tx.status = TransactionStatus::Solved(true);
-                    self.locked = true;
+            (Some(_), Some(_)) => TxOutcome::IgnoredConflictingDuplicate,
+            // ignore non-numeric but previously absent inputs
+            (None, None) => TxOutcome::IgnoredMissingTx,
+            (None, Some(found_tx)) => match tx {
+                TransactionInput::Dispute(..) => {
+                    let disputable = match found_tx.status {
+                        TransactionStatus::Normal => true,
+                        TransactionStatus::Solved(false) => rules.allow_redispute,
+                        TransactionStatus::Disputed
+                        | TransactionStatus::Solved(true)
+                        | TransactionStatus::Authorized
+                        | TransactionStatus::Expired => false,
+                    };
+                    match found_tx.side {
+                        _ if !disputable => TxOutcome::IgnoredInvalidTransition,
+                        TransactionSide::Deposit => {
+                            found_tx.status = TransactionStatus::Disputed;
+                            self.available = self.available - found_tx.amount;
+                            Self::hold(self.id, &mut self.held, found_tx.amount)?;
+                            self.open_disputes += 1;
+                            TxOutcome::Applied
+                        }
+                        TransactionSide::Withdrawal if !rules.dispute_withdrawals => {
+                            TxOutcome::IgnoredInvalidTransition
+                        }
+                        TransactionSide::Withdrawal => {
+                            // the withdrawn funds stay spent, the claim on them is held
+                            found_tx.status = TransactionStatus::Disputed;
+                            Self::hold(self.id, &mut self.held, found_tx.amount)?;
+                            self.open_disputes += 1;
+                            TxOutcome::Applied
+                        }
+                    }
                 }
-            }
-            // ignore duplicate id numeric and non-numeric but previously absent inputs
-            (_, _) => {}
+                TransactionInput::Resolve(..) => {
+                    if found_tx.status == TransactionStatus::Disputed {
+                        found_tx.status = TransactionStatus::Solved(false);
+                        Self::release(self.id, &mut self.held, found_tx.amount)?;
+                        self.open_disputes -= 1;
+                        if found_tx.side == TransactionSide::Deposit {
+                            self.available = self.available + found_tx.amount;
+                        }
+                        TxOutcome::Applied
+                    } else {
+                        TxOutcome::IgnoredInvalidTransition
+                    }
+                }
+                TransactionInput::Chargeback(..) => match (found_tx.status, found_tx.side) {
+                    (TransactionStatus::Disputed, side) => {
+                        found_tx.status = TransactionStatus::Solved(true);
+                        Self::release(self.id, &mut self.held, found_tx.amount)?;
+                        self.open_disputes -= 1;
+                        if side == TransactionSide::Withdrawal {
+                            // reversed, so the withdrawn funds are given back
+                            self.available = self.available + found_tx.amount;
+                        }
+                        self.locked = true;
+                        TxOutcome::Applied
+                    }
+                    (TransactionStatus::Solved(false), _)
+                        if !rules.chargeback_overrides_resolve =>
+                    {
+                        TxOutcome::IgnoredInvalidTransition
+                    }
+                    (TransactionStatus::Solved(false), TransactionSide::Deposit)
+                        if self.available < found_tx.amount =>
+                    {
+                        // taking it back would take available funds below zero
+                        TxOutcome::IgnoredInsufficientFunds
+                    }
+                    (TransactionStatus::Solved(false), side) => {
+                        // the resolve released the funds, so they're taken from available
+                        found_tx.status = TransactionStatus::Solved(true);
+                        self.available = match side {
+                            TransactionSide::Deposit => self.available - found_tx.amount,
+                            TransactionSide::Withdrawal => self.available + found_tx.amount,
+                        };
+                        self.locked = true;
+                        TxOutcome::Applied
+                    }
+                    _ => TxOutcome::IgnoredInvalidTransition,
+                },
+                TransactionInput::Capture(..) => {
+                    if found_tx.status == TransactionStatus::Authorized {
+                        // the funds already left available, they're only released from held
+                        found_tx.status = TransactionStatus::Normal;
+                        Self::release(self.id, &mut self.held, found_tx.amount)?;
+                        TxOutcome::Applied
+                    } else {
+                        TxOutcome::IgnoredInvalidTransition
+                    }
+                }
+                TransactionInput::Deposit(..)
+                | TransactionInput::Withdrawal(..)
+                | TransactionInput::Authorization(..) => {
+                    unreachable!("deposits, withdrawals and authorizations have funds")
+                }
+            },
+        };
+
+        if let (Some(log), TxOutcome::Applied) = (self.audit.as_mut(), outcome) {
+            log.push(AuditEntry {
+                tx_id,
+                line,
+                from: previous_status,
+                to: self
+                    .transactions
+                    .get(tx_id)
+                    .expect("applied transactions are stored")
+                    .status,
+            });
+        }
+
+        Ok(outcome)
+    }
+
+    /// Releases the funds of the authorization `tx_id` back to available, returning whether it
+    /// was still `Authorized`, see `TxRules::authorization_expiry`.
+    pub(crate) fn expire(&mut self, tx_id: u32) -> Result<bool, AppError> {
+        let Some(found_tx) = self
+            .transactions
+            .get_mut(tx_id)
+            .filter(|tx| tx.status == TransactionStatus::Authorized)
+        else {
+            return Ok(false);
+        };
+        found_tx.status = TransactionStatus::Expired;
+        Self::release(self.id, &mut self.held, found_tx.amount)?;
+        self.available = self.available + found_tx.amount;
+        if let Some(log) = self.audit.as_mut() {
+            log.push(AuditEntry {
+                tx_id,
+                line: None,
+                from: Some(TransactionStatus::Authorized),
+                to: TransactionStatus::Expired,
+            });
         }
+        Ok(true)
+    }
+
+    /// adds `amount` to `held`, erroring instead of overflowing
+    fn hold(
+        client_id: u16,
+        held: &mut Amount<S::Ticks>,
+        amount: Amount<S::Ticks>,
+    ) -> Result<(), AppError> {
+        *held = held.checked_add(amount).ok_or_else(|| {
+            AppError::InvalidRecord(format!("client {}: held funds overflowed", client_id))
+        })?;
+        Ok(())
+    }
 
+    /// Takes `amount` back out of `held`.
+    ///
+    /// Only disputed transactions are released, each once, so `held` going negative is a logic
+    /// bug: it trips a debug assertion, and is clamped to zero otherwise so `verify` reports it.
+    fn release(
+        client_id: u16,
+        held: &mut Amount<S::Ticks>,
+        amount: Amount<S::Ticks>,
+    ) -> Result<(), AppError> {
+        let released = held.checked_sub(amount).ok_or_else(|| {
+            AppError::InvalidRecord(format!("client {}: held funds overflowed", client_id))
+        })?;
+        debug_assert!(
+            !released.is_negative(),
+            "client {}: held funds went negative ({})",
+            client_id,
+            released
+        );
+        *held = released.max(Amount::ZERO);
         Ok(())
     }
 
-    fn available(&self) -> i32 {
-        self.transactions
-            .values()
-            .fold(0, |acc, tx| match (tx.side, tx.status) {
+    /// Errors when the computed available funds are negative, which `balance` would otherwise
+    /// clamp to zero.
+    ///
+    /// Withdrawals are gated on available funds, so this only happens once a deposit already
+    /// spent is disputed, or through a logic bug.
+    pub fn check_available(&self) -> Result<(), AppError> {
+        let available = self.unclamped_available();
+        if available.is_negative() {
+            return Err(AppError::InvalidRecord(format!(
+                "client {}: available funds went negative ({})",
+                self.id, available
+            )));
+        }
+        Ok(())
+    }
+
+    /// Funds a withdrawal may take, which is `available`, never `total`.
+    ///
+    /// Held funds back open disputes, of deposits as of withdrawals, so they're never withdrawable
+    /// whatever the dispute state. Unclamped, unlike `balance`, so a negative balance left by a
+    /// logic bug blocks withdrawals rather than reading as zero.
+    fn withdrawable(&self) -> Amount<S::Ticks> {
+        self.unclamped_available()
+    }
+
+    /// how much a withdrawal of `amount` goes past what `withdrawable` allows under `rules`
+    pub(crate) fn shortfall(&self, amount: Amount<S::Ticks>, rules: &TxRules) -> Amount<i128> {
+        amount.widen() - (self.withdrawable().widen() - rules.min_balance.widen())
+    }
+
+    fn unclamped_available(&self) -> Amount<S::Ticks> {
+        self.available
+    }
+
+    /// Replays `transactions` into `(available, held)` on top of the opening balance,
+    /// independently of the cached balances
+    fn replay(&self) -> (Amount<S::Ticks>, Amount<S::Ticks>) {
+        self.transactions.transactions().fold(
+            (self.opening, Amount::ZERO),
+            |(available, held), tx| match (tx.side, tx.status) {
                 // normal or resolved deposits increase available
                 (TransactionSide::Deposit, TransactionStatus::Normal)
-                | (TransactionSide::Deposit, TransactionStatus::Solved(false)) => acc + tx.amount,
-                // withdrawals always subtract immediately (disputed withdrawals are ignored)
+                | (TransactionSide::Deposit, TransactionStatus::Solved(false)) => {
+                    (available + tx.amount, held)
+                }
+                // deposits under dispute are held
+                (TransactionSide::Deposit, TransactionStatus::Disputed) => {
+                    (available, held + tx.amount)
+                }
+                // withdrawals subtract immediately, and under dispute their claim is held
                 (TransactionSide::Withdrawal, TransactionStatus::Normal)
                 | (TransactionSide::Withdrawal, TransactionStatus::Solved(false)) => {
-                    acc - tx.amount
+                    (available - tx.amount, held)
                 }
-                // disputed or chargebacked deposits are not available
-                _ => acc,
-            })
-            .max(0) // ensures amount >= 0
+                (TransactionSide::Withdrawal, TransactionStatus::Disputed) => {
+                    (available - tx.amount, held + tx.amount)
+                }
+                // authorized withdrawals are held until captured, and expired ones released
+                (_, TransactionStatus::Authorized) => (available - tx.amount, held + tx.amount),
+                (_, TransactionStatus::Expired) => (available, held),
+                // chargebacked deposits are gone, and chargebacked withdrawals reversed
+                (_, TransactionStatus::Solved(true)) => (available, held),
+            },
+        )
     }
 
-    fn held(&self) -> i32 {
-        self.transactions
-            .values()
-            .fold(0, |acc, tx| match (tx.side, tx.status) {
-                // deposits under dispute are held
-                (TransactionSide::Deposit, TransactionStatus::Disputed) => acc + tx.amount,
-                _ => acc,
-            })
+    /// Resets the cached balances to the ones replayed from `transactions`, e.g. after restoring
+    /// them from a snapshot.
+    pub fn sync_balances(&mut self) {
+        (self.available, self.held) = self.replay();
+        self.open_disputes = self
+            .transactions
+            .transactions()
+            .filter(|tx| tx.status == TransactionStatus::Disputed)
+            .count();
     }
 
-    fn total(&self) -> i32 {
-        self.available() + self.held()
+    /// balances replayed from `transactions`, as a cross-check of the cached ones in `balance`
+    pub fn recompute_balances(&self) -> ClientBalance {
+        let (available, held) = self.replay();
+        self.balance_of(available, held)
     }
 
-    pub fn to_csv_row(&self) -> String {
-        let available = self.available() as f32 * TICK_SIZE;
-        let held = self.held() as f32 * TICK_SIZE;
-        let total = self.total() as f32 * TICK_SIZE;
+    /// errors when the cached balances drifted from the ones replayed from `transactions`
+    pub fn verify(&self) -> Result<(), AppError> {
+        if (self.available, self.held) != self.replay() {
+            return Err(AppError::BalanceMismatch {
+                cached: Box::new(self.balance()),
+                recomputed: Box::new(self.recompute_balances()),
+            });
+        }
+        Ok(())
+    }
 
-        format!(
-            "{},{:.4},{:.4},{:.4},{}",
-            self.id, available, held, total, self.locked
-        )
+    /// Consistency checks of this client failing: negative total or held funds, read before
+    /// `balance` clamps them, and cached balances drifting from the replayed ones, see `verify`
+    pub fn invariant_violations(&self) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let total = self.unclamped_total();
+        if total.is_negative() {
+            violations.push(InvariantViolation::NegativeTotal {
+                client: self.id,
+                total,
+            });
+        }
+        if self.held.is_negative() {
+            violations.push(InvariantViolation::NegativeHeld {
+                client: self.id,
+                held: self.held.widen(),
+            });
+        }
+        if (self.available, self.held) != self.replay() {
+            violations.push(InvariantViolation::BalanceMismatch {
+                cached: Box::new(self.balance()),
+                recomputed: Box::new(self.recompute_balances()),
+            });
+        }
+        violations
+    }
+
+    /// cached available plus held funds, without the clamping of `balance`
+    pub(crate) fn unclamped_total(&self) -> Amount<i128> {
+        self.unclamped_available().widen() + self.held.widen()
+    }
+
+    pub fn balance(&self) -> ClientBalance {
+        self.balance_of(self.available, self.held)
+    }
+
+    fn balance_of(&self, available: Amount<S::Ticks>, held: Amount<S::Ticks>) -> ClientBalance {
+        // safety net, see `check_available`
+        let available = available.max(Amount::ZERO);
+        ClientBalance {
+            client: self.id,
+            available: available.widen().to_ticks(),
+            held: held.widen().to_ticks(),
+            total: (available.widen() + held.widen()).to_ticks(),
+            locked: self.locked,
+            held_tx_ids: None,
+            open_disputes: None,
+            currency: None,
+        }
+    }
+
+    /// transactions by ascending id, as `transactions` is unordered
+    pub fn transactions_sorted(&self) -> impl Iterator<Item = Transaction<S::Ticks>> {
+        let mut transactions: Vec<_> = self.transactions.transactions().collect();
+        transactions.sort_unstable_by_key(|tx| tx.id);
+        transactions.into_iter()
+    }
+
+    /// number of currently disputed transactions, counted as they're applied rather than by
+    /// going through `transactions`
+    pub fn open_disputes(&self) -> usize {
+        self.open_disputes
+    }
+
+    /// Clone of the transaction `tx_id`, `None` unless applied to this client.
+    ///
+    /// Owned rather than borrowed, as it comes from `TxStore::get`, which returns transactions by
+    /// value: `FileTxStore` decodes them from its file on every read, leaving nothing in memory to
+    /// borrow from. Transactions are a few integers, so the clone is cheap for `HashMap` stores too.
+    pub fn transaction(&self, tx_id: u32) -> Option<Transaction<S::Ticks>> {
+        self.transactions.get(tx_id)
+    }
+
+    /// status of the transaction `tx_id`, see `transaction`
+    pub fn transaction_status(&self, tx_id: u32) -> Option<TransactionStatus> {
+        self.transaction(tx_id).map(|tx| tx.status)
+    }
+
+    /// ids of the currently disputed transactions, in ascending order
+    pub fn held_transaction_ids(&self) -> Vec<u32> {
+        self.transactions_sorted()
+            .filter(|tx| tx.status == TransactionStatus::Disputed)
+            .map(|tx| tx.id)
+            .collect()
+    }
+
+    /// formatted straight from the cached ticks, see `format_ticks`
+    pub fn to_csv_row(&self) -> String {
+        self.balance().to_csv_row()
     }
 }
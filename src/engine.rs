@@ -0,0 +1,885 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+
+use crate::{
+    Amount, AppError, ClientBalance, Currency, Summary, Transaction, TransactionInput,
+    TransactionSide, TransactionStatus, TransactionType, TxOutcome, TxRules, TxStore, User,
+    ViolationPolicy,
+};
+
+/// A dispute, resolve or chargeback filed under another client than the owner of its tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientMismatch {
+    pub tx_id: u32,
+    /// client id the input was filed with
+    pub filed: u16,
+    /// client the input was routed to instead
+    pub owner: u16,
+    pub line: Option<usize>,
+}
+
+impl Display for ClientMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "line -: ")?,
+        }
+        write!(
+            f,
+            "tx {} filed for client {} belongs to client {}",
+            self.tx_id, self.filed, self.owner
+        )
+    }
+}
+
+/// An input ignored for breaking a rule under `ViolationPolicy::Warn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxWarning {
+    pub tx_id: u32,
+    /// client id the input was filed with
+    pub client: u16,
+    pub line: Option<usize>,
+    pub outcome: TxOutcome,
+}
+
+impl Display for TxWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "line -: ")?,
+        }
+        write!(
+            f,
+            "tx {} of client {} {}, ignored",
+            self.tx_id,
+            self.client,
+            violation(self.outcome)
+        )
+    }
+}
+
+/// A withdrawal ignored for going past the funds of its client, see
+/// `PaymentEngine::with_withdrawal_report`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectedWithdrawal {
+    pub tx_id: u32,
+    pub client: u16,
+    pub line: Option<usize>,
+    /// how much the withdrawal exceeded the funds it could take, minimum balance included
+    pub shortfall: Amount<i128>,
+}
+
+impl Display for RejectedWithdrawal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "line -: ")?,
+        }
+        write!(
+            f,
+            "withdrawal tx {} of client {} short by {}, rejected",
+            self.tx_id, self.client, self.shortfall
+        )
+    }
+}
+
+/// A consistency check of the ledger that failed, see `PaymentEngine::verify_invariants`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// available plus held funds of a client are negative
+    NegativeTotal {
+        client: u16,
+        total: Amount<i128>,
+    },
+    NegativeHeld {
+        client: u16,
+        held: Amount<i128>,
+    },
+    /// cached balances of a client drifted from the ones replayed from its transactions
+    BalanceMismatch {
+        cached: Box<ClientBalance>,
+        recomputed: Box<ClientBalance>,
+    },
+    /// sum of every client's total differs from `PaymentEngine::net_flow_ticks`
+    NetFlowMismatch {
+        grand_total: Amount<i128>,
+        net_flows: Amount<i128>,
+    },
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        match self {
+            InvariantViolation::NegativeTotal { client, total } => {
+                write!(f, "client {}: total funds are negative ({})", client, total)
+            }
+            InvariantViolation::NegativeHeld { client, held } => {
+                write!(f, "client {}: held funds are negative ({})", client, held)
+            }
+            InvariantViolation::BalanceMismatch { cached, recomputed } => write!(
+                f,
+                "client {}: cached balances {} differ from recomputed {}",
+                cached.client,
+                cached.to_csv_row(),
+                recomputed.to_csv_row()
+            ),
+            InvariantViolation::NetFlowMismatch {
+                grand_total,
+                net_flows,
+            } => write!(
+                f,
+                "grand total {} differs from net flows {}",
+                grand_total, net_flows
+            ),
+        }
+    }
+}
+
+/// what the input ignored as `outcome` violated
+fn violation(outcome: TxOutcome) -> &'static str {
+    match outcome {
+        TxOutcome::IgnoredDuplicate => "reuses an existing tx id",
+        TxOutcome::IgnoredConflictingDuplicate => "reuses the tx id of a different transaction",
+        TxOutcome::IgnoredTxLimit => "exceeds the transactions per client limit",
+        _ => "was not applied",
+    }
+}
+
+/// Invoked with a client right after it becomes locked
+pub type LockHook<S = HashMap<u32, Transaction>> = Box<dyn FnMut(&User<S>)>;
+
+/// Creates the empty `TxStore` of a new client
+pub type StoreFactory<S> = Box<dyn Fn(u16) -> S>;
+
+/// Ledger owning every client, simulating a simple database.
+///
+/// Transactions are kept in memory unless another `TxStore` is picked with `with_store`.
+pub struct PaymentEngine<S: TxStore = HashMap<u32, Transaction>> {
+    users: HashMap<u16, User<S>>,
+    /// owner of every deposit and withdrawal, as tx ids are unique across all clients
+    tx_owners: HashMap<u32, u16>,
+    mismatches: Vec<ClientMismatch>,
+    warnings: Vec<TxWarning>,
+    /// only recorded `with_withdrawal_report`
+    rejected_withdrawals: Option<Vec<RejectedWithdrawal>>,
+    /// type and tx id of every input processed, only recorded `with_global_dedupe`
+    processed: Option<HashSet<(TransactionType, u32)>>,
+    /// number of inputs applied so far, the clock of `TxRules::authorization_expiry`
+    inputs: usize,
+    /// `(deadline, client, tx id)` of every authorization that may expire, by ascending deadline
+    authorizations: VecDeque<(usize, u16, u32)>,
+    audit: bool,
+    strict: bool,
+    rules: TxRules,
+    on_lock: Option<LockHook<S>>,
+    new_store: StoreFactory<S>,
+}
+
+impl Default for PaymentEngine {
+    fn default() -> Self {
+        Self::with_store(|_| HashMap::new())
+    }
+}
+
+impl PaymentEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S: TxStore> PaymentEngine<S> {
+    /// creates an engine keeping the transactions of each client in the store `new_store` returns
+    pub fn with_store(new_store: impl Fn(u16) -> S + 'static) -> Self {
+        Self {
+            users: HashMap::new(),
+            tx_owners: HashMap::new(),
+            mismatches: Vec::new(),
+            warnings: Vec::new(),
+            rejected_withdrawals: None,
+            processed: None,
+            inputs: 0,
+            authorizations: VecDeque::new(),
+            audit: false,
+            strict: false,
+            rules: TxRules::default(),
+            on_lock: None,
+            new_store: Box::new(new_store),
+        }
+    }
+
+    /// makes every client created from now on record an audit log
+    pub fn with_audit(mut self) -> Self {
+        self.audit = true;
+        self
+    }
+
+    /// Rejects inputs that would otherwise be ignored with `AppError::InvalidRecord`, as well as
+    /// inputs leaving a client with negative available funds (see `User::check_available`),
+    /// which are still applied.
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// records every withdrawal ignored for insufficient funds, see `rejected_withdrawals`
+    pub fn with_withdrawal_report(mut self) -> Self {
+        self.rejected_withdrawals = Some(Vec::new());
+        self
+    }
+
+    /// Processes each input at most once, ignoring as `TxOutcome::IgnoredDuplicate` any later one
+    /// of the same type and tx id, whatever its client or amount, so reprocessing an input is a
+    /// no-op, e.g. `file + file` ends up as `file`.
+    ///
+    /// Deposits and withdrawals ignored for insufficient funds count as processed, so they aren't
+    /// retried once funds arrive, nor are disputes under `TxRules::allow_redispute`. Inputs
+    /// failing with an error don't count.
+    pub fn with_global_dedupe(mut self) -> Self {
+        self.processed = Some(HashSet::new());
+        self
+    }
+
+    /// applies every transaction under `rules` instead of the defaults
+    pub fn with_rules(mut self, rules: TxRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Registers `hook` to be called once each time a client becomes locked, e.g. to push a
+    /// fraud alert as soon as a chargeback lands.
+    ///
+    /// The hook fires as the chargeback is applied, so within an atomic batch it may report a
+    /// lock that the batch later rolls back.
+    pub fn on_lock(mut self, hook: impl FnMut(&User<S>) + 'static) -> Self {
+        self.on_lock = Some(Box::new(hook));
+        self
+    }
+
+    /// Routes `tx` to its client, creating it on first reference.
+    ///
+    /// Tx ids are unique across clients, so a deposit or withdrawal reusing the id of another
+    /// client's transaction is ignored as `TxOutcome::IgnoredConflictingDuplicate`, while a dispute, resolve
+    /// or chargeback is routed to the owner of its tx, recording a `ClientMismatch` when it was
+    /// filed under another client.
+    pub fn apply(&mut self, tx: TransactionInput) -> Result<TxOutcome, AppError> {
+        self.apply_inner(tx, None, None)
+    }
+
+    /// same as `apply`, tagging audit entries with the input line `tx` was parsed from
+    pub fn apply_at_line(
+        &mut self,
+        tx: TransactionInput,
+        line: usize,
+    ) -> Result<TxOutcome, AppError> {
+        self.apply_inner(tx, Some(line), None)
+    }
+
+    /// Same as `apply_at_line`, first checking the amount `claimed` by a resolve or chargeback
+    /// row against its transaction, see `TransactionInput::try_from_string_record_claimed`.
+    pub(crate) fn apply_claimed_at_line(
+        &mut self,
+        tx: TransactionInput,
+        line: usize,
+        claimed: Option<Amount>,
+    ) -> Result<TxOutcome, AppError> {
+        self.apply_inner(tx, Some(line), claimed)
+    }
+
+    fn apply_inner(
+        &mut self,
+        tx: TransactionInput,
+        line: Option<usize>,
+        claimed: Option<Amount>,
+    ) -> Result<TxOutcome, AppError> {
+        self.expire_authorizations()?;
+        let input = self.inputs;
+        self.inputs += 1;
+        let key = (tx.tx_type(), tx.id());
+        if self.is_processed(key) {
+            return Ok(TxOutcome::IgnoredDuplicate);
+        }
+        let filed = tx.client_id();
+        let owner = self.tx_owners.get(&tx.id()).copied();
+        let (tx, owned_elsewhere) = match (tx, owner) {
+            (_, Some(owner)) if owner == filed => (tx, false),
+            (_, Some(_)) if tx.tx_type().is_monetary() => (tx, true),
+            (_, Some(owner)) => {
+                self.mismatches.push(ClientMismatch {
+                    tx_id: tx.id(),
+                    filed,
+                    owner,
+                    line,
+                });
+                (tx.with_client_id(owner), false)
+            }
+            (_, None) => (tx, false),
+        };
+        let client_id = tx.client_id();
+        let (audit, new_store) = (self.audit, &self.new_store);
+        let client = self.users.entry(client_id).or_insert_with(|| {
+            let user = User::with_store(client_id, new_store(client_id));
+            if audit { user.audited() } else { user }
+        });
+        // transactions missing altogether are left to be ignored as such
+        if let Some((claimed, found)) = claimed.zip(client.transactions.get(tx.id()))
+            && claimed.widen() != found.amount.widen()
+        {
+            return Err(AppError::InvalidRecord(format!(
+                "{:?} of tx {} claims {}, but the transaction is of {}",
+                tx.tx_type(),
+                tx.id(),
+                claimed,
+                found.amount
+            )));
+        }
+        let was_locked = client.locked;
+        let outcome = if owned_elsewhere {
+            client.seen_at(line);
+            TxOutcome::IgnoredConflictingDuplicate
+        } else {
+            client.process_tx_input_with(tx, line, &self.rules)?
+        };
+        if let (TxOutcome::IgnoredInsufficientFunds, Some(rejected)) =
+            (outcome, self.rejected_withdrawals.as_mut())
+            && let Some((_, amount)) = tx.funds()
+        {
+            rejected.push(RejectedWithdrawal {
+                tx_id: tx.id(),
+                client: client_id,
+                line,
+                shortfall: client.shortfall(Amount::from(amount), &self.rules),
+            });
+        }
+        let policy = match outcome {
+            TxOutcome::IgnoredDuplicate => self.rules.on_duplicate,
+            TxOutcome::IgnoredConflictingDuplicate => self.rules.on_conflicting_duplicate,
+            TxOutcome::IgnoredTxLimit => self.rules.on_tx_limit,
+            _ => ViolationPolicy::Ignore,
+        };
+        match policy {
+            ViolationPolicy::Ignore => {}
+            ViolationPolicy::Error => {
+                return Err(AppError::InvalidRecord(format!(
+                    "client {}: tx {} {}",
+                    client_id,
+                    tx.id(),
+                    violation(outcome)
+                )));
+            }
+            ViolationPolicy::Warn => self.warnings.push(TxWarning {
+                tx_id: tx.id(),
+                client: client_id,
+                line,
+                outcome,
+            }),
+        }
+        if outcome == TxOutcome::Applied && tx.tx_type().is_monetary() {
+            self.tx_owners.insert(tx.id(), client_id);
+        }
+        if let (TransactionInput::Authorization(..), TxOutcome::Applied, Some(expiry)) =
+            (tx, outcome, self.rules.authorization_expiry)
+        {
+            self.authorizations
+                .push_back((input + expiry, client_id, tx.id()));
+        }
+        if let Some(hook) = self
+            .on_lock
+            .as_mut()
+            .filter(|_| !was_locked && client.locked)
+        {
+            hook(client);
+        }
+        if self.strict {
+            client.check_available()?;
+            if outcome != TxOutcome::Applied {
+                return Err(AppError::InvalidRecord(format!(
+                    "client {}: tx {} {:?}",
+                    client_id,
+                    tx.id(),
+                    outcome
+                )));
+            }
+        }
+        if let Some(processed) = self.processed.as_mut() {
+            processed.insert(key);
+        }
+        Ok(outcome)
+    }
+
+    /// Expires the authorizations left uncaptured past their deadline, see
+    /// `TxRules::authorization_expiry`. Captured ones are left as they are.
+    fn expire_authorizations(&mut self) -> Result<(), AppError> {
+        while let Some(&(deadline, client_id, tx_id)) = self.authorizations.front()
+            && deadline < self.inputs
+        {
+            self.authorizations.pop_front();
+            if let Some(user) = self.users.get_mut(&client_id) {
+                user.expire(tx_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_processed(&self, key: (TransactionType, u32)) -> bool {
+        self.processed
+            .as_ref()
+            .is_some_and(|processed| processed.contains(&key))
+    }
+
+    /// inputs routed to the owner of their tx, in the order they were applied
+    pub fn client_mismatches(&self) -> &[ClientMismatch] {
+        &self.mismatches
+    }
+
+    /// inputs ignored under `ViolationPolicy::Warn`, in input order
+    pub fn warnings(&self) -> &[TxWarning] {
+        &self.warnings
+    }
+
+    /// withdrawals ignored for insufficient funds, in input order, empty unless created
+    /// `with_withdrawal_report`
+    pub fn rejected_withdrawals(&self) -> &[RejectedWithdrawal] {
+        self.rejected_withdrawals.as_deref().unwrap_or_default()
+    }
+
+    /// Creates `client_id` with `available` opening funds, before it processes any transaction.
+    ///
+    /// They count as available funds as a deposit would, but aren't a transaction, so they
+    /// can't be disputed. Errors when the client already exists, or when `available` is negative.
+    pub fn open_account(
+        &mut self,
+        client_id: u16,
+        available: Amount<S::Ticks>,
+    ) -> Result<(), AppError> {
+        if available.is_negative() {
+            return Err(AppError::InvalidRecord(format!(
+                "client {}: opening balance {} can't be negative",
+                client_id, available
+            )));
+        }
+        if self.users.contains_key(&client_id) {
+            return Err(AppError::InvalidRecord(format!(
+                "client {}: opened after it was created",
+                client_id
+            )));
+        }
+        self.client_entry(client_id).credit_opening(available);
+        Ok(())
+    }
+
+    /// Tags `client_id` with the `currency` of one of its deposits or withdrawals, creating it if
+    /// needed, as every client transacts in a single currency.
+    ///
+    /// Errors when it was tagged with another one, mixing currencies in its balances.
+    pub fn tag_currency(&mut self, client_id: u16, currency: &Currency) -> Result<(), AppError> {
+        let user = self.client_entry(client_id);
+        match user.currency() {
+            Some(held) if held.code != currency.code => Err(AppError::InvalidRecord(format!(
+                "client {}: {} transaction on an account held in {}",
+                client_id, currency.code, held.code
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                user.set_currency(currency.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// `client_id`, created on first reference
+    fn client_entry(&mut self, client_id: u16) -> &mut User<S> {
+        let (audit, new_store) = (self.audit, &self.new_store);
+        self.users.entry(client_id).or_insert_with(|| {
+            let user = User::with_store(client_id, new_store(client_id));
+            if audit { user.audited() } else { user }
+        })
+    }
+
+    /// client owning the deposit or withdrawal `tx_id`, once applied
+    pub fn owner_of(&self, tx_id: u32) -> Option<u16> {
+        self.tx_owners.get(&tx_id).copied()
+    }
+
+    pub fn get(&self, client_id: u16) -> Option<&User<S>> {
+        self.users.get(&client_id)
+    }
+
+    /// users in ascending id order, so every output derived from them is deterministic
+    pub fn users(&self) -> impl Iterator<Item = &User<S>> {
+        let mut users: Vec<&User<S>> = self.users.values().collect();
+        users.sort_by_key(|user| user.id);
+        users.into_iter()
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = ClientBalance> + '_ {
+        self.users().map(User::balance)
+    }
+
+    /// Cross-checks the cached balances of every client against the ones replayed from its
+    /// transactions, see `User::verify`.
+    pub fn verify(&self) -> Result<(), AppError> {
+        self.users().try_for_each(User::verify)
+    }
+
+    /// Every internal consistency check failing on the ledger, see `User::invariant_violations`,
+    /// followed by the grand total of the unclamped balances not matching `net_flow_ticks`.
+    ///
+    /// Inputs never break these, so any violation points at a logic bug or corrupted state.
+    pub fn invariant_violations(&self) -> Vec<InvariantViolation> {
+        let mut violations: Vec<InvariantViolation> =
+            self.users().flat_map(User::invariant_violations).collect();
+        let grand_total: i128 = self
+            .users
+            .values()
+            .map(|user| user.unclamped_total().to_ticks())
+            .sum();
+        let net_flows = self.net_flow_ticks();
+        if grand_total != net_flows {
+            violations.push(InvariantViolation::NetFlowMismatch {
+                grand_total: Amount::from_ticks(grand_total),
+                net_flows: Amount::from_ticks(net_flows),
+            });
+        }
+        violations
+    }
+
+    /// errors listing every `invariant_violations`, if any
+    pub fn verify_invariants(&self) -> Result<(), AppError> {
+        let violations = self.invariant_violations();
+        if !violations.is_empty() {
+            return Err(AppError::InvariantViolations(violations));
+        }
+        Ok(())
+    }
+
+    /// sum of every client's total, to reconcile against `net_flow_ticks`, widened to `i128` so
+    /// it can't overflow whatever the `Ticks` of the engine
+    pub fn grand_total_ticks(&self) -> i128 {
+        self.users.values().map(|user| user.balance().total).sum()
+    }
+
+    /// client counts and money totals, to print instead of every client's balance
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary {
+            clients: self.users.len(),
+            locked: 0,
+            total: self.grand_total_ticks(),
+            held: 0,
+            filtered: None,
+        };
+        for user in self.users.values() {
+            summary.locked += usize::from(user.locked);
+            summary.held += user.balance().held;
+        }
+        summary
+    }
+
+    /// Opening balances plus applied deposits minus withdrawals and charged back deposits,
+    /// summed straight from the transactions of every client. Disputed, charged back,
+    /// authorized and expired withdrawals are left out, as their funds are held or given back.
+    ///
+    /// It should always equal `grand_total_ticks`, as funds only enter or leave through these.
+    pub fn net_flow_ticks(&self) -> i128 {
+        let opening: i128 = self
+            .users
+            .values()
+            .map(|user| user.opening_balance().widen().to_ticks())
+            .sum();
+        let flows: i128 = self
+            .users
+            .values()
+            .flat_map(|user| user.transactions.transactions())
+            .map(|tx| match (tx.side, tx.status) {
+                (TransactionSide::Deposit, TransactionStatus::Solved(true)) => 0,
+                (TransactionSide::Deposit, _) => tx.amount.widen().to_ticks(),
+                // disputed and authorized withdrawals are held back, and charged back or expired
+                // ones reversed
+                (
+                    TransactionSide::Withdrawal,
+                    TransactionStatus::Disputed
+                    | TransactionStatus::Solved(true)
+                    | TransactionStatus::Authorized
+                    | TransactionStatus::Expired,
+                ) => 0,
+                (TransactionSide::Withdrawal, _) => -tx.amount.widen().to_ticks(),
+            })
+            .sum();
+        opening + flows
+    }
+
+    /// used when restoring state, replacing any user with the same id
+    pub(crate) fn insert_user(&mut self, user: User<S>) {
+        self.remove_user(user.id);
+        for tx in user.transactions.transactions() {
+            self.tx_owners.insert(tx.id, user.id);
+        }
+        self.users.insert(user.id, user);
+    }
+
+    fn remove_user(&mut self, client_id: u16) -> Option<User<S>> {
+        let user = self.users.remove(&client_id)?;
+        for tx in user.transactions.transactions() {
+            self.tx_owners.remove(&tx.id);
+        }
+        Some(user)
+    }
+}
+
+/// Batches need to snapshot the clients they touch, so they're only available with a `Clone`
+/// store.
+impl<S: TxStore + Clone> PaymentEngine<S> {
+    /// Applies `inputs` in order, stopping at the first error.
+    ///
+    /// When `atomic`, that error also rolls every client touched by the batch back to its
+    /// pre-batch state, otherwise the inputs applied before the failure are kept.
+    pub fn apply_batch(
+        &mut self,
+        inputs: Vec<TransactionInput>,
+        atomic: bool,
+    ) -> Result<Vec<TxOutcome>, AppError> {
+        let inputs = inputs.into_iter().map(|tx| (tx, None, None)).collect();
+        self.apply_batch_inner(inputs, atomic)
+    }
+
+    /// atomic batch tagging audit entries with the input line of each tx, and checking the
+    /// amounts claimed by resolves and chargebacks, see `apply_claimed_at_line`
+    pub(crate) fn apply_batch_at_lines(
+        &mut self,
+        inputs: Vec<(TransactionInput, usize, Option<Amount>)>,
+    ) -> Result<Vec<TxOutcome>, AppError> {
+        let inputs = inputs
+            .into_iter()
+            .map(|(tx, line, claimed)| (tx, Some(line), claimed))
+            .collect();
+        self.apply_batch_inner(inputs, true)
+    }
+
+    /// Folds the clients of `other` in, e.g. to combine engines that each processed a shard of
+    /// the input.
+    ///
+    /// Clients only known to `other`, none of whose tx ids are used here, are moved in as they
+    /// are, audit log and seen lines included. Every other client of `other` is replayed on top
+    /// of this engine, under its rules: its opening balance first, added to any one here, then
+    /// deposits and withdrawals by ascending tx id, and the disputes, resolves and chargebacks
+    /// bringing them to their state in `other`. Tx ids already
+    /// used here are duplicates, ignored as such, and so are the disputes of them, keeping the
+    /// transactions of this engine.
+    ///
+    /// Replaying is an atomic batch, so its first error, e.g. a duplicate under
+    /// `ViolationPolicy::Error`, leaves this engine untouched. The mismatches, warnings and
+    /// rejected withdrawals of `other` are appended to the ones of this engine.
+    pub fn merge(&mut self, other: PaymentEngine<S>) -> Result<(), AppError> {
+        let mut users: Vec<User<S>> = other.users.into_values().collect();
+        users.sort_by_key(|user| user.id);
+        let (mut moved, mut inputs, mut openings) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut currencies, mut expired) = (Vec::new(), Vec::new());
+        for user in users {
+            let existing = self.users.contains_key(&user.id);
+            let known = existing
+                || user
+                    .transactions
+                    .transactions()
+                    .any(|tx| self.tx_owners.contains_key(&tx.id));
+            if known {
+                inputs.extend(replay_inputs(&user, &self.tx_owners)?);
+                // replayed as authorizations, expired once the batch is applied
+                expired.extend(
+                    user.transactions
+                        .transactions()
+                        .filter(|tx| {
+                            tx.status == TransactionStatus::Expired
+                                && !self.tx_owners.contains_key(&tx.id)
+                        })
+                        .map(|tx| (user.id, tx.id)),
+                );
+                if user.opening_balance() != Amount::ZERO {
+                    openings.push((user.id, user.opening_balance(), existing));
+                }
+                if let Some(currency) = user.currency() {
+                    // checked ahead of any change, as tagging can't be rolled back
+                    if let Some(held) = self.get(user.id).and_then(User::currency)
+                        && held.code != currency.code
+                    {
+                        return Err(AppError::InvalidRecord(format!(
+                            "client {}: {} transactions on an account held in {}",
+                            user.id, currency.code, held.code
+                        )));
+                    }
+                    currencies.push((user.id, currency.clone()));
+                }
+            } else {
+                moved.push(user);
+            }
+        }
+        // opening balances add up, ahead of the replayed transactions as in `other`
+        for (client_id, opening, _) in &openings {
+            self.client_entry(*client_id).credit_opening(*opening);
+        }
+        let inputs = inputs.into_iter().map(|tx| (tx, None, None)).collect();
+        if let Err(err) = self.apply_batch_inner(inputs, true) {
+            for (client_id, opening, existing) in openings {
+                match self.users.get_mut(&client_id) {
+                    Some(user) if existing => user.credit_opening(-opening),
+                    _ => {
+                        self.remove_user(client_id);
+                    }
+                }
+            }
+            return Err(err);
+        }
+        for (client_id, currency) in currencies {
+            self.tag_currency(client_id, &currency)?;
+        }
+        for (client_id, tx_id) in expired {
+            if let Some(user) = self.users.get_mut(&client_id) {
+                user.expire(tx_id)?;
+            }
+        }
+
+        for user in moved {
+            if let Some(hook) = self.on_lock.as_mut().filter(|_| user.locked) {
+                hook(&user);
+            }
+            self.insert_user(user);
+        }
+        if let (Some(processed), Some(other)) = (self.processed.as_mut(), other.processed) {
+            processed.extend(other);
+        }
+        self.mismatches.extend(other.mismatches);
+        self.warnings.extend(other.warnings);
+        if let Some(rejected) = self.rejected_withdrawals.as_mut() {
+            rejected.extend(other.rejected_withdrawals.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    fn apply_batch_inner(
+        &mut self,
+        inputs: Vec<(TransactionInput, Option<usize>, Option<Amount>)>,
+        atomic: bool,
+    ) -> Result<Vec<TxOutcome>, AppError> {
+        // only the clients touched by the batch need to be restored, including the owners
+        // disputes, resolves and chargebacks filed under another client are sent to
+        let backup: Vec<(u16, Option<User<S>>)> = if atomic {
+            let mut client_ids: Vec<u16> = inputs
+                .iter()
+                .flat_map(|(tx, _, _)| {
+                    let owner = self
+                        .tx_owners
+                        .get(&tx.id())
+                        .copied()
+                        .filter(|_| !tx.tx_type().is_monetary());
+                    [Some(tx.client_id()), owner]
+                })
+                .flatten()
+                .collect();
+            // as well as the ones whose authorizations expire while the batch is applied
+            let clock_end = self.inputs + inputs.len();
+            client_ids.extend(
+                self.authorizations
+                    .iter()
+                    .take_while(|(deadline, _, _)| *deadline < clock_end)
+                    .map(|(_, client_id, _)| *client_id),
+            );
+            client_ids.sort_unstable();
+            client_ids.dedup();
+            client_ids
+                .into_iter()
+                .map(|client_id| (client_id, self.users.get(&client_id).cloned()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let (mismatches, warnings) = (self.mismatches.len(), self.warnings.len());
+        let rejected = self.rejected_withdrawals().len();
+        // restored along with the clients, so expiries within the batch are undone too
+        let clock = (atomic && self.rules.authorization_expiry.is_some())
+            .then(|| (self.inputs, self.authorizations.clone()));
+        // inputs first processed by the batch, forgotten along with it
+        let mut processed = Vec::new();
+        let mut outcomes = Vec::with_capacity(inputs.len());
+        for (tx, line, claimed) in inputs {
+            let key = (tx.tx_type(), tx.id());
+            let fresh = self.processed.is_some() && !self.is_processed(key);
+            match self.apply_inner(tx, line, claimed) {
+                Ok(outcome) => {
+                    if fresh {
+                        processed.push(key);
+                    }
+                    outcomes.push(outcome)
+                }
+                Err(err) => {
+                    if atomic {
+                        if let Some(seen) = self.processed.as_mut() {
+                            for key in processed {
+                                seen.remove(&key);
+                            }
+                        }
+                        self.mismatches.truncate(mismatches);
+                        self.warnings.truncate(warnings);
+                        if let Some(rejected_withdrawals) = self.rejected_withdrawals.as_mut() {
+                            rejected_withdrawals.truncate(rejected);
+                        }
+                        if let Some((inputs, authorizations)) = clock {
+                            (self.inputs, self.authorizations) = (inputs, authorizations);
+                        }
+                    }
+                    for (client_id, user) in backup {
+                        match user {
+                            Some(user) => self.insert_user(user),
+                            None => {
+                                self.remove_user(client_id);
+                            }
+                        };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+/// Inputs bringing the transactions of `user` to their current state, skipping the disputes of
+/// the ones whose id is in `used`, see `PaymentEngine::merge`.
+fn replay_inputs<S: TxStore>(
+    user: &User<S>,
+    used: &HashMap<u32, u16>,
+) -> Result<Vec<TransactionInput>, AppError> {
+    let transactions: Vec<_> = user.transactions_sorted().collect();
+    let mut inputs = Vec::with_capacity(transactions.len());
+    for tx in &transactions {
+        let ticks = i32::try_from(tx.amount.widen().to_ticks()).map_err(|_| {
+            AppError::InvalidRecord(format!("tx {} of {} can't be replayed", tx.id, tx.amount))
+        })?;
+        inputs.push(match tx.status {
+            TransactionStatus::Authorized | TransactionStatus::Expired => {
+                TransactionInput::authorization(tx.id, user.id, ticks)?
+            }
+            _ => TransactionInput::monetary(tx.side, tx.id, user.id, ticks)?,
+        });
+    }
+    let settled = || transactions.iter().filter(|tx| !used.contains_key(&tx.id));
+    inputs.extend(
+        settled()
+            .filter(|tx| {
+                matches!(
+                    tx.status,
+                    TransactionStatus::Disputed | TransactionStatus::Solved(_)
+                )
+            })
+            .map(|tx| TransactionInput::dispute(tx.id, user.id)),
+    );
+    inputs.extend(
+        settled()
+            .filter(|tx| tx.status == TransactionStatus::Solved(false))
+            .map(|tx| TransactionInput::resolve(tx.id, user.id)),
+    );
+    inputs.extend(
+        settled()
+            .filter(|tx| tx.status == TransactionStatus::Solved(true))
+            .map(|tx| TransactionInput::chargeback(tx.id, user.id)),
+    );
+    Ok(inputs)
+}
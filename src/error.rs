@@ -1,11 +1,15 @@
 use csv::Error as CsvError;
+use std::convert::Infallible;
 use std::fmt::{Display, Formatter, Result as FormatResult};
 use std::io::Error as IoError;
-use std::num::{ParseFloatError, ParseIntError};
+use std::num::{ParseFloatError, ParseIntError, TryFromIntError};
+
+use crate::{ClientBalance, InvariantViolation, LineTooLong};
 
 #[derive(Debug)]
 pub enum AppError {
     MissingArgument,
+    InvalidArgument(String),
     FileNotFound(String),
     InvalidFormat(String),
     InvalidRecord(String),
@@ -14,16 +18,107 @@ pub enum AppError {
     CsvError(CsvError),
     ParseInt(ParseIntError),
     ParseFloat(ParseFloatError),
+    /// a value out of the range of the integer it's converted into, naming it and the range when
+    /// known, e.g. for amounts
+    RangeError(String),
+    TooManyErrors(usize),
+    /// invalid records skipped under `--continue-on-error`, the valid ones being processed
+    SkippedRecords(usize),
+    /// stopped on `ProcessOptions::interrupt` once this many records were processed, the balances
+    /// written being the partial ones computed so far
+    Interrupted(usize),
+    /// cached balances of a client drifted from the ones replayed from its transactions
+    BalanceMismatch {
+        cached: Box<ClientBalance>,
+        recomputed: Box<ClientBalance>,
+    },
+    /// consistency checks failing on the ledger after processing, under `--verify-invariants`
+    InvariantViolations(Vec<InvariantViolation>),
+    /// error caused by the input record at `line`
+    AtLine {
+        line: usize,
+        source: Box<AppError>,
+    },
+}
+
+impl AppError {
+    /// attaches the input `line` this error was caused by, rendered as `Line {line}: {self}`
+    pub fn with_line(self, line: usize) -> Self {
+        AppError::AtLine {
+            line,
+            source: Box::new(self),
+        }
+    }
+
+    /// Process exit code reporting the category of this error, so scripts can tell them apart:
+    ///
+    /// | code | category                                            |
+    /// | ---- | --------------------------------------------------- |
+    /// | 1    | I/O failure, reading the input or writing output    |
+    /// | 2    | usage, a missing or invalid argument                |
+    /// | 3    | input or resumed snapshot file not found            |
+    /// | 4    | malformed input, a bad file format or record        |
+    /// | 5    | inconsistent ledger, e.g. drifted cached balances   |
+    /// | 130  | interrupted, after partial balances                 |
+    pub fn exit_code(&self) -> i32 {
+        match self.inner() {
+            AppError::IoError(_) => 1,
+            AppError::CsvError(err) if err.is_io_error() => 1,
+            AppError::MissingArgument | AppError::InvalidArgument(_) => 2,
+            AppError::FileNotFound(_) => 3,
+            AppError::InvalidFormat(_)
+            | AppError::InvalidRecord(_)
+            | AppError::InvalidTxType(_)
+            | AppError::CsvError(_)
+            | AppError::ParseInt(_)
+            | AppError::ParseFloat(_)
+            | AppError::RangeError(_)
+            | AppError::TooManyErrors(_)
+            | AppError::SkippedRecords(_) => 4,
+            AppError::BalanceMismatch { .. } | AppError::InvariantViolations(_) => 5,
+            // 128 + SIGINT, as shells report processes killed by it
+            AppError::Interrupted(_) => 130,
+            AppError::AtLine { .. } => unreachable!("inner errors have no line"),
+        }
+    }
+
+    /// the error itself, without the line it may be attached to
+    pub fn inner(&self) -> &AppError {
+        match self {
+            AppError::AtLine { source, .. } => source.inner(),
+            err => err,
+        }
+    }
+}
+
+/// Annotates the error of a `Result` with the input line it was caused by, see
+/// `AppError::with_line`.
+pub trait ResultExt<T> {
+    fn at_line(self, line: usize) -> Result<T, AppError>;
+}
+
+impl<T, E: Into<AppError>> ResultExt<T> for Result<T, E> {
+    fn at_line(self, line: usize) -> Result<T, AppError> {
+        self.map_err(|err| err.into().with_line(line))
+    }
 }
 
 impl From<csv::Error> for AppError {
     fn from(value: CsvError) -> Self {
+        if let csv::ErrorKind::Io(err) = value.kind()
+            && let Some(too_long) = LineTooLong::of(err)
+        {
+            return AppError::InvalidFormat(too_long.to_string());
+        }
         AppError::CsvError(value)
     }
 }
 
 impl From<IoError> for AppError {
     fn from(value: IoError) -> Self {
+        if let Some(too_long) = LineTooLong::of(&value) {
+            return AppError::InvalidFormat(too_long.to_string());
+        }
         AppError::IoError(value)
     }
 }
@@ -40,13 +135,27 @@ impl From<ParseFloatError> for AppError {
     }
 }
 
+impl From<TryFromIntError> for AppError {
+    fn from(err: TryFromIntError) -> Self {
+        AppError::RangeError(err.to_string())
+    }
+}
+
+/// for tick conversions that can't fail under some `TickInt`, e.g. `i32` into `i32`
+impl From<Infallible> for AppError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
 impl Display for AppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
         match self {
             AppError::MissingArgument => write!(
                 f,
-                "Usage: cargo run -- <input_file>\nError: missing input file argument"
+                "Usage: cargo run -- [options] <input_file>\nError: missing input file argument"
             ),
+            AppError::InvalidArgument(reason) => write!(f, "Invalid argument: {}", reason),
             AppError::FileNotFound(path) => write!(f, "File not found: {}", path),
             AppError::InvalidFormat(reason) => write!(f, "Invalid file format: {}", reason),
             AppError::InvalidRecord(record) => {
@@ -57,6 +166,29 @@ impl Display for AppError {
             AppError::CsvError(err) => write!(f, "CSV error: {}", err),
             AppError::ParseInt(err) => write!(f, "Parse int error {}", err),
             AppError::ParseFloat(err) => write!(f, "Parse float error {}", err),
+            AppError::RangeError(reason) => write!(f, "Range error: {}", reason),
+            AppError::TooManyErrors(max) => write!(f, "Aborted after {} invalid records", max),
+            AppError::SkippedRecords(count) => write!(f, "Skipped {} invalid records", count),
+            AppError::Interrupted(records) => write!(
+                f,
+                "Interrupted after {} records, balances are partial",
+                records
+            ),
+            AppError::BalanceMismatch { cached, recomputed } => write!(
+                f,
+                "Balance mismatch for client {}: cached {}, recomputed {}",
+                cached.client,
+                cached.to_csv_row(),
+                recomputed.to_csv_row()
+            ),
+            AppError::InvariantViolations(violations) => {
+                write!(f, "{} ledger invariants violated:", violations.len())?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            AppError::AtLine { line, source } => write!(f, "Line {}: {}", line, source),
         }
     }
 }
@@ -0,0 +1,280 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::{AppError, format_ticks, parse_flag};
+
+/// Relative weights of each transaction type in a generated input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxMix {
+    pub deposits: u32,
+    pub withdrawals: u32,
+    pub disputes: u32,
+    pub resolves: u32,
+    pub chargebacks: u32,
+}
+
+impl Default for TxMix {
+    fn default() -> Self {
+        Self {
+            deposits: 60,
+            withdrawals: 30,
+            disputes: 6,
+            resolves: 3,
+            chargebacks: 1,
+        }
+    }
+}
+
+impl TxMix {
+    fn weights(&self) -> [u32; 5] {
+        [
+            self.deposits,
+            self.withdrawals,
+            self.disputes,
+            self.resolves,
+            self.chargebacks,
+        ]
+    }
+}
+
+/// parses `deposits,withdrawals,disputes,resolves,chargebacks` weights, e.g. `60,30,6,3,1`
+impl FromStr for TxMix {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::InvalidArgument(format!(
+                "--mix expects 5 comma separated weights, at least one of them non zero, got {}",
+                s
+            ))
+        };
+        let weights = s
+            .split(',')
+            .map(|weight| weight.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [deposits, withdrawals, disputes, resolves, chargebacks] =
+            weights.try_into().map_err(|_| invalid())?;
+        let mix = Self {
+            deposits,
+            withdrawals,
+            disputes,
+            resolves,
+            chargebacks,
+        };
+        if mix.weights().iter().all(|weight| *weight == 0) {
+            return Err(invalid());
+        }
+        Ok(mix)
+    }
+}
+
+/// Options of the `generate` subcommand, parsed from
+/// `cargo run -- generate --clients <n> --rows <n> [--seed <n>] [--mix <weights>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerateOptions {
+    pub clients: u16,
+    pub rows: usize,
+    pub seed: u64,
+    pub mix: TxMix,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            clients: 10,
+            rows: 1_000,
+            seed: 1,
+            mix: TxMix::default(),
+        }
+    }
+}
+
+impl GenerateOptions {
+    /// expects the subcommand as first argument, as `Config::from_args` does the program name
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, AppError> {
+        let mut args = args.into_iter().skip(1);
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--clients" => options.clients = parse_flag(&mut args, &arg)?,
+                "--rows" => options.rows = parse_flag(&mut args, &arg)?,
+                "--seed" => options.seed = parse_flag(&mut args, &arg)?,
+                "--mix" => options.mix = parse_flag(&mut args, &arg)?,
+                _ => {
+                    return Err(AppError::InvalidArgument(format!(
+                        "unknown generate option {}",
+                        arg
+                    )));
+                }
+            }
+        }
+        if options.clients == 0 {
+            return Err(AppError::InvalidArgument(
+                "--clients must be at least 1".to_string(),
+            ));
+        }
+        if u32::try_from(options.rows).is_err() {
+            return Err(AppError::InvalidArgument(format!(
+                "--rows can't exceed {}, the last tx id",
+                u32::MAX
+            )));
+        }
+        Ok(options)
+    }
+}
+
+/// xorshift, so the same seed always generates the same input
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// in `0..bound`, `bound` being non zero
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// What the generator tracks of a client, to only emit rows the engine applies
+#[derive(Debug, Clone, Copy, Default)]
+struct ClientState {
+    available: i64,
+    locked: bool,
+}
+
+/// Writes a csv of `options.rows` rows, with a header, spread over `options.clients`.
+///
+/// Rows only reference prior transactions of the same client: disputes name undisputed
+/// deposits still covered by the available funds, resolves and chargebacks name disputed ones,
+/// and withdrawals never exceed the available funds, so every row is applied when replaying the
+/// output, even with `--strict`.
+///
+/// Types without anything to reference yet, rows picking a client locked by a chargeback, and
+/// chargebacks that would lock the last unlocked client fall back to a deposit on an unlocked
+/// client, so exactly `options.rows` rows are written.
+pub fn generate<W: Write>(writer: &mut W, options: &GenerateOptions) -> Result<(), AppError> {
+    let mut rng = Rng::new(options.seed);
+    let weights = options.mix.weights();
+    let total_weight: u64 = weights.iter().map(|weight| u64::from(*weight)).sum();
+    let mut clients = vec![ClientState::default(); usize::from(options.clients)];
+    // (tx, client, ticks) of deposits open to disputes, and of the disputed ones
+    let mut undisputed: Vec<(u32, u16, i64)> = Vec::new();
+    let mut disputed: Vec<(u32, u16, i64)> = Vec::new();
+    let mut locked = 0;
+
+    writeln!(writer, "type,client,tx,amount")?;
+    for tx in 1..=options.rows as u32 {
+        let mut roll = rng.below(total_weight);
+        let kind = weights
+            .iter()
+            .position(|weight| {
+                let hit = roll < u64::from(*weight);
+                roll = roll.saturating_sub(u64::from(*weight));
+                hit
+            })
+            .unwrap_or(0);
+        let client = rng.below(u64::from(options.clients)) as u16;
+        // 0.0001 to 100.0000
+        let ticks = rng.below(1_000_000) as i64 + 1;
+
+        let state = clients[usize::from(client)];
+        match kind {
+            1 if !state.locked && state.available > 0 => {
+                let ticks = ticks.min(state.available);
+                clients[usize::from(client)].available -= ticks;
+                write_row(writer, "withdrawal", client, tx, Some(ticks))?;
+                continue;
+            }
+            2 => {
+                if let Some((id, client, ticks)) =
+                    take(&mut undisputed, &clients, &mut rng, |state, ticks| {
+                        state.available >= ticks
+                    })
+                {
+                    clients[usize::from(client)].available -= ticks;
+                    disputed.push((id, client, ticks));
+                    write_row(writer, "dispute", client, id, None)?;
+                    continue;
+                }
+            }
+            3 => {
+                if let Some((id, client, ticks)) =
+                    take(&mut disputed, &clients, &mut rng, |_, _| true)
+                {
+                    clients[usize::from(client)].available += ticks;
+                    write_row(writer, "resolve", client, id, None)?;
+                    continue;
+                }
+            }
+            // never locks the last unlocked client, which would leave nothing to generate
+            4 if locked + 1 < clients.len() => {
+                if let Some((id, client, _)) = take(&mut disputed, &clients, &mut rng, |_, _| true)
+                {
+                    clients[usize::from(client)].locked = true;
+                    locked += 1;
+                    write_row(writer, "chargeback", client, id, None)?;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        let client = unlocked_from(client, &clients);
+        clients[usize::from(client)].available += ticks;
+        undisputed.push((tx, client, ticks));
+        write_row(writer, "deposit", client, tx, Some(ticks))?;
+    }
+    Ok(())
+}
+
+/// Removes a random transaction of an unlocked client from `pool`, unless `applies` rejects it,
+/// leaving it in for later.
+fn take(
+    pool: &mut Vec<(u32, u16, i64)>,
+    clients: &[ClientState],
+    rng: &mut Rng,
+    applies: impl Fn(&ClientState, i64) -> bool,
+) -> Option<(u32, u16, i64)> {
+    while !pool.is_empty() {
+        let index = rng.below(pool.len() as u64) as usize;
+        let (_, client, ticks) = pool[index];
+        let state = &clients[usize::from(client)];
+        // transactions of locked clients are dropped as they come up
+        if state.locked {
+            pool.swap_remove(index);
+            continue;
+        }
+        return applies(state, ticks).then(|| pool.swap_remove(index));
+    }
+    None
+}
+
+/// `client`, or the next unlocked one after it, wrapping around, one always being left unlocked
+fn unlocked_from(client: u16, clients: &[ClientState]) -> u16 {
+    (0..clients.len())
+        .map(|offset| (usize::from(client) + offset) % clients.len())
+        .find(|index| !clients[*index].locked)
+        .expect("the last unlocked client is never charged back") as u16
+}
+
+/// client ids are generated from 0 and written from 1
+fn write_row<W: Write>(
+    writer: &mut W,
+    kind: &str,
+    client: u16,
+    tx: u32,
+    ticks: Option<i64>,
+) -> Result<(), AppError> {
+    let amount = ticks.map(format_ticks).unwrap_or_default();
+    writeln!(writer, "{},{},{},{}", kind, client + 1, tx, amount)?;
+    Ok(())
+}
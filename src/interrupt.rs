@@ -0,0 +1,24 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// whether `flag` was set, e.g. `ProcessOptions::interrupt`
+pub(crate) fn is_set(flag: Option<&AtomicBool>) -> bool {
+    flag.is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// Fails every read once `flag` is set instead of reading on, so a run stops on the read path
+/// rather than only between records, see `BufferInfo::with_interrupt`.
+pub(crate) struct Interruptible {
+    pub(crate) inner: Box<dyn Read>,
+    pub(crate) flag: &'static AtomicBool,
+}
+
+impl Read for Interruptible {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if is_set(Some(self.flag)) {
+            // not `ErrorKind::Interrupted`, which readers retry
+            return Err(io::Error::other("interrupted"));
+        }
+        self.inner.read(buf)
+    }
+}
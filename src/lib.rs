@@ -0,0 +1,36 @@
+mod error;
+pub use error::*;
+mod utils;
+pub use utils::*;
+mod amount;
+pub use amount::*;
+mod timestamp;
+pub use timestamp::*;
+mod ndjson;
+pub use ndjson::*;
+mod core;
+pub use core::*;
+mod store;
+pub use store::*;
+mod audit;
+pub use audit::*;
+mod metrics;
+pub use metrics::*;
+mod profile;
+pub use profile::*;
+mod interrupt;
+use interrupt::*;
+mod engine;
+pub use engine::*;
+mod balance;
+mod opening;
+mod snapshot;
+pub use balance::*;
+mod config;
+pub use config::*;
+mod processor;
+pub use processor::*;
+mod generate;
+pub use generate::*;
+mod r#static;
+pub use r#static::*;
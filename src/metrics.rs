@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+
+use crate::{TransactionType, TxOutcome};
+
+/// Mix of a processed input: how many transactions of each type were seen, and what became of them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    seen: BTreeMap<TransactionType, usize>,
+    outcomes: BTreeMap<(TransactionType, TxOutcome), usize>,
+}
+
+impl Metrics {
+    /// counts a parsed transaction, whether or not it ends up being applied
+    pub fn record_seen(&mut self, tx_type: TransactionType) {
+        *self.seen.entry(tx_type).or_default() += 1;
+    }
+
+    pub fn record_outcome(&mut self, tx_type: TransactionType, outcome: TxOutcome) {
+        *self.outcomes.entry((tx_type, outcome)).or_default() += 1;
+    }
+
+    pub fn seen(&self, tx_type: TransactionType) -> usize {
+        self.seen.get(&tx_type).copied().unwrap_or_default()
+    }
+
+    pub fn outcome(&self, tx_type: TransactionType, outcome: TxOutcome) -> usize {
+        self.outcomes
+            .get(&(tx_type, outcome))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Display for Metrics {
+    /// one `<type> seen <n>` line per type, each followed by its `<type> <outcome> <n>` lines
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        for (tx_type, seen) in &self.seen {
+            writeln!(f, "{:?} seen {}", tx_type, seen)?;
+            for ((_, outcome), count) in self
+                .outcomes
+                .iter()
+                .filter(|((outcome_type, _), _)| outcome_type == tx_type)
+            {
+                writeln!(f, "{:?} {:?} {}", tx_type, outcome, count)?;
+            }
+        }
+        Ok(())
+    }
+}
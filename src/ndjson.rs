@@ -0,0 +1,163 @@
+use csv::StringRecord;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::AppError;
+
+/// fields of a ndjson object, in the positional order expected by `TransactionInput`, followed by
+/// the optional timestamp and currency, see `ColumnMap::TIMESTAMP` and `ColumnMap::CURRENCY`
+const FIELDS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "currency"];
+
+/// Parses a single ndjson line, e.g. `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`, into
+/// the `[type, client, tx, amount]` record it stands for, so it goes through the same validation
+/// as a csv row. A `timestamp` key is appended as 5th field when present, and a `currency` key
+/// as 6th one, after an empty timestamp if needed.
+///
+/// Values may be strings, numbers or `null`, which behaves as an empty field. Numbers are kept as
+/// written, so amounts never go through floating point. Unknown keys are ignored, like extra
+/// columns would be.
+pub fn parse_ndjson_line(line: &str) -> Result<StringRecord, AppError> {
+    let mut parser = Parser {
+        line,
+        chars: line.char_indices().peekable(),
+    };
+    let mut fields: [Option<String>; FIELDS.len()] = Default::default();
+    parser.expect('{')?;
+    if !parser.eat('}') {
+        loop {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            let value = parser.value()?;
+            if let Some(index) = FIELDS.iter().position(|field| *field == key) {
+                if fields[index].is_some() {
+                    return Err(parser.error(&format!("duplicated key \"{}\"", key)));
+                }
+                fields[index] = Some(value);
+            }
+            if parser.eat('}') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return Err(parser.error("trailing characters after object"));
+    }
+    let [fields @ .., timestamp, currency] = &fields;
+    let timestamp = match (timestamp, currency) {
+        (None, Some(_)) => Some(""),
+        (timestamp, _) => timestamp.as_deref(),
+    };
+    Ok(fields
+        .iter()
+        .map(|field| field.as_deref().unwrap_or_default())
+        .chain(timestamp)
+        .chain(currency.as_deref())
+        .collect())
+}
+
+struct Parser<'a> {
+    line: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> AppError {
+        AppError::InvalidRecord(format!("invalid json object {}: {}", self.line, reason))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\r' | '\n'))
+            .is_some()
+        {}
+    }
+
+    /// consumes `expected` if it's the next non whitespace char
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if(|(_, c)| *c == expected).is_some()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), AppError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn value(&mut self) -> Result<String, AppError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some((_, '"')) => self.string(),
+            Some((_, 'n')) => {
+                self.literal("null")?;
+                Ok(String::new())
+            }
+            Some((start, c)) if c.is_ascii_digit() || *c == '-' => {
+                let start = *start;
+                let mut end = start;
+                while let Some((index, c)) = self.chars.next_if(|(_, c)| {
+                    c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')
+                }) {
+                    end = index + c.len_utf8();
+                }
+                Ok(self.line[start..end].to_string())
+            }
+            _ => Err(self.error("expected a string, number or null")),
+        }
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), AppError> {
+        for expected in literal.chars() {
+            if self.chars.next_if(|(_, c)| *c == expected).is_none() {
+                return Err(self.error(&format!("expected {}", literal)));
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, AppError> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(value),
+                Some((_, '\\')) => {
+                    let escaped = match self.chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'u')) => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    value.push(escaped);
+                }
+                Some((_, c)) => value.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// the `XXXX` of a `\uXXXX` escape, surrogate pairs aren't needed by any of our fields
+    fn unicode_escape(&mut self) -> Result<char, AppError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+}
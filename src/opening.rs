@@ -0,0 +1,52 @@
+use csv::{ReaderBuilder, Trim};
+use std::io::Read;
+
+use crate::{Amount, AppError, PaymentEngine, ResultExt, TxStore};
+
+impl<S: TxStore> PaymentEngine<S> {
+    /// Opens every client of a `client,available` csv, see `open_account`, returning how many.
+    ///
+    /// Columns are matched by name, and amounts parsed as the ones of transactions. Errors carry
+    /// their line, e.g. for a client listed twice.
+    pub fn load_opening_balances<R: Read>(&mut self, reader: R) -> Result<usize, AppError> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    AppError::InvalidFormat(format!("opening balances: missing {} column", name))
+                })
+        };
+        let (client, available) = (column("client")?, column("available")?);
+
+        let mut opened = 0;
+        for (i, record) in reader.records().enumerate() {
+            // the header is line 1
+            let line = record
+                .as_ref()
+                .ok()
+                .and_then(|record| record.position())
+                .map_or(i + 2, |position| position.line() as usize);
+            let record = record.at_line(line)?;
+            let field = |index: usize| {
+                record.get(index).ok_or_else(|| {
+                    AppError::InvalidRecord(format!("missing field {} in {:?}", index, record))
+                })
+            };
+            let parse = || -> Result<(u16, Amount<S::Ticks>), AppError> {
+                let raw = field(available)?;
+                let amount: Amount = raw.parse()?;
+                let amount = Amount::narrow(amount.widen()).ok_or_else(|| {
+                    AppError::InvalidRecord(format!("opening balance {} is out of range", raw))
+                })?;
+                Ok((field(client)?.parse()?, amount))
+            };
+            let (client_id, amount) = parse().at_line(line)?;
+            self.open_account(client_id, amount).at_line(line)?;
+            opened += 1;
+        }
+        Ok(opened)
+    }
+}
@@ -0,0 +1,881 @@
+use csv::{Reader, StringRecord};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write, stderr};
+use std::iter;
+use std::mem;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::AtomicBool;
+
+use crate::{
+    Amount, AppError, BatchMarker, BufferInfo, ClientBalance, ColumnMap, Config, InputFormat,
+    Metrics, ParseOptions, PaymentEngine, Phase, Profile, ResultExt, TransactionInput,
+    TransactionType, TxOutcome, audit_control_flow, is_set, parse_currency, parse_ndjson_line,
+    parse_timestamp, self_check_csv, validate_buff, write_balances_with, write_summary,
+};
+
+/// Options controlling how records are streamed into the engine
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// when set, invalid records are skipped instead of aborting, until this many accumulate
+    pub max_errors: Option<usize>,
+    pub parse: ParseOptions,
+    /// applies records in timestamp order rather than input order, see `sort_by_timestamp`
+    pub sort_by_timestamp: bool,
+    /// Holds back up to this many disputes, resolves and chargebacks of tx ids not applied yet,
+    /// replaying them right after their transaction, see `HeldDisputes`.
+    ///
+    /// Held rows count as settled in `Progress::cursor`, so resuming from a snapshot taken while
+    /// some are held drops them.
+    pub buffer_disputes: Option<usize>,
+    /// Only applies the inputs filed under these clients, dropping the others once parsed, e.g.
+    /// to debug a single client. Disputes are kept by the client they're filed under, so one of
+    /// a dropped client's tx is ignored, its transaction missing.
+    pub clients: Option<HashSet<u16>>,
+    /// Only applies the inputs of the types it admits, skipping the others once parsed rather
+    /// than failing, e.g. to ignore chargebacks during a migration. Skipped inputs are counted
+    /// in `Progress::filtered`.
+    pub types: Option<TypeFilter>,
+    /// Stops reading records once set, e.g. by a Ctrl-C handler of the caller, as if the input
+    /// ended there, so the balances computed so far can still be written, see
+    /// `Progress::interrupted`. `run` also checks it on every read of the input, see
+    /// `BufferInfo::with_interrupt`.
+    pub interrupt: Option<&'static AtomicBool>,
+}
+
+/// How far processing went, kept up to date even when it stops on a failure
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// number of records whose effects are settled in the engine, see `process_records_from`
+    pub cursor: usize,
+    /// skipped invalid records, with their line, each annotated as `AppError::AtLine`
+    pub errors: Vec<(usize, AppError)>,
+    pub metrics: Metrics,
+    /// only measured when present, see `Config::profile`
+    pub profile: Option<Profile>,
+    /// whether processing stopped early on `ProcessOptions::interrupt`
+    pub interrupted: bool,
+    /// inputs skipped for their type, see `ProcessOptions::types`
+    pub filtered: usize,
+}
+
+/// Transaction types `ProcessOptions::types` applies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeFilter {
+    /// only these types
+    Allow(HashSet<TransactionType>),
+    /// every type but these
+    Deny(HashSet<TransactionType>),
+}
+
+impl TypeFilter {
+    pub fn admits(&self, tx_type: TransactionType) -> bool {
+        match self {
+            TypeFilter::Allow(types) => types.contains(&tx_type),
+            TypeFilter::Deny(types) => !types.contains(&tx_type),
+        }
+    }
+}
+
+/// Runs the whole pipeline described by `config`, writing balances into `writer`
+pub fn run<W: Write>(config: &Config, writer: &mut W) -> Result<(), AppError> {
+    run_with_diagnostics(config, writer, &mut stderr().lock())
+}
+
+/// Same as `run`, writing every diagnostic (warnings, skipped errors, metrics, audit logs) into
+/// `diagnostics` instead of stderr, or nowhere when `config.quiet` is set.
+pub fn run_with_diagnostics<W: Write, D: Write>(
+    config: &Config,
+    writer: &mut W,
+    diagnostics: &mut D,
+) -> Result<(), AppError> {
+    let mut sink = io::sink();
+    let diagnostics: &mut dyn Write = if config.quiet { &mut sink } else { diagnostics };
+    let buffer = open_input(&config.input_path, &config.processing.parse)?
+        .with_interrupt(config.processing.interrupt);
+    if config.require_header && !buffer.has_headers {
+        return Err(AppError::InvalidFormat("missing header".to_string()));
+    }
+    if config.check {
+        check_records(
+            &config.input_path,
+            config.input_format,
+            &config.processing.parse,
+        )?;
+        return Ok(());
+    }
+
+    if config.audit_control_flow {
+        // pre-pass over a fresh handle, leaving `reader` untouched for processing
+        let mut scan = open_input(&config.input_path, &config.processing.parse)?.into_csv_reader();
+        for finding in audit_control_flow(&mut scan)? {
+            writeln!(diagnostics, "{}", finding)?;
+        }
+    }
+
+    if config.echo_normalized {
+        echo_normalized(
+            &config.input_path,
+            config.input_format,
+            &config.processing.parse,
+            diagnostics,
+        )?;
+    }
+
+    let (mut engine, cursor) = match &config.resume {
+        Some(path) => PaymentEngine::load_snapshot(
+            File::open(path).map_err(|_| AppError::FileNotFound(path.to_string()))?,
+        )?,
+        None => (PaymentEngine::new(), 0),
+    };
+    let mut processing = config.processing.clone();
+    restore_currency_precision(&mut processing.parse.currency_precision, &engine)?;
+    engine = engine.with_rules(config.rules);
+    if config.strict {
+        engine = engine.with_strict();
+    }
+    if config.dedupe_global {
+        engine = engine.with_global_dedupe();
+    }
+    if config.audit {
+        engine = engine.with_audit();
+    }
+    if config.report_rejected_withdrawals {
+        engine = engine.with_withdrawal_report();
+    }
+    if let Some(path) = &config.opening_balances {
+        engine.load_opening_balances(
+            File::open(path).map_err(|_| AppError::FileNotFound(path.to_string()))?,
+        )?;
+    }
+    let mut progress = Progress {
+        cursor,
+        profile: config.profile.then(Profile::default),
+        ..Default::default()
+    };
+    let result = match config.input_format {
+        InputFormat::Csv => {
+            let mut reader = buffer.into_csv_reader();
+            process_records_from(&mut reader, &mut engine, &processing, &mut progress)
+        }
+        InputFormat::Ndjson => process_ndjson_from(
+            BufReader::new(buffer.file),
+            &mut engine,
+            &processing,
+            &mut progress,
+        ),
+    };
+    // snapshot even on failure, so a fixed input can be resumed from where it stopped
+    if let Some(path) = &config.snapshot {
+        engine.save_snapshot(File::create(path)?, progress.cursor)?;
+    }
+    for (_, err) in &progress.errors {
+        writeln!(diagnostics, "{}", err)?;
+    }
+    result?;
+    if config.verify_invariants {
+        engine.verify_invariants()?;
+    }
+
+    if config.summary_only {
+        let mut summary = engine.summary();
+        if config.processing.types.is_some() {
+            summary.filtered = Some(progress.filtered);
+        }
+        write_summary(writer, summary, config.output_format, config.units)?;
+    } else {
+        write_client_balances(&engine, config, writer)?;
+    }
+
+    for mismatch in engine.client_mismatches() {
+        writeln!(diagnostics, "{}", mismatch)?;
+    }
+    for warning in engine.warnings() {
+        writeln!(diagnostics, "{}", warning)?;
+    }
+    for rejected in engine.rejected_withdrawals() {
+        writeln!(diagnostics, "{}", rejected)?;
+    }
+
+    if config.metrics {
+        write!(diagnostics, "{}", progress.metrics)?;
+    }
+    if let Some(profile) = &progress.profile {
+        write!(diagnostics, "{}", profile)?;
+    }
+
+    if config.audit {
+        for user in engine.users() {
+            if let (Some(first), Some(last)) = (user.first_seen_line(), user.last_seen_line()) {
+                writeln!(
+                    diagnostics,
+                    "client {}, first seen on line {}, last seen on line {}",
+                    user.id, first, last
+                )?;
+            }
+            for entry in user.audit_log() {
+                writeln!(diagnostics, "client {}, {}", user.id, entry)?;
+            }
+        }
+    }
+
+    if progress.interrupted {
+        return Err(AppError::Interrupted(progress.cursor));
+    }
+    if config.continue_on_error && !progress.errors.is_empty() {
+        return Err(AppError::SkippedRecords(progress.errors.len()));
+    }
+    Ok(())
+}
+
+/// Adds the currencies of the clients restored from a snapshot to `precision`, so a run resumed
+/// without `--currency-precision` still parses and renders them.
+///
+/// Errors when `--currency-precision` gives one of them other decimals than the snapshot.
+fn restore_currency_precision(
+    precision: &mut HashMap<String, u32>,
+    engine: &PaymentEngine,
+) -> Result<(), AppError> {
+    for currency in engine.users().filter_map(|user| user.currency()) {
+        let decimals = *precision
+            .entry(currency.code.clone())
+            .or_insert(currency.decimals);
+        if decimals != currency.decimals {
+            return Err(AppError::InvalidArgument(format!(
+                "--currency-precision gives {} {} decimals, the resumed snapshot {}",
+                currency.code, decimals, currency.decimals
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// balances of every client `config` selects, see `Config::locked_only`, along with zeroed ones
+/// for the clients of `Config::client_range` without any
+fn write_client_balances<W: Write>(
+    engine: &PaymentEngine,
+    config: &Config,
+    writer: &mut W,
+) -> Result<(), AppError> {
+    let mut balances: Vec<ClientBalance> = engine
+        .users()
+        .map(|user| {
+            let mut balance = user.balance();
+            balance.currency = user.currency().cloned();
+            if config.held_tx_ids {
+                balance.held_tx_ids = Some(user.held_transaction_ids());
+            }
+            if config.open_disputes {
+                balance.open_disputes = Some(user.open_disputes());
+            }
+            balance
+        })
+        .collect();
+    if let Some(range) = config.client_range.clone() {
+        balances.extend(
+            range
+                .filter(|client| engine.get(*client).is_none())
+                .map(|client| ClientBalance {
+                    client,
+                    available: 0,
+                    held: 0,
+                    total: 0,
+                    locked: false,
+                    held_tx_ids: config.held_tx_ids.then(Vec::new),
+                    open_disputes: config.open_disputes.then_some(0),
+                    currency: None,
+                }),
+        );
+        balances.sort_by_key(|balance| balance.client);
+    }
+    let balances = balances
+        .into_iter()
+        .filter(|balance| !config.locked_only || balance.locked);
+    if config.self_check {
+        let mut output = Vec::new();
+        write_balances_with(&mut output, balances, config.output_format, config.units)?;
+        self_check_csv(&output)?;
+        writer.write_all(&output)?;
+        return Ok(());
+    }
+    write_balances_with(writer, balances, config.output_format, config.units)
+}
+
+/// Same as `run`, writing into `path` instead.
+///
+/// Output goes to a temporary file next to `path`, only renamed into place on success, so readers
+/// never see a partial file and a previous output survives a failed run.
+pub fn run_to_file(config: &Config, path: &str) -> Result<(), AppError> {
+    let path = Path::new(path);
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let write = || -> Result<(), AppError> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        let result = run(config, &mut writer);
+        // skipped records and interruptions still leave valid output behind
+        if result.as_ref().is_err_and(|err| {
+            !matches!(err, AppError::SkippedRecords(_) | AppError::Interrupted(_))
+        }) {
+            return result;
+        }
+        // surfaces write errors, which dropping the writer would swallow
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&temp_path, path)?;
+        result
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Streams every record of `reader` into `engine`.
+///
+/// Inputs between `begin` and `commit` markers are buffered and only applied on commit, all or
+/// nothing, so a `rollback` (or a batch left open at the end of the input) simply discards them.
+pub fn process_records<R: Read>(
+    reader: &mut Reader<R>,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+) -> Result<Progress, AppError> {
+    let mut progress = Progress::default();
+    process_records_from(reader, engine, options, &mut progress)?;
+    Ok(progress)
+}
+
+impl PaymentEngine {
+    /// Runs the csv pipeline over an in-memory `body`, e.g. in tests or when embedding, without
+    /// touching the filesystem.
+    ///
+    /// The header and delimiter are sniffed from its first line, as `validate_buff` does for
+    /// files, and records are applied under the default `ProcessOptions`.
+    pub fn from_csv_str(body: &str) -> Result<Self, AppError> {
+        let buffer = BufferInfo::from_reader(Cursor::new(body.as_bytes().to_vec()))?;
+        let mut engine = Self::new();
+        process_records(
+            &mut buffer.into_csv_reader(),
+            &mut engine,
+            &ProcessOptions::default(),
+        )?;
+        Ok(engine)
+    }
+}
+
+/// Same as `process_records`, skipping the first `progress.cursor` records, e.g. when resuming
+/// from a snapshot.
+///
+/// The cursor is advanced past every record whose effects are settled in `engine`, which excludes
+/// records of a batch still open, so it's always safe to resume from on failure.
+pub fn process_records_from<R: Read>(
+    reader: &mut Reader<R>,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    let records = match csv_records(reader) {
+        // the header is read first, so an interrupted read may fail it already
+        Err(_) if is_set(options.interrupt) => {
+            progress.interrupted = true;
+            return Ok(());
+        }
+        records => records?,
+    };
+    process_stream(records, engine, options, progress)
+}
+
+/// `(line, record)` pairs of `reader`, in positional order
+fn csv_records<R: Read>(
+    reader: &mut Reader<R>,
+) -> Result<impl Iterator<Item = (usize, Result<StringRecord, AppError>)> + '_, AppError> {
+    let columns = columns_of(reader)?;
+    // according to GPT:
+    // records() returns a StringRecordsIter<'a, R> — where R: io::Read.
+    // That iterator wraps your reader’s R (in your case, a File), and calls .fill_buf() on it when needed.
+    // in short: It pulls bytes incrementally from the file handle using buffered I/O.
+    Ok(locate_records(reader.records(), columns))
+}
+
+/// pairs every record with its line, reordering it into positional order per `columns`
+fn locate_records(
+    records: impl Iterator<Item = Result<StringRecord, csv::Error>>,
+    columns: ColumnMap,
+) -> impl Iterator<Item = (usize, Result<StringRecord, AppError>)> {
+    let reorder = !columns.is_positional();
+    records.enumerate().map(move |(i, result)| match result {
+        Ok(record) => {
+            let line = record
+                .position()
+                .map_or(i + 1, |position| position.line() as usize);
+            let record = columns.check_width(&record).map(|_| match reorder {
+                true => columns.reorder(&record),
+                false => record,
+            });
+            (line, record)
+        }
+        Err(e) => {
+            let line = e
+                .position()
+                .map_or(i + 1, |position| position.line() as usize);
+            (line, Err(AppError::from(e)))
+        }
+    })
+}
+
+/// Lazily parses every csv record of `reader`, without applying it, e.g. to pre-filter inputs by
+/// client. Errors carry the line of their record.
+///
+/// The header and delimiter are sniffed as in `validate_buff`. Batch markers aren't
+/// transactions, so they're skipped, which `process_records` reads from the same record stream
+/// to apply batches atomically.
+pub fn parse_records<R: Read + 'static>(
+    reader: R,
+) -> impl Iterator<Item = Result<TransactionInput, AppError>> {
+    parse_records_with(reader, ParseOptions::default())
+}
+
+/// same as `parse_records`, parsing records with `options`
+pub fn parse_records_with<R: Read + 'static>(
+    reader: R,
+    options: ParseOptions,
+) -> impl Iterator<Item = Result<TransactionInput, AppError>> {
+    let records: Box<dyn Iterator<Item = (usize, Result<StringRecord, AppError>)>> =
+        match BufferInfo::from_reader(reader).and_then(|buffer| {
+            let buffer = buffer.with_max_line_length(options.max_line_length);
+            let mut reader = buffer.into_csv_reader();
+            let columns = columns_of(&mut reader)?;
+            Ok(locate_records(reader.into_records(), columns))
+        }) {
+            Ok(records) => Box::new(records),
+            Err(err) => Box::new(iter::once((1, Err(err)))),
+        };
+    records.filter_map(move |(line, record)| match record {
+        Ok(record) if BatchMarker::from_record(&record).is_some() => None,
+        record => Some(
+            record
+                .and_then(|record| TransactionInput::try_from_string_record_with(record, &options))
+                .at_line(line),
+        ),
+    })
+}
+
+/// Columns of `reader` when its header names them out of positional order, `None` when records
+/// can be used as they are.
+pub fn column_map<R: Read>(reader: &mut Reader<R>) -> Result<Option<ColumnMap>, AppError> {
+    let columns = columns_of(reader)?;
+    Ok((!columns.is_positional()).then_some(columns))
+}
+
+/// columns named by the header of `reader`, positional ones when it has none
+fn columns_of<R: Read>(reader: &mut Reader<R>) -> Result<ColumnMap, AppError> {
+    if !reader.has_headers() {
+        return Ok(ColumnMap::default());
+    }
+    Ok(ColumnMap::from_headers(reader.headers()?).unwrap_or_default())
+}
+
+/// Same as `process_records_from`, reading one json object per line instead, see
+/// `parse_ndjson_line`. Blank lines are skipped and don't count as records.
+pub fn process_ndjson_from<R: BufRead>(
+    reader: R,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    let records = ndjson_records(reader);
+    process_stream(records, engine, options, progress)
+}
+
+/// `(line, record)` pairs of `reader`, skipping blank lines
+fn ndjson_records<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = (usize, Result<StringRecord, AppError>)> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(i, result)| match result {
+            Ok(text) if text.trim().is_empty() => None,
+            Ok(text) => Some((i + 1, parse_ndjson_line(&text))),
+            Err(e) => Some((i + 1, Err(AppError::from(e)))),
+        })
+}
+
+/// Dry run parsing every record of `input_path` without applying it, returning how many records
+/// were checked, or the first error along with its line.
+pub fn check_records(
+    input_path: &str,
+    input_format: InputFormat,
+    options: &ParseOptions,
+) -> Result<usize, AppError> {
+    let mut checked = 0;
+    for_each_record(input_path, input_format, options, |line, record| {
+        let record = record.at_line(line)?;
+        if BatchMarker::from_record(&record).is_none() {
+            TransactionInput::try_from_string_record_with(record, options).at_line(line)?;
+        }
+        checked += 1;
+        Ok(())
+    })?;
+    Ok(checked)
+}
+
+/// Writes how every record of `input_path` is interpreted as a canonical csv row, see
+/// `TransactionInput::to_csv_row`, surfacing normalization such as case folding or truncated
+/// decimals.
+///
+/// Rows that can't be parsed are skipped, as reporting them is left to processing itself.
+pub fn echo_normalized<W: Write + ?Sized>(
+    input_path: &str,
+    input_format: InputFormat,
+    options: &ParseOptions,
+    writer: &mut W,
+) -> Result<(), AppError> {
+    for_each_record(input_path, input_format, options, |_, record| {
+        let Ok(record) = record else {
+            return Ok(());
+        };
+        if let Some(marker) = BatchMarker::from_record(&record) {
+            writeln!(writer, "{},,,", format!("{:?}", marker).to_lowercase())?;
+        } else if let Ok(tx) = TransactionInput::try_from_string_record_with(record, options) {
+            writeln!(writer, "{}", tx.to_csv_row())?;
+        }
+        Ok(())
+    })
+}
+
+/// opens `input_path` on its own handle and feeds `f` with its `(line, record)` pairs
+fn for_each_record(
+    input_path: &str,
+    input_format: InputFormat,
+    options: &ParseOptions,
+    mut f: impl FnMut(usize, Result<StringRecord, AppError>) -> Result<(), AppError>,
+) -> Result<(), AppError> {
+    let buffer = open_input(input_path, options)?;
+    match input_format {
+        InputFormat::Csv => {
+            let mut reader = buffer.into_csv_reader();
+            csv_records(&mut reader)?.try_for_each(|(line, record)| f(line, record))
+        }
+        InputFormat::Ndjson => ndjson_records(BufReader::new(buffer.file))
+            .try_for_each(|(line, record)| f(line, record)),
+    }
+}
+
+/// `validate_buff`, guarded by `options.max_line_length`
+fn open_input(input_path: &str, options: &ParseOptions) -> Result<BufferInfo, AppError> {
+    Ok(validate_buff(input_path)?.with_max_line_length(options.max_line_length))
+}
+
+/// shared by every input format, over `(line, record)` pairs
+fn process_stream(
+    records: impl Iterator<Item = (usize, Result<StringRecord, AppError>)>,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    if options.sort_by_timestamp {
+        let records = Profile::time(progress.profile.as_mut(), Phase::Reading, || {
+            sort_by_timestamp(records)
+        });
+        return apply_records(records.into_iter(), engine, options, progress);
+    }
+    apply_records(records, engine, options, progress)
+}
+
+/// Buffers every record and stably sorts them by their timestamp field, see `parse_timestamp`.
+///
+/// A record without a timestamp takes the one of the record before it, so it stays right after
+/// it, and records before the first timestamp stay first. A record with an invalid timestamp is
+/// replaced by its error, keeping its position.
+fn sort_by_timestamp(
+    records: impl Iterator<Item = (usize, Result<StringRecord, AppError>)>,
+) -> Vec<(usize, Result<StringRecord, AppError>)> {
+    let mut last = i64::MIN;
+    let mut keyed: Vec<_> = records
+        .map(|(line, result)| {
+            let timestamp = result.as_ref().ok().and_then(|record| {
+                record
+                    .get(ColumnMap::TIMESTAMP)
+                    .map(str::trim)
+                    .filter(|raw| !raw.is_empty())
+                    .map(parse_timestamp)
+            });
+            match timestamp {
+                Some(Ok(timestamp)) => last = timestamp,
+                Some(Err(err)) => return (last, (line, Err(err))),
+                None => {}
+            }
+            (last, (line, result))
+        })
+        .collect();
+    keyed.sort_by_key(|(timestamp, _)| *timestamp);
+    keyed.into_iter().map(|(_, record)| record).collect()
+}
+
+fn apply_records(
+    records: impl Iterator<Item = (usize, Result<StringRecord, AppError>)>,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    let mut batch: Option<Vec<(TransactionInput, usize, Option<Amount>)>> = None;
+    let mut held = options.buffer_disputes.map(HeldDisputes::new);
+
+    let mut records = records.enumerate().skip(progress.cursor);
+    loop {
+        // an open batch is discarded, as at the end of the input
+        if is_set(options.interrupt) {
+            progress.interrupted = true;
+            break;
+        }
+        let Some((i, (line, result))) =
+            Profile::time(progress.profile.as_mut(), Phase::Reading, || records.next())
+        else {
+            break;
+        };
+        let result = match result {
+            // reads fail once interrupted, see `BufferInfo::with_interrupt`, and the record they
+            // cut short is dropped along with the rest
+            Err(_) if is_set(options.interrupt) => {
+                progress.interrupted = true;
+                break;
+            }
+            // the input itself can't be read past it, e.g. a line over
+            // `ParseOptions::max_line_length`, so it aborts even when skipping invalid records
+            Err(err @ AppError::InvalidFormat(_)) => return Err(err.with_line(line)),
+            result => result,
+        };
+        let result = result
+            .and_then(|record| {
+                process_record(
+                    record,
+                    line,
+                    engine,
+                    options,
+                    &mut batch,
+                    held.as_mut(),
+                    progress,
+                )
+            })
+            .at_line(line);
+        if let Err(err) = result {
+            skip_or_abort(options, progress, line, err)?;
+        }
+        if let Some(held) = held.as_mut() {
+            apply_held(held.release(), engine, options, progress)?;
+        }
+        if batch.is_none() {
+            progress.cursor = i + 1;
+        }
+    }
+    // rows whose transaction never showed up, ignored as such
+    if let Some(held) = held.as_mut() {
+        apply_held(held.flush(), engine, options, progress)?;
+    }
+
+    Ok(())
+}
+
+/// Disputes, resolves and chargebacks held back until the transaction they reference is applied,
+/// see `ProcessOptions::buffer_disputes`.
+///
+/// Only rows outside of batches are held. Once more than `capacity` are, the oldest ones are
+/// applied as they are, as are the ones left at the end of the input.
+struct HeldDisputes {
+    capacity: usize,
+    rows: VecDeque<(TransactionInput, usize, Option<Amount>)>,
+    /// number of held rows per tx id
+    ids: HashMap<u32, usize>,
+    /// held tx ids whose transaction was just applied
+    ready: Vec<u32>,
+}
+
+impl HeldDisputes {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            rows: VecDeque::new(),
+            ids: HashMap::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Whether `tx` references a transaction `engine` hasn't applied yet. Rows of a tx id
+    /// already held are held too, keeping them in order.
+    fn holds(&self, tx: &TransactionInput, engine: &PaymentEngine) -> bool {
+        !tx.tx_type().is_monetary()
+            && (engine.owner_of(tx.id()).is_none() || self.ids.contains_key(&tx.id()))
+    }
+
+    fn hold(&mut self, row: (TransactionInput, usize, Option<Amount>)) {
+        *self.ids.entry(row.0.id()).or_default() += 1;
+        self.rows.push_back(row);
+    }
+
+    /// marks the rows held for `tx_id` as ready to be released, its transaction being applied
+    fn applied(&mut self, tx_id: u32) {
+        if self.ids.contains_key(&tx_id) {
+            self.ready.push(tx_id);
+        }
+    }
+
+    /// rows to apply now, in input order: the ready ones, and the oldest ones past `capacity`
+    fn release(&mut self) -> Vec<(TransactionInput, usize, Option<Amount>)> {
+        let mut released = Vec::new();
+        if !self.ready.is_empty() {
+            let ready = mem::take(&mut self.ready);
+            self.rows.retain(|row| {
+                let now = ready.contains(&row.0.id());
+                if now {
+                    released.push(*row);
+                }
+                !now
+            });
+        }
+        while self.rows.len() > self.capacity {
+            released.extend(self.rows.pop_front());
+        }
+        released.sort_by_key(|(_, line, _)| *line);
+        self.forget(&released);
+        released
+    }
+
+    /// every held row, in input order
+    fn flush(&mut self) -> Vec<(TransactionInput, usize, Option<Amount>)> {
+        let released: Vec<_> = self.rows.drain(..).collect();
+        self.forget(&released);
+        released
+    }
+
+    fn forget(&mut self, released: &[(TransactionInput, usize, Option<Amount>)]) {
+        for (tx, _, _) in released {
+            if let Some(count) = self.ids.get_mut(&tx.id()) {
+                *count -= 1;
+                if *count == 0 {
+                    self.ids.remove(&tx.id());
+                }
+            }
+        }
+    }
+}
+
+/// applies rows released by `HeldDisputes`, reporting errors at their own line
+fn apply_held(
+    rows: Vec<(TransactionInput, usize, Option<Amount>)>,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    for (tx, line, claimed) in rows {
+        let result = Profile::time(progress.profile.as_mut(), Phase::Applying, || {
+            engine.apply_claimed_at_line(tx, line, claimed)
+        });
+        match result.at_line(line) {
+            Ok(outcome) => progress.metrics.record_outcome(tx.tx_type(), outcome),
+            Err(err) => skip_or_abort(options, progress, line, err)?,
+        }
+    }
+    Ok(())
+}
+
+/// parses and applies a single record, or buffers it while a batch is open
+fn process_record(
+    record: StringRecord,
+    line: usize,
+    engine: &mut PaymentEngine,
+    options: &ProcessOptions,
+    batch: &mut Option<Vec<(TransactionInput, usize, Option<Amount>)>>,
+    mut held: Option<&mut HeldDisputes>,
+    progress: &mut Progress,
+) -> Result<(), AppError> {
+    let (metrics, profile) = (&mut progress.metrics, &mut progress.profile);
+    if let Some(marker) = BatchMarker::from_record(&record) {
+        return match (marker, batch.take()) {
+            (BatchMarker::Begin, None) => {
+                *batch = Some(Vec::new());
+                Ok(())
+            }
+            (BatchMarker::Commit, Some(inputs)) => {
+                let txs: Vec<_> = inputs.iter().map(|(tx, _, _)| *tx).collect();
+                let outcomes = Profile::time(profile.as_mut(), Phase::Applying, || {
+                    engine.apply_batch_at_lines(inputs)
+                })?;
+                for (tx, outcome) in txs.into_iter().zip(outcomes) {
+                    metrics.record_outcome(tx.tx_type(), outcome);
+                    if let Some(held) = held.as_mut().filter(|_| outcome == TxOutcome::Applied) {
+                        held.applied(tx.id());
+                    }
+                }
+                Ok(())
+            }
+            (BatchMarker::Rollback, Some(_)) => Ok(()),
+            (marker, _) => Err(AppError::InvalidFormat(format!(
+                "unexpected {:?} marker",
+                marker
+            ))),
+        };
+    }
+    let (currency, (tx_input, claimed)) = Profile::time(profile.as_mut(), Phase::Parsing, || {
+        Ok::<_, AppError>((
+            parse_currency(&record, &options.parse)?,
+            TransactionInput::try_from_string_record_claimed(record, &options.parse)?,
+        ))
+    })?;
+    if let Some(clients) = &options.clients
+        && !clients.contains(&tx_input.client_id())
+    {
+        return Ok(());
+    }
+    if let Some(types) = &options.types
+        && !types.admits(tx_input.tx_type())
+    {
+        progress.filtered += 1;
+        return Ok(());
+    }
+    // tagged as parsed, so a row mixing currencies fails even within a batch
+    if let Some(currency) = currency.filter(|_| tx_input.funds().is_some()) {
+        engine.tag_currency(tx_input.client_id(), &currency)?;
+    }
+    metrics.record_seen(tx_input.tx_type());
+    match batch.as_mut() {
+        Some(inputs) => inputs.push((tx_input, line, claimed)),
+        None => {
+            if let Some(held) = held.as_mut().filter(|held| held.holds(&tx_input, engine)) {
+                held.hold((tx_input, line, claimed));
+                return Ok(());
+            }
+            let outcome = Profile::time(profile.as_mut(), Phase::Applying, || {
+                engine.apply_claimed_at_line(tx_input, line, claimed)
+            })?;
+            metrics.record_outcome(tx_input.tx_type(), outcome);
+            if let Some(held) = held.filter(|_| outcome == TxOutcome::Applied) {
+                held.applied(tx_input.id());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// records `err`, already annotated with its `line`, as skipped when allowed by
+/// `options.max_errors`, otherwise returns it to abort
+fn skip_or_abort(
+    options: &ProcessOptions,
+    progress: &mut Progress,
+    line: usize,
+    err: AppError,
+) -> Result<(), AppError> {
+    let Some(max_errors) = options.max_errors else {
+        return Err(err);
+    };
+    progress.errors.push((line, err));
+    if progress.errors.len() >= max_errors {
+        return Err(AppError::TooManyErrors(max_errors));
+    }
+    Ok(())
+}
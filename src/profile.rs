@@ -0,0 +1,66 @@
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::time::{Duration, Instant};
+
+/// Phases the time of a run splits into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// pulling records out of the input, including sorting them by timestamp
+    Reading,
+    /// turning records into `TransactionInput`s
+    Parsing,
+    /// applying inputs to the engine
+    Applying,
+}
+
+/// Time spent in each `Phase` of a run. Only measured when a run carries one, so runs without it
+/// never read the clock.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    reading: Duration,
+    parsing: Duration,
+    applying: Duration,
+}
+
+impl Profile {
+    /// Runs `f`, adding how long it took to `phase` of `profile`, if any
+    pub fn time<T>(profile: Option<&mut Self>, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let Some(profile) = profile else {
+            return f();
+        };
+        let started = Instant::now();
+        let result = f();
+        *profile.phase_mut(phase) += started.elapsed();
+        result
+    }
+
+    pub fn elapsed(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Reading => self.reading,
+            Phase::Parsing => self.parsing,
+            Phase::Applying => self.applying,
+        }
+    }
+
+    fn phase_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Reading => &mut self.reading,
+            Phase::Parsing => &mut self.parsing,
+            Phase::Applying => &mut self.applying,
+        }
+    }
+}
+
+impl Display for Profile {
+    /// one `profile <phase> <elapsed>` line per phase, in pipeline order
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatResult {
+        for phase in [Phase::Reading, Phase::Parsing, Phase::Applying] {
+            writeln!(
+                f,
+                "profile {} {:.3?}",
+                format!("{:?}", phase).to_lowercase(),
+                self.elapsed(phase)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::io::{Read, Write};
+
+use crate::{
+    Amount, AppError, Currency, PaymentEngine, Transaction, TransactionSide, TransactionStatus,
+    User,
+};
+
+const SNAPSHOT_VERSION: &str = "3";
+
+/// Snapshot rows, written as a flexible CSV:
+/// - `snapshot,<version>,<records>` header, `records` being the input records already applied
+/// - `user,<id>,<locked>,<opening>` for every client, `opening` being its opening balance in
+///   ticks, missing from version 1 snapshots, which are still read, followed by its currency
+///   code and decimals in multi-currency inputs, the latter missing from version 2 snapshots,
+///   which are then rejected as `--resume` has no other way to render that currency
+/// - `tx,<id>,<client_id>,<side>,<status>,<amount>` for every transaction, following its user
+impl PaymentEngine {
+    pub fn save_snapshot<W: Write>(&self, writer: W, records: usize) -> Result<(), AppError> {
+        let mut writer = WriterBuilder::new().flexible(true).from_writer(writer);
+        writer.write_record(["snapshot", SNAPSHOT_VERSION, &records.to_string()])?;
+        for user in self.users() {
+            let mut row = StringRecord::from(vec![
+                "user".to_string(),
+                user.id.to_string(),
+                user.locked.to_string(),
+                user.opening_balance().to_ticks().to_string(),
+            ]);
+            if let Some(currency) = user.currency() {
+                row.push_field(&currency.code);
+                row.push_field(&currency.decimals.to_string());
+            }
+            writer.write_record(&row)?;
+            for tx in user.transactions_sorted() {
+                writer.write_record([
+                    "tx",
+                    &tx.id.to_string(),
+                    &tx.client_id.to_string(),
+                    side_to_str(tx.side),
+                    status_to_str(tx.status),
+                    &tx.amount.to_ticks().to_string(),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// restores an engine saved by `save_snapshot`, along with the number of records it had applied
+    pub fn load_snapshot<R: Read>(reader: R) -> Result<(Self, usize), AppError> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+        let mut rows = reader.records();
+        let header = rows
+            .next()
+            .ok_or_else(|| invalid_snapshot("empty snapshot"))??;
+        if field(&header, 0)? != "snapshot"
+            || !["1", "2", SNAPSHOT_VERSION].contains(&field(&header, 1)?)
+        {
+            return Err(invalid_snapshot("unsupported snapshot header"));
+        }
+        let records = field(&header, 2)?.parse::<usize>()?;
+
+        let mut engine = PaymentEngine::new();
+        let mut current: Option<User> = None;
+        for row in rows {
+            let row = row?;
+            match field(&row, 0)? {
+                "user" => {
+                    if let Some(mut user) = current.take() {
+                        user.sync_balances();
+                        engine.insert_user(user);
+                    }
+                    let mut user = User::new(field(&row, 1)?.parse()?);
+                    user.locked = parse_bool(field(&row, 2)?)?;
+                    if let Some(opening) = row.get(3) {
+                        user.credit_opening(Amount::from_ticks(opening.parse()?));
+                    }
+                    if let Some(code) = row.get(4) {
+                        let decimals = row.get(5).ok_or_else(|| {
+                            invalid_snapshot(&format!("currency {} lacks its decimals", code))
+                        })?;
+                        user.set_currency(Currency {
+                            code: code.to_string(),
+                            decimals: decimals.parse()?,
+                        });
+                    }
+                    current = Some(user);
+                }
+                "tx" => {
+                    let user = current
+                        .as_mut()
+                        .ok_or_else(|| invalid_snapshot("tx row before any user row"))?;
+                    let mut tx = Transaction::new(
+                        field(&row, 1)?.parse()?,
+                        field(&row, 2)?.parse()?,
+                        side_from_str(field(&row, 3)?)?,
+                        Amount::from_ticks(field(&row, 5)?.parse()?),
+                    );
+                    tx.status = status_from_str(field(&row, 4)?)?;
+                    user.transactions.insert(tx.id, tx);
+                }
+                other => return Err(invalid_snapshot(&format!("unknown row kind {}", other))),
+            }
+        }
+        if let Some(mut user) = current {
+            user.sync_balances();
+            engine.insert_user(user);
+        }
+        Ok((engine, records))
+    }
+}
+
+fn invalid_snapshot(reason: &str) -> AppError {
+    AppError::InvalidFormat(format!("snapshot: {}", reason))
+}
+
+fn field(row: &StringRecord, index: usize) -> Result<&str, AppError> {
+    row.get(index)
+        .ok_or_else(|| invalid_snapshot(&format!("missing field {} in {:?}", index, row)))
+}
+
+fn parse_bool(value: &str) -> Result<bool, AppError> {
+    value
+        .parse()
+        .map_err(|_| invalid_snapshot(&format!("invalid flag {}", value)))
+}
+
+fn side_to_str(side: TransactionSide) -> &'static str {
+    match side {
+        TransactionSide::Deposit => "deposit",
+        TransactionSide::Withdrawal => "withdrawal",
+    }
+}
+
+fn side_from_str(value: &str) -> Result<TransactionSide, AppError> {
+    match value {
+        "deposit" => Ok(TransactionSide::Deposit),
+        "withdrawal" => Ok(TransactionSide::Withdrawal),
+        _ => Err(invalid_snapshot(&format!("invalid side {}", value))),
+    }
+}
+
+fn status_to_str(status: TransactionStatus) -> &'static str {
+    match status {
+        TransactionStatus::Normal => "normal",
+        TransactionStatus::Disputed => "disputed",
+        TransactionStatus::Solved(false) => "resolved",
+        TransactionStatus::Solved(true) => "chargeback",
+        TransactionStatus::Authorized => "authorized",
+        TransactionStatus::Expired => "expired",
+    }
+}
+
+fn status_from_str(value: &str) -> Result<TransactionStatus, AppError> {
+    match value {
+        "normal" => Ok(TransactionStatus::Normal),
+        "disputed" => Ok(TransactionStatus::Disputed),
+        "resolved" => Ok(TransactionStatus::Solved(false)),
+        "chargeback" => Ok(TransactionStatus::Solved(true)),
+        "authorized" => Ok(TransactionStatus::Authorized),
+        "expired" => Ok(TransactionStatus::Expired),
+        _ => Err(invalid_snapshot(&format!("invalid status {}", value))),
+    }
+}
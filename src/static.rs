@@ -1 +1,6 @@
 pub const TICK_SIZE: f32 = 0.0001;
+/// decimal places represented by a tick
+pub const DECIMALS: u32 = 4;
+/// Integer width balances and transactions count ticks in by default, see `Ticks`, the one place
+/// to switch it, e.g. to `i128` for huge datasets
+pub type TickInt = i64;
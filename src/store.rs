@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::{Amount, AppError, TickInt, Ticks, Transaction, TransactionSide, TransactionStatus};
+
+/// Storage of the transactions of a single client, keyed by tx id.
+///
+/// `HashMap` is the default, in-memory one. Stores are infallible, so one losing its backing
+/// storage panics, the same way the in-memory one aborts once out of memory.
+pub trait TxStore {
+    /// integer the amounts of its transactions, and the balances of its client, are counted in
+    type Ticks: Ticks;
+
+    /// inserts `tx`, replacing any transaction with the same id
+    fn insert(&mut self, tx: Transaction<Self::Ticks>);
+
+    fn get(&self, id: u32) -> Option<Transaction<Self::Ticks>>;
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Transaction<Self::Ticks>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// every stored transaction, in no particular order
+    fn transactions(&self) -> Box<dyn Iterator<Item = Transaction<Self::Ticks>> + '_>;
+}
+
+impl<T: Ticks> TxStore for HashMap<u32, Transaction<T>> {
+    type Ticks = T;
+
+    fn insert(&mut self, tx: Transaction<T>) {
+        HashMap::insert(self, tx.id, tx);
+    }
+
+    fn get(&self, id: u32) -> Option<Transaction<T>> {
+        HashMap::get(self, &id).cloned()
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Transaction<T>> {
+        HashMap::get_mut(self, &id)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = Transaction<T>> + '_> {
+        Box::new(self.values().cloned())
+    }
+}
+
+/// size of a transaction on disk: id, client, side, status and amount
+const RECORD_LEN: usize = 8 + size_of::<TickInt>();
+
+/// Transactions kept in a file, shared by the stores of every client, with only their offsets
+/// kept in memory.
+///
+/// The transaction last handed out by `get_mut` is cached, and written back on the next access
+/// of another one. Not `Clone`, so engines using it can't apply atomic batches, which would need
+/// to snapshot the file.
+#[derive(Debug)]
+pub struct FileTxStore {
+    file: Rc<File>,
+    offsets: HashMap<u32, u64>,
+    cached: Option<Transaction>,
+}
+
+impl FileTxStore {
+    /// Creates, or truncates, the file at `path`, returning a factory of stores backed by it to
+    /// pass to `PaymentEngine::with_store`.
+    pub fn factory(path: &Path) -> Result<impl Fn(u16) -> FileTxStore + use<>, AppError> {
+        let file = Rc::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?,
+        );
+        Ok(move |_| FileTxStore {
+            file: Rc::clone(&file),
+            offsets: HashMap::new(),
+            cached: None,
+        })
+    }
+
+    fn read_at(&self, offset: u64) -> Transaction {
+        let mut record = [0u8; RECORD_LEN];
+        let mut file = &*self.file;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut record))
+            .expect("failed to read from the transaction store");
+        decode(&record)
+    }
+
+    fn write_at(&self, offset: SeekFrom, tx: &Transaction) -> u64 {
+        let mut file = &*self.file;
+        file.seek(offset)
+            .and_then(|offset| file.write_all(&encode(tx)).map(|_| offset))
+            .expect("failed to write to the transaction store")
+    }
+
+    /// writes the transaction cached by `get_mut` back, as it may have been modified
+    fn write_back(&mut self) {
+        if let Some(tx) = self.cached.take() {
+            self.write_at(SeekFrom::Start(self.offsets[&tx.id]), &tx);
+        }
+    }
+}
+
+impl TxStore for FileTxStore {
+    type Ticks = TickInt;
+
+    fn insert(&mut self, tx: Transaction) {
+        self.write_back();
+        let offset = match self.offsets.get(&tx.id) {
+            Some(offset) => SeekFrom::Start(*offset),
+            None => SeekFrom::End(0),
+        };
+        let offset = self.write_at(offset, &tx);
+        self.offsets.insert(tx.id, offset);
+    }
+
+    fn get(&self, id: u32) -> Option<Transaction> {
+        match &self.cached {
+            Some(tx) if tx.id == id => Some(tx.clone()),
+            _ => self.offsets.get(&id).map(|offset| self.read_at(*offset)),
+        }
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Transaction> {
+        if self.cached.as_ref().is_none_or(|tx| tx.id != id) {
+            let offset = *self.offsets.get(&id)?;
+            self.write_back();
+            self.cached = Some(self.read_at(offset));
+        }
+        self.cached.as_mut()
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = Transaction> + '_> {
+        Box::new(self.offsets.keys().filter_map(|id| self.get(*id)))
+    }
+}
+
+fn encode(tx: &Transaction) -> [u8; RECORD_LEN] {
+    let mut record = [0u8; RECORD_LEN];
+    record[0..4].copy_from_slice(&tx.id.to_le_bytes());
+    record[4..6].copy_from_slice(&tx.client_id.to_le_bytes());
+    record[6] = match tx.side {
+        TransactionSide::Deposit => 0,
+        TransactionSide::Withdrawal => 1,
+    };
+    record[7] = match tx.status {
+        TransactionStatus::Normal => 0,
+        TransactionStatus::Disputed => 1,
+        TransactionStatus::Solved(false) => 2,
+        TransactionStatus::Solved(true) => 3,
+        TransactionStatus::Authorized => 4,
+        TransactionStatus::Expired => 5,
+    };
+    record[8..].copy_from_slice(&tx.amount.to_ticks().to_le_bytes());
+    record
+}
+
+fn decode(record: &[u8; RECORD_LEN]) -> Transaction {
+    let (id, rest) = record.split_first_chunk().unwrap();
+    let (client_id, rest) = rest.split_first_chunk().unwrap();
+    let ([side, status], amount) = rest.split_first_chunk().unwrap();
+    let amount = amount.first_chunk().unwrap();
+    Transaction {
+        id: u32::from_le_bytes(*id),
+        client_id: u16::from_le_bytes(*client_id),
+        side: match side {
+            0 => TransactionSide::Deposit,
+            _ => TransactionSide::Withdrawal,
+        },
+        status: match status {
+            0 => TransactionStatus::Normal,
+            1 => TransactionStatus::Disputed,
+            2 => TransactionStatus::Solved(false),
+            3 => TransactionStatus::Solved(true),
+            4 => TransactionStatus::Authorized,
+            _ => TransactionStatus::Expired,
+        },
+        amount: Amount::from_ticks(TickInt::from_le_bytes(*amount)),
+    }
+}
@@ -0,0 +1,108 @@
+use crate::AppError;
+
+/// Parses a `timestamp` field into milliseconds since the unix epoch.
+///
+/// Accepts either epoch millis, e.g. `1700000000000`, or an RFC3339 date-time such as
+/// `2023-11-14T22:13:20Z` or `2023-11-14T22:13:20.250+01:00`. Fractional seconds past the
+/// millisecond are truncated.
+pub fn parse_timestamp(raw: &str) -> Result<i64, AppError> {
+    let invalid = || AppError::InvalidRecord(format!("invalid timestamp \"{}\"", raw));
+    if is_digits(raw.strip_prefix('-').unwrap_or(raw)) {
+        return raw.parse().map_err(|_| invalid());
+    }
+    parse_rfc3339(raw).ok_or_else(invalid)
+}
+
+fn is_digits(raw: &str) -> bool {
+    !raw.is_empty() && raw.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)`, `None` when malformed or out of range
+fn parse_rfc3339(raw: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = raw.get(range)?;
+        is_digits(digits).then(|| digits.parse().ok())?
+    };
+    let separator = |index: usize, allowed: &[u8]| {
+        raw.as_bytes()
+            .get(index)
+            .is_some_and(|byte| allowed.contains(byte))
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(separator(4, b"-")
+        && separator(7, b"-")
+        && separator(10, b"Tt ")
+        && separator(13, b":")
+        && separator(16, b":"))
+    {
+        return None;
+    }
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        // 60 is a leap second
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &raw[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        millis = format!("{:0<3}", &fraction[..digits.min(3)]).parse().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_minutes = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = &rest[1..];
+            if offset.len() != 5 || offset.as_bytes()[2] != b':' {
+                return None;
+            }
+            let (hours, minutes) = (offset.get(0..2)?, offset.get(3..5)?);
+            if !is_digits(hours) || !is_digits(minutes) {
+                return None;
+            }
+            let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 60 + minutes)
+        }
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second
+        - offset_minutes * 60;
+    Some(seconds * 1_000 + millis)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// days since 1970-01-01 of a proleptic gregorian date, after Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -1,11 +1,229 @@
 use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
     fs::File,
-    io::{BufRead, BufReader, Seek, SeekFrom},
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    iter,
+    num::{IntErrorKind, ParseIntError},
+    str::FromStr,
+    sync::atomic::AtomicBool,
 };
 
-use crate::AppError;
+use csv::ReaderBuilder;
 
-pub fn validate_buff(input_path: &str) -> Result<(bool, File), AppError> {
+use crate::{AppError, ColumnMap, DECIMALS, Interruptible, ParseOptions, TickInt};
+
+/// whether `first_line` is a header naming our expected columns, in any order and among others
+fn is_expected_header(first_line: &str, delimiter: u8) -> bool {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_reader(first_line.as_bytes());
+    matches!(
+        reader.records().next(),
+        Some(Ok(record)) if ColumnMap::from_headers(&record).is_some()
+    )
+}
+
+/// Whether `first_line` is a header, see `is_expected_header`, erroring with the corrected
+/// header when it's a misspelled one, see `header_suggestion`.
+fn detect_header(first_line: &str, delimiter: u8) -> Result<bool, AppError> {
+    if is_expected_header(first_line, delimiter) {
+        return Ok(true);
+    }
+    match header_suggestion(first_line, delimiter) {
+        Some(suggestion) => Err(AppError::InvalidFormat(format!(
+            "header \"{}\" looks misspelled, expected \"{}\"",
+            first_line.trim_end(),
+            suggestion
+        ))),
+        None => Ok(false),
+    }
+}
+
+/// Corrects a `first_line` that is almost our header, with every expected column present but
+/// some of them misspelled, such as `typ,client,tx,amount`.
+///
+/// Such a line would otherwise be read as a data row, failing on its first field. Data rows
+/// always carry numeric fields, so lines with any are never taken as headers.
+fn header_suggestion(first_line: &str, delimiter: u8) -> Option<String> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_reader(first_line.as_bytes());
+    let record = reader.records().next()?.ok()?;
+    let mut cells: Vec<String> = record
+        .iter()
+        .map(|cell| cell.trim().to_ascii_lowercase())
+        .collect();
+    if cells.iter().any(|cell| cell.parse::<f64>().is_ok()) {
+        return None;
+    }
+    let mut misspelled = false;
+    for name in ColumnMap::NAMES {
+        if cells.iter().any(|cell| cell == name) {
+            continue;
+        }
+        // within a third of the name's length, so `tx` tolerates a single typo
+        let cell = cells
+            .iter_mut()
+            .filter(|cell| !ColumnMap::NAMES.contains(&cell.as_str()))
+            .find(|cell| edit_distance(cell, name) <= (name.len() / 3).max(1))?;
+        *cell = name.to_string();
+        misspelled = true;
+    }
+    misspelled.then(|| cells.join(&char::from(delimiter).to_string()))
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// the most frequent of `DELIMITERS` in `first_line`, the earliest one on ties
+fn sniff_delimiter(first_line: &str) -> u8 {
+    let bytes = first_line.as_bytes();
+    let count = |delimiter: u8| {
+        (0..bytes.len())
+            .filter(|i| bytes[*i] == delimiter)
+            .filter(|i| delimiter != b'\t' || !is_padding(bytes, *i))
+            .count()
+    };
+    let mut sniffed = (DELIMITERS[0], count(DELIMITERS[0]));
+    for delimiter in &DELIMITERS[1..] {
+        let found = count(*delimiter);
+        if found > sniffed.1 {
+            sniffed = (*delimiter, found);
+        }
+    }
+    sniffed.0
+}
+
+/// Whether the tab at `index` pads a field rather than delimiting it, as it sits at either end of
+/// the line, or next to a space or another delimiter, as in `type,\tclient`.
+fn is_padding(bytes: &[u8], index: usize) -> bool {
+    let is_edge = |byte: Option<&u8>| {
+        byte.is_none_or(|byte| *byte == b' ' || (*byte != b'\t' && DELIMITERS.contains(byte)))
+    };
+    let before = index.checked_sub(1).map(|i| &bytes[i]);
+    is_edge(before) || is_edge(bytes.get(index + 1))
+}
+
+/// The first line of an input as the csv reader sees it, ending at the first `\r` or `\n`, as
+/// `read_line` only stops at the latter, reading a whole file with lone `\r` line endings.
+fn first_record(line: &str) -> &str {
+    line.split(['\r', '\n']).next().unwrap_or_default()
+}
+
+/// Delimiters recognized when sniffing an input, comma first as the default
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+const UTF8_BOM: &str = "\u{feff}";
+
+/// input path standing for stdin, see `validate_buff`
+pub const STDIN_PATH: &str = "-";
+
+/// Bytes of the first line read to sniff it, so an input without line breaks isn't read whole
+/// before `ParseOptions::max_line_length` can guard it. Longer lines are sniffed truncated,
+/// which is never taken for a header.
+const SNIFF_LIMIT: u64 = 64 * 1024;
+
+/// Reads the first line of `reader` for sniffing, up to `SNIFF_LIMIT` bytes, returning its raw
+/// bytes past any BOM, along with whether it had one.
+fn read_first_line<R: BufRead>(reader: &mut R) -> Result<(Vec<u8>, bool), AppError> {
+    let mut first_line = Vec::new();
+    reader
+        .take(SNIFF_LIMIT)
+        .read_until(b'\n', &mut first_line)?;
+    match first_line.strip_prefix(UTF8_BOM.as_bytes()) {
+        Some(rest) => Ok((rest.to_vec(), true)),
+        None => Ok((first_line, false)),
+    }
+}
+
+/// What `validate_buff` detected about an input, along with the stream to read it from
+pub struct BufferInfo {
+    /// whether the first line is our expected header
+    pub has_headers: bool,
+    /// field delimiter sniffed from the first line, see `DELIMITERS`
+    pub delimiter: u8,
+    /// stream positioned at the start of the first line, past any BOM
+    pub file: Box<dyn Read>,
+    /// whether the input started with a UTF-8 byte order mark, which is skipped
+    pub had_bom: bool,
+}
+
+impl BufferInfo {
+    /// Sniffs the first line of `reader`, see `validate_buff`.
+    ///
+    /// `reader` isn't seekable, so the sniffed first line is kept in memory and chained back in
+    /// front of the rest of it instead.
+    pub fn from_reader<R: Read + 'static>(reader: R) -> Result<Self, AppError> {
+        let mut reader = BufReader::new(reader);
+        let (first_line, had_bom) = read_first_line(&mut reader)?;
+        let sniffed = String::from_utf8_lossy(&first_line);
+        let delimiter = sniff_delimiter(first_record(&sniffed));
+        Ok(BufferInfo {
+            has_headers: detect_header(first_record(&sniffed), delimiter)?,
+            delimiter,
+            file: Box::new(Cursor::new(first_line).chain(reader)),
+            had_bom,
+        })
+    }
+
+    /// Fails reading `file` once a line grows past `max` bytes, if any, see
+    /// `ParseOptions::max_line_length`.
+    pub fn with_max_line_length(mut self, max: Option<usize>) -> Self {
+        if let Some(max) = max {
+            self.file = Box::new(LineLimit {
+                inner: self.file,
+                max,
+                current: 0,
+            });
+        }
+        self
+    }
+
+    /// Fails reading `file` once `flag` is set, if any, so processing stops there, see
+    /// `ProcessOptions::interrupt`.
+    pub fn with_interrupt(mut self, flag: Option<&'static AtomicBool>) -> Self {
+        if let Some(flag) = flag {
+            self.file = Box::new(Interruptible {
+                inner: self.file,
+                flag,
+            });
+        }
+        self
+    }
+
+    /// csv reader over `file`, honoring the detected header and delimiter
+    pub fn into_csv_reader(self) -> csv::Reader<Box<dyn Read>> {
+        ReaderBuilder::new()
+            .has_headers(self.has_headers)
+            .delimiter(self.delimiter)
+            // dispute, resolve and chargeback rows may leave out the amount column
+            .flexible(true)
+            // .buffer_capacity(64 * 1024) // for further on this, check validate_buff comments
+            .from_reader(self.file)
+    }
+}
+
+/// Opens `input_path`, or stdin for `-`, and sniffs its first line: whether it's our expected
+/// header, its delimiter, and a leading BOM.
+pub fn validate_buff(input_path: &str) -> Result<BufferInfo, AppError> {
+    if input_path == STDIN_PATH {
+        return BufferInfo::from_reader(io::stdin());
+    }
     // according to Docs:
     // pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
     //    OpenOptions::new().read(true).open(path.as_ref())
@@ -20,17 +238,356 @@ pub fn validate_buff(input_path: &str) -> Result<(bool, File), AppError> {
     let mut file =
         File::open(input_path).map_err(|_| AppError::FileNotFound(input_path.to_string()))?;
     let mut reader = BufReader::new(file.try_clone()?);
-    let mut first_line = String::new();
-    reader.read_line(&mut first_line)?;
-    // Trim and check whether it matches our expected header
-    let header_line = first_line.trim().replace(' ', "");
-    let has_headers = header_line.eq_ignore_ascii_case("type,client,tx,amount");
-    // reset cursor in order to avoid reloading file
-    file.seek(SeekFrom::Start(0))?;
-    Ok((has_headers, file))
-}
-
-pub fn trunc_decimals(value: f32, decimals: u32) -> f32 {
-    let factor = 10f32.powi(decimals as i32);
-    (value * factor).trunc() / factor
+    let (first_line, had_bom) = read_first_line(&mut reader)?;
+    let first_line = String::from_utf8_lossy(&first_line);
+    let first_line = first_record(&first_line);
+    let delimiter = sniff_delimiter(first_line);
+    // reset cursor in order to avoid reloading file, skipping the BOM
+    let start = if had_bom { UTF8_BOM.len() } else { 0 };
+    file.seek(SeekFrom::Start(start as u64))?;
+    Ok(BufferInfo {
+        has_headers: detect_header(first_line, delimiter)?,
+        delimiter,
+        file: Box::new(file),
+        had_bom,
+    })
+}
+
+/// Error payload of `LineLimit`, surfaced as `AppError::InvalidFormat`
+#[derive(Debug)]
+pub(crate) struct LineTooLong(usize);
+
+impl Display for LineTooLong {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line longer than the maximum of {} bytes", self.0)
+    }
+}
+
+impl Error for LineTooLong {}
+
+impl LineTooLong {
+    /// the `LineLimit` payload of `err`, if any
+    pub(crate) fn of(err: &io::Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+/// Errors reading `inner` once a line, ending at `\n` or `\r`, grows past `max` bytes, before
+/// the csv reader buffers it whole.
+struct LineLimit<R> {
+    inner: R,
+    max: usize,
+    /// length of the line read so far
+    current: usize,
+}
+
+impl<R: Read> Read for LineLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut lines = buf[..read].split(|byte| matches!(byte, b'\n' | b'\r'));
+        self.current += lines.next().map_or(0, <[u8]>::len);
+        let mut longest = self.current;
+        for line in lines {
+            self.current = line.len();
+            longest = longest.max(self.current);
+        }
+        if longest > self.max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                LineTooLong(self.max),
+            ));
+        }
+        Ok(read)
+    }
+}
+
+/// How digits past `DECIMALS` are rounded into ticks when parsing an amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// toward zero, discarding the extra digits
+    #[default]
+    Truncate,
+    /// to nearest, ties away from zero
+    HalfUp,
+    /// to nearest, ties to the even tick
+    HalfEven,
+    /// toward positive infinity
+    Ceil,
+    /// toward negative infinity
+    Floor,
+}
+
+impl FromStr for RoundingMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Self::Truncate),
+            "half-up" => Ok(Self::HalfUp),
+            "half-even" => Ok(Self::HalfEven),
+            "ceil" => Ok(Self::Ceil),
+            "floor" => Ok(Self::Floor),
+            _ => Err(AppError::InvalidArgument(format!(
+                "unknown rounding mode {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What the amount column of the input counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmountUnits {
+    /// decimals in the currency, e.g. `1.2345`, rounded into ticks
+    #[default]
+    Major,
+    /// integer tick counts, e.g. `12345` for `1.2345`, taken as is without any rounding
+    Minor,
+}
+
+impl FromStr for AmountUnits {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            _ => Err(AppError::InvalidArgument(format!(
+                "unknown amount units {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl RoundingMode {
+    /// whether a tick `magnitude` should be bumped away from zero, given the `discarded` digits
+    fn round_up(self, magnitude: i128, discarded: &str, negative: bool) -> bool {
+        let discarded = discarded.trim_end_matches('0');
+        if discarded.is_empty() {
+            return false;
+        }
+        let first = discarded.as_bytes()[0];
+        match self {
+            RoundingMode::Truncate => false,
+            RoundingMode::HalfUp => first >= b'5',
+            RoundingMode::HalfEven => {
+                first > b'5'
+                    || discarded.len() > 1 && first == b'5'
+                    || discarded == "5" && magnitude % 2 == 1
+            }
+            RoundingMode::Ceil => !negative,
+            RoundingMode::Floor => negative,
+        }
+    }
+}
+
+/// A tick, in the billionths `ParseOptions::tick_tolerance` is expressed in
+const TICK_FRACTION: u64 = 1_000_000_000;
+
+/// `0.00000001`, a ten-thousandth of a tick
+pub const DEFAULT_TICK_TOLERANCE: u32 = 100_000;
+
+/// Parses a `--tick-tolerance` amount, such as `0.00000001`, into billionths of a tick.
+///
+/// It must be below half a tick, so an amount is never within it of two boundaries.
+pub fn parse_tick_tolerance(raw: &str) -> Result<u32, AppError> {
+    let invalid =
+        |reason: &str| AppError::InvalidArgument(format!("--tick-tolerance {} {}", raw, reason));
+    let fraction = match raw.split_once('.') {
+        Some(("0" | "", fraction)) => fraction,
+        None if raw == "0" => "",
+        _ => return Err(invalid("must be a decimal amount below 1")),
+    };
+    if !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid("must be a decimal amount below 1"));
+    }
+    // past the billionths of a tick, digits are dropped
+    let width = DECIMALS as usize + 9;
+    let digits = &fraction[..fraction.len().min(width)];
+    let tolerance: u64 = format!("{:0<width$}", digits, width = width).parse()?;
+    if tolerance >= TICK_FRACTION / 2 {
+        return Err(invalid("must be below half a tick"));
+    }
+    Ok(tolerance as u32)
+}
+
+/// Whether the `discarded` digits past a tick are within `tolerance` billionths of a tick of
+/// the next boundary, `Some(true)`, or of the current one, `Some(false)`. `None` otherwise.
+fn snap_to_tick(discarded: &str, tolerance: u32) -> Option<bool> {
+    let (billionths, rest) = discarded.split_at(discarded.len().min(9));
+    // right padded with zeros, up to billionths
+    let billionths = billionths
+        .bytes()
+        .chain(iter::repeat(b'0'))
+        .take(9)
+        .fold(0u64, |acc, digit| acc * 10 + u64::from(digit - b'0'));
+    let tolerance = u64::from(tolerance);
+    if billionths >= TICK_FRACTION - tolerance {
+        Some(true)
+    } else if billionths < tolerance || billionths == tolerance && rest.bytes().all(|b| b == b'0') {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// names formatting artifacts commonly found in exported amounts, which would otherwise
+/// be rejected with a generic error
+fn amount_artifact(raw: &str) -> Option<&'static str> {
+    let unsigned = raw.trim_start_matches(['+', '-']).to_ascii_lowercase();
+    if matches!(unsigned.as_str(), "inf" | "infinity" | "nan") {
+        Some("is not a finite number")
+    } else if unsigned.contains('e')
+        && unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+    {
+        Some("uses scientific notation")
+    } else if raw.contains(',') {
+        Some("contains a thousands separator")
+    } else if raw.starts_with('(') && raw.ends_with(')') {
+        Some("uses parentheses for a negative amount")
+    } else if raw.contains(['$', '€', '£', '¥']) {
+        Some("contains a currency symbol")
+    } else {
+        None
+    }
+}
+
+/// number of fractional digits carrying information, i.e. ignoring trailing zeros
+fn significant_decimals(raw: &str) -> usize {
+    raw.split_once('.')
+        .map_or(0, |(_, fraction)| fraction.trim_end_matches('0').len())
+}
+
+/// parses the id in `raw`, naming `field` and its `type_name` limit when out of range
+pub fn parse_id<T: FromStr<Err = ParseIntError>>(
+    raw: &str,
+    field: &str,
+    type_name: &str,
+) -> Result<T, AppError> {
+    raw.parse().map_err(|err: ParseIntError| match err.kind() {
+        IntErrorKind::PosOverflow => {
+            AppError::InvalidRecord(format!("{} {} exceeds {} max", field, raw, type_name))
+        }
+        _ => AppError::from(err),
+    })
+}
+
+/// Parses a decimal amount string into ticks, rounding past `DECIMALS` decimals with
+/// `options.rounding` unless `options.reject_excess_precision` is set. Under
+/// `AmountUnits::Minor`, the string is the integer tick count itself.
+pub fn parse_amount(raw: &str, options: &ParseOptions) -> Result<i32, AppError> {
+    let ticks = parse_ticks(raw, options)?;
+    i32::try_from(ticks).map_err(|_| amount_out_of_range(raw, i32::MIN, i32::MAX))
+}
+
+/// range error of the amount `raw`, which doesn't fit within `min` and `max` ticks
+fn amount_out_of_range(raw: &str, min: impl Into<i128>, max: impl Into<i128>) -> AppError {
+    AppError::RangeError(format!(
+        "amount \"{}\" must be within {} and {}",
+        raw,
+        format_ticks(min),
+        format_ticks(max)
+    ))
+}
+
+/// same as `parse_amount`, over the wider range of `Amount`
+pub fn parse_ticks(raw: &str, options: &ParseOptions) -> Result<TickInt, AppError> {
+    if let Some(reason) = amount_artifact(raw) {
+        return Err(AppError::InvalidRecord(format!(
+            "amount \"{}\" {}",
+            raw, reason
+        )));
+    }
+    if options.amount_units == AmountUnits::Minor {
+        return parse_minor_units(raw);
+    }
+    if options.reject_excess_precision && significant_decimals(raw) > DECIMALS as usize {
+        return Err(AppError::InvalidRecord(format!(
+            "amount \"{}\" has more than {} decimal places",
+            raw, DECIMALS
+        )));
+    }
+    // parsed digit by digit, so the amount never goes through floating point
+    let invalid = || AppError::InvalidRecord(format!("amount \"{}\" is not a decimal number", raw));
+    // some exporters write `+5.0`, `.5` or `5.`, which are accepted as long as one side of the
+    // dot has digits
+    let (negative, unsigned) = match raw.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let is_digits = |digits: &str| digits.bytes().all(|byte| byte.is_ascii_digit());
+    if integer.is_empty() && fraction.is_empty() || !is_digits(integer) || !is_digits(fraction) {
+        return Err(invalid());
+    }
+    let split = fraction.len().min(DECIMALS as usize);
+    let (kept, discarded) = fraction.split_at(split);
+    // saturating, so out of range amounts are caught by try_from
+    let mut magnitude = format!("{}{:0<width$}", integer, kept, width = DECIMALS as usize)
+        .bytes()
+        .fold(0i128, |acc, digit| {
+            acc.saturating_mul(10)
+                .saturating_add((digit - b'0') as i128)
+        });
+    let round_up = snap_to_tick(discarded, options.tick_tolerance)
+        .unwrap_or_else(|| options.rounding.round_up(magnitude, discarded, negative));
+    if round_up {
+        magnitude = magnitude.saturating_add(1);
+    }
+    let ticks = if negative { -magnitude } else { magnitude };
+    TickInt::try_from(ticks).map_err(|_| amount_out_of_range(raw, TickInt::MIN, TickInt::MAX))
+}
+
+/// integer tick count in `raw`, see `AmountUnits::Minor`
+fn parse_minor_units(raw: &str) -> Result<TickInt, AppError> {
+    if raw.contains('.') {
+        return Err(AppError::InvalidRecord(format!(
+            "amount \"{}\" has a decimal point, while minor units are integer ticks",
+            raw
+        )));
+    }
+    raw.parse().map_err(|err: ParseIntError| match err.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            AppError::InvalidRecord(format!("amount \"{}\" is out of range", raw))
+        }
+        _ => AppError::InvalidRecord(format!("amount \"{}\" is not an integer tick count", raw)),
+    })
+}
+
+/// Renders ticks as a decimal string with `DECIMALS` decimal places, see
+/// `ticks_to_decimal_string`.
+pub fn format_ticks(ticks: impl Into<i128>) -> String {
+    ticks_to_decimal_string(ticks.into(), DECIMALS)
+}
+
+/// Renders ticks as a decimal string with `decimals` decimal places, and no decimal point for 0.
+///
+/// Integer and fractional parts are split out of the ticks themselves, so the output is exact:
+/// past `DECIMALS` places the fraction is padded with zeros, and below them it's truncated toward
+/// zero, as `RoundingMode::Truncate` parses. Zero is canonical, never rendering as `-0.0000`,
+/// even once truncated from a negative amount.
+///
+/// Takes `i128` ticks rather than `i64`, as output rows widen ticks to `i128` whatever the
+/// `TickInt` balances are counted in; narrower ticks go through `i128::from`.
+pub fn ticks_to_decimal_string(ticks: i128, decimals: u32) -> String {
+    let scale = 10u128.pow(DECIMALS);
+    let magnitude = ticks.unsigned_abs();
+    let whole = magnitude / scale;
+    let mut fraction = format!("{:0width$}", magnitude % scale, width = DECIMALS as usize);
+    fraction.truncate(decimals as usize);
+    let sign = match ticks < 0 && (whole != 0 || fraction.bytes().any(|digit| digit != b'0')) {
+        true => "-",
+        false => "",
+    };
+    if decimals == 0 {
+        return format!("{}{}", sign, whole);
+    }
+    format!(
+        "{}{}.{:0<width$}",
+        sign,
+        whole,
+        fraction,
+        width = decimals as usize
+    )
 }
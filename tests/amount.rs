@@ -0,0 +1,42 @@
+use csv_ledger::{Amount, AppError};
+
+#[test]
+fn amount_round_trips_through_display() {
+    for raw in [
+        "0.0000",
+        "0.0001",
+        "1.2345",
+        "-1.2345",
+        "10.5000",
+        "214748.3647",
+        "-214748.3648",
+        "922337203685477.5807",
+        "-922337203685477.5808",
+    ] {
+        assert_eq!(raw.parse::<Amount>().unwrap().to_string(), raw);
+    }
+    // every tick in a range spanning several integer parts
+    for ticks in -30_000..30_000 {
+        let amount = Amount::from_ticks(ticks);
+        assert_eq!(amount.to_string().parse::<Amount>().unwrap(), amount);
+    }
+}
+
+#[test]
+fn amount_parses_to_exact_ticks() {
+    assert_eq!("1.2345".parse::<Amount>().unwrap().to_ticks(), 12_345);
+    assert_eq!("7".parse::<Amount>().unwrap().to_ticks(), 70_000);
+    assert!(matches!(
+        "1.2.3".parse::<Amount>(),
+        Err(AppError::InvalidRecord(_))
+    ));
+}
+
+#[test]
+fn amount_arithmetic_is_checked() {
+    let one = Amount::from_ticks(10_000);
+    assert_eq!(one.checked_add(one), Some(Amount::from_ticks(20_000)));
+    assert_eq!(Amount::ZERO.checked_sub(one), Some(-one));
+    assert_eq!(Amount::from_ticks(i64::MAX).checked_add(one), None);
+    assert_eq!(Amount::from_ticks(i64::MIN).checked_sub(one), None);
+}
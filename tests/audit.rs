@@ -0,0 +1,170 @@
+mod common;
+
+use std::fs;
+
+use common::write_temp;
+use csv::ReaderBuilder;
+use csv_ledger::{
+    AuditEntry, Config, ControlFlowFinding, PaymentEngine, ProcessOptions, TransactionStatus,
+    TransactionType, audit_control_flow, process_records, run_with_diagnostics,
+};
+
+#[test]
+fn audit_log_tracks_dispute_then_chargeback() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+dispute,1,1,
+chargeback,1,1,
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new().with_audit();
+    process_records(&mut reader, &mut engine, &ProcessOptions::default()).unwrap();
+
+    let user = engine.get(1).unwrap();
+    assert_eq!(
+        user.audit_log(),
+        &[
+            AuditEntry {
+                tx_id: 1,
+                line: Some(2),
+                from: None,
+                to: TransactionStatus::Normal,
+            },
+            AuditEntry {
+                tx_id: 1,
+                line: Some(4),
+                from: Some(TransactionStatus::Normal),
+                to: TransactionStatus::Disputed,
+            },
+            AuditEntry {
+                tx_id: 1,
+                line: Some(5),
+                from: Some(TransactionStatus::Disputed),
+                to: TransactionStatus::Solved(true),
+            },
+        ]
+    );
+    assert_eq!(
+        user.audit_log()[2].to_string(),
+        "line 5: tx 1 Disputed -> Solved(true)"
+    );
+}
+
+#[test]
+fn first_and_last_seen_lines_are_tracked_per_client() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+deposit,1,3,1.0
+withdrawal,1,4,2.0
+dispute,1,1,
+resolve,1,1,
+withdrawal,1,5,1.0
+withdrawal,2,6,99.0
+deposit,1,7,1.0
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new();
+    process_records(&mut reader, &mut engine, &ProcessOptions::default()).unwrap();
+
+    let user = engine.get(2).unwrap();
+    // the withdrawal on line 9 is ignored for insufficient funds, but still seen
+    assert_eq!(user.first_seen_line(), Some(3));
+    assert_eq!(user.last_seen_line(), Some(9));
+    let user = engine.get(1).unwrap();
+    assert_eq!(
+        (user.first_seen_line(), user.last_seen_line()),
+        (Some(2), Some(10))
+    );
+
+    let path = write_temp("audit_seen_lines", input.as_bytes());
+    let config =
+        Config::from_args(["csv_ledger", "--audit", &path.to_string_lossy()].map(String::from))
+            .unwrap();
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    run_with_diagnostics(&config, &mut output, &mut diagnostics).unwrap();
+    fs::remove_file(&path).unwrap();
+    let diagnostics = String::from_utf8(diagnostics).unwrap();
+    assert!(diagnostics.contains("client 2, first seen on line 3, last seen on line 9\n"));
+}
+
+#[test]
+fn rejected_withdrawals_are_reported_after_the_run() {
+    let input = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,7.5
+withdrawal,2,3,1.0
+withdrawal,1,4,5.0
+";
+    let path = write_temp("rejected_withdrawals", input.as_bytes());
+    let config = Config::from_args(
+        [
+            "csv_ledger",
+            "--report-rejected-withdrawals",
+            &path.to_string_lossy(),
+        ]
+        .map(String::from),
+    )
+    .unwrap();
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    run_with_diagnostics(&config, &mut output, &mut diagnostics).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        String::from_utf8(diagnostics).unwrap(),
+        "line 3: withdrawal tx 2 of client 1 short by 2.5000, rejected
+line 4: withdrawal tx 3 of client 2 short by 1.0000, rejected
+"
+    );
+}
+
+#[test]
+fn seen_lines_are_absent_for_programmatic_inputs() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(csv_ledger::TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    assert_eq!(engine.get(1).unwrap().first_seen_line(), None);
+}
+
+#[test]
+fn audit_log_is_empty_unless_enabled() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(csv_ledger::TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    assert!(engine.get(1).unwrap().audit_log().is_empty());
+}
+
+#[test]
+fn control_flow_reports_chargeback_without_dispute() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+chargeback,1,1,
+dispute,1,2,
+resolve,1,2,
+resolve,1,2,
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let findings = audit_control_flow(&mut reader).unwrap();
+    assert_eq!(
+        findings,
+        vec![
+            ControlFlowFinding {
+                line: 4,
+                tx_type: TransactionType::Chargeback,
+                tx_id: 1,
+            },
+            ControlFlowFinding {
+                line: 7,
+                tx_type: TransactionType::Resolve,
+                tx_id: 2,
+            },
+        ]
+    );
+    assert_eq!(
+        findings[0].to_string(),
+        "line 4: Chargeback of tx 1 has no open dispute"
+    );
+}
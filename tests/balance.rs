@@ -0,0 +1,184 @@
+use csv_ledger::{
+    AppError, ClientBalance, OutputFormat, PaymentEngine, TransactionInput, format_ticks,
+    self_check_csv, ticks_to_decimal_string, write_balances,
+};
+
+#[test]
+fn client_balance_round_trips_through_serde() {
+    let balance = ClientBalance {
+        client: 1,
+        available: 15_000,
+        held: 5_000,
+        total: 20_000,
+        locked: false,
+        held_tx_ids: None,
+        open_disputes: None,
+        currency: None,
+    };
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.serialize(&balance).unwrap();
+    let data = writer.into_inner().unwrap();
+    assert_eq!(
+        String::from_utf8(data.clone()).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.5000,2.0000,false\n"
+    );
+
+    let mut reader = csv::Reader::from_reader(data.as_slice());
+    let parsed: ClientBalance = reader.deserialize().next().unwrap().unwrap();
+    assert_eq!(parsed, balance);
+}
+
+#[test]
+fn client_balance_json_keeps_four_decimals() {
+    let balance = ClientBalance {
+        client: 3,
+        available: 0,
+        held: 0,
+        total: 0,
+        locked: true,
+        held_tx_ids: None,
+        open_disputes: None,
+        currency: None,
+    };
+    assert_eq!(
+        balance.to_json(),
+        r#"{"client":3,"available":0.0000,"held":0.0000,"total":0.0000,"locked":true}"#
+    );
+}
+
+#[test]
+fn csv_output_serializes_client_balances() {
+    let engine = PaymentEngine::from_csv_str(
+        "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,2.0\ndispute,2,2,\n",
+    )
+    .unwrap();
+    let balances: Vec<ClientBalance> = engine.balances().collect();
+
+    let mut serialized = csv::Writer::from_writer(vec![]);
+    for balance in &balances {
+        serialized.serialize(balance).unwrap();
+    }
+    let mut written = Vec::new();
+    write_balances(&mut written, balances.iter().cloned(), OutputFormat::Csv).unwrap();
+    assert_eq!(written, serialized.into_inner().unwrap());
+}
+
+#[test]
+fn held_transaction_ids_lists_disputed_deposits() {
+    let mut engine = PaymentEngine::new();
+    for input in [
+        TransactionInput::Deposit(7, 1, 10_000),
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Deposit(4, 1, 1_000),
+        TransactionInput::Dispute(7, 1),
+        TransactionInput::Dispute(2, 1),
+    ] {
+        engine.apply(input).unwrap();
+    }
+    let user = engine.get(1).unwrap();
+    assert_eq!(user.held_transaction_ids(), vec![2, 7]);
+
+    let mut balance = user.balance();
+    balance.held_tx_ids = Some(user.held_transaction_ids());
+    assert_eq!(
+        balance.to_json(),
+        r#"{"client":1,"available":0.1000,"held":1.5000,"total":1.6000,"locked":false,"held_tx_ids":[2,7]}"#
+    );
+}
+
+#[test]
+fn zero_available_after_withdrawal_has_no_sign() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 12_345))
+        .unwrap();
+    engine
+        .apply(TransactionInput::Withdrawal(2, 1, 12_345))
+        .unwrap();
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!(balance.available, 0);
+    assert_eq!(balance.to_csv_row(), "1,0.0000,0.0000,0.0000,false");
+}
+
+#[test]
+fn ticks_format_exactly() {
+    assert_eq!(format_ticks(0), "0.0000");
+    assert_eq!(format_ticks(-1), "-0.0001");
+    assert_eq!(format_ticks(123_456_789), "12345.6789");
+    assert_eq!(format_ticks(i32::MIN), "-214748.3648");
+}
+
+#[test]
+fn ticks_render_at_any_precision() {
+    for (ticks, decimals, expected) in [
+        (0, 4, "0.0000"),
+        (1, 4, "0.0001"),
+        (-1, 4, "-0.0001"),
+        (12_345, 4, "1.2345"),
+        (12_345, 6, "1.234500"),
+        (12_345, 2, "1.23"),
+        (-12_345, 0, "-1"),
+        (1, 0, "0"),
+        // truncated to zero, which is never negative
+        (-1, 2, "0.00"),
+        (-1, 0, "0"),
+        (i64::MAX.into(), 4, "922337203685477.5807"),
+        (i64::MIN.into(), 4, "-922337203685477.5808"),
+        (i64::MIN.into(), 8, "-922337203685477.58080000"),
+        (i128::MIN, 4, "-17014118346046923173168730371588410.5728"),
+    ] {
+        assert_eq!(
+            ticks_to_decimal_string(ticks, decimals),
+            expected,
+            "{} at {} decimals",
+            ticks,
+            decimals
+        );
+    }
+    assert_eq!(format_ticks(-12_345), ticks_to_decimal_string(-12_345, 4));
+}
+
+#[test]
+fn large_balances_render_exactly() {
+    let mut engine = PaymentEngine::new();
+    // 5 x 200000.0000 + 0.0001, far past the 2^24 integers f32 holds exactly
+    for tx in 1..=5 {
+        engine
+            .apply(TransactionInput::Deposit(tx, 1, 2_000_000_000))
+            .unwrap();
+    }
+    engine.apply(TransactionInput::Deposit(6, 1, 1)).unwrap();
+    engine.apply(TransactionInput::Dispute(1, 1)).unwrap();
+    assert_eq!(
+        engine.get(1).unwrap().to_csv_row(),
+        "1,800000.0001,200000.0000,1000000.0001,false"
+    );
+}
+
+#[test]
+fn self_check_catches_malformed_balances() {
+    let engine = PaymentEngine::from_csv_str(
+        "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,2.0\ndispute,2,2,\n",
+    )
+    .unwrap();
+    let mut output = Vec::new();
+    write_balances(&mut output, engine.balances(), Default::default()).unwrap();
+    assert_eq!(self_check_csv(&output).unwrap(), 2);
+
+    // a NaN leaking into an extra row, then a row missing its locked flag
+    let mut injected = String::from_utf8(output).unwrap();
+    injected.push_str("3,NaN,0.0000,0.0000,false\n");
+    let err = self_check_csv(injected.as_bytes()).unwrap_err();
+    assert!(matches!(err, AppError::InvalidFormat(_)), "{}", err);
+    assert!(
+        err.to_string().contains(r#"line 4: available "NaN""#),
+        "{}",
+        err
+    );
+
+    let truncated = "client,available,held,total,locked\n1,1.5000,0.0000,1.5000\n";
+    assert!(matches!(
+        self_check_csv(truncated.as_bytes()),
+        Err(AppError::InvalidFormat(_))
+    ));
+}
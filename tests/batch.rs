@@ -0,0 +1,67 @@
+use csv::ReaderBuilder;
+use csv_ledger::{AppError, ClientBalance, PaymentEngine, ProcessOptions, process_records};
+
+fn balance_of(input: &str, client_id: u16) -> Result<Option<ClientBalance>, AppError> {
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new();
+    process_records(&mut reader, &mut engine, &ProcessOptions::default())?;
+    Ok(engine.get(client_id).map(|user| user.balance()))
+}
+
+#[test]
+fn rolled_back_batch_leaves_balances_unchanged() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+begin,,,
+deposit,1,2,5.0
+withdrawal,1,3,12.0
+rollback,,,
+";
+    let balance = balance_of(input, 1).unwrap().unwrap();
+    assert_eq!(balance.available, 100_000);
+    assert_eq!(balance.total, 100_000);
+}
+
+#[test]
+fn committed_batch_applies() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+begin,,,
+deposit,1,2,5.0
+withdrawal,1,3,12.0
+commit,,,
+";
+    let balance = balance_of(input, 1).unwrap().unwrap();
+    assert_eq!(balance.available, 30_000);
+}
+
+#[test]
+fn commit_without_begin_is_rejected() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+commit,,,
+";
+    let err = balance_of(input, 1).unwrap_err();
+    assert!(matches!(err, AppError::AtLine { line: 3, .. }));
+    assert!(matches!(err.inner(), AppError::InvalidFormat(_)));
+}
+
+#[test]
+fn strict_commit_is_all_or_nothing() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+begin,,,
+deposit,1,2,5.0
+withdrawal,1,3,20.0
+commit,,,
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new().with_strict();
+    let options = ProcessOptions {
+        max_errors: Some(10),
+        ..Default::default()
+    };
+    let progress = process_records(&mut reader, &mut engine, &options).unwrap();
+    assert_eq!(progress.errors.len(), 1);
+    assert_eq!(engine.get(1).unwrap().balance().available, 100_000);
+}
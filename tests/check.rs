@@ -0,0 +1,35 @@
+mod common;
+
+use common::run_with_args;
+use csv_ledger::AppError;
+
+#[test]
+fn check_reports_the_line_of_the_first_bad_row() {
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,2,1.0
+withdrawal,1,3,0.5
+dispute,1,1,
+resolve,1,1,
+deposit,1,4,abc
+deposit,1,5,1.0
+";
+    match run_with_args("check_bad_row", input, &["--check"]) {
+        Err(err @ AppError::AtLine { line: 7, .. }) => assert!(err.to_string().contains('7')),
+        other => panic!("expected an error at line 7, got {:?}", other),
+    }
+}
+
+#[test]
+fn check_prints_no_balances() {
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+withdrawal,1,2,5.0
+commit,,,
+";
+    assert_eq!(
+        run_with_args("check_valid", input, &["--check"]).unwrap(),
+        ""
+    );
+}
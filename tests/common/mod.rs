@@ -0,0 +1,44 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use csv_ledger::{Config, run};
+
+/// writes `contents` into a file unique to the calling test
+pub fn write_temp(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("csv_ledger_{}_{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// runs the pipeline over `input` with the given CLI options, returning the output
+pub fn run_with_args(
+    name: &str,
+    input: &str,
+    options: &[&str],
+) -> Result<String, csv_ledger::AppError> {
+    let path = write_temp(name, input.as_bytes());
+    let result = run_file(&path, options);
+    fs::remove_file(&path).unwrap();
+    result
+}
+
+/// runs the pipeline over the file at `path` with the given CLI options, returning the output
+pub fn run_file(path: &Path, options: &[&str]) -> Result<String, csv_ledger::AppError> {
+    let mut args = vec!["csv_ledger".to_string()];
+    args.extend(options.iter().map(|option| option.to_string()));
+    args.push(path.to_string_lossy().into_owned());
+    let config = Config::from_args(args)?;
+    let mut output = Vec::new();
+    run(&config, &mut output)?;
+    Ok(String::from_utf8(output).unwrap())
+}
+
+/// path of a committed test fixture
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::fs;
+
+use common::write_temp;
+use csv_ledger::{InputFormat, ParseOptions, echo_normalized};
+
+#[test]
+fn echo_shows_canonical_rows() {
+    let input = "type,client,tx,amount
+ DEPOSIT , 1 , 1 , 1.23456
+Withdrawal,1,2,0.5
+begin,,,
+dispute,  1,1,
+bogus,1,3,1.0
+commit,,,
+";
+    let path = write_temp("echo_normalized.csv", input.as_bytes());
+    let mut echo = Vec::new();
+    echo_normalized(
+        &path.to_string_lossy(),
+        InputFormat::Csv,
+        &ParseOptions::default(),
+        &mut echo,
+    )
+    .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        String::from_utf8(echo).unwrap(),
+        "deposit,1,1,1.2345
+withdrawal,1,2,0.5000
+begin,,,
+dispute,1,1,
+commit,,,
+"
+    );
+}
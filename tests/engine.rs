@@ -0,0 +1,1466 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use csv_ledger::{
+    Amount, AppError, FreezePolicy, InvariantViolation, PaymentEngine, TransactionInput,
+    TransactionStatus, TxOutcome, TxRules, User, ViolationPolicy,
+};
+
+#[test]
+fn engine_applies_inputs_per_client() {
+    let mut engine = PaymentEngine::new();
+    let inputs = [
+        (TransactionInput::Deposit(1, 1, 15_000), TxOutcome::Applied),
+        (TransactionInput::Deposit(2, 2, 20_000), TxOutcome::Applied),
+        (
+            TransactionInput::Withdrawal(3, 1, 5_000),
+            TxOutcome::Applied,
+        ),
+        (
+            TransactionInput::Withdrawal(4, 2, 30_000),
+            TxOutcome::IgnoredInsufficientFunds,
+        ),
+        (
+            TransactionInput::Deposit(1, 1, 1_000),
+            TxOutcome::IgnoredConflictingDuplicate,
+        ),
+        (TransactionInput::Dispute(9, 1), TxOutcome::IgnoredMissingTx),
+    ];
+    for (input, expected) in inputs {
+        assert_eq!(engine.apply(input).unwrap(), expected);
+    }
+
+    let first = engine.get(1).unwrap().balance();
+    assert_eq!(
+        (first.available, first.held, first.total),
+        (10_000, 0, 10_000)
+    );
+    let second = engine.get(2).unwrap().balance();
+    assert_eq!(
+        (second.available, second.held, second.total),
+        (20_000, 0, 20_000)
+    );
+    assert!(engine.get(3).is_none());
+    assert_eq!(engine.balances().count(), 2);
+}
+
+#[test]
+fn engine_locks_client_on_chargeback() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    engine.apply(TransactionInput::Dispute(1, 1)).unwrap();
+    let held = engine.get(1).unwrap().balance();
+    assert_eq!((held.available, held.held), (0, 10_000));
+
+    assert_eq!(
+        engine.apply(TransactionInput::Chargeback(1, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(2, 1, 10_000))
+            .unwrap(),
+        TxOutcome::IgnoredLocked
+    );
+    let locked = engine.get(1).unwrap().balance();
+    assert_eq!((locked.total, locked.locked), (0, true));
+}
+
+#[test]
+fn transaction_status_follows_dispute_and_chargeback() {
+    let mut user = User::new(1);
+    user.process_tx_input(TransactionInput::Deposit(1, 1, 10_000), None)
+        .unwrap();
+    assert_eq!(user.transaction_status(1), Some(TransactionStatus::Normal));
+    assert_eq!(user.transaction_status(2), None);
+
+    user.process_tx_input(TransactionInput::Dispute(1, 1), None)
+        .unwrap();
+    assert_eq!(
+        user.transaction_status(1),
+        Some(TransactionStatus::Disputed)
+    );
+
+    user.process_tx_input(TransactionInput::Chargeback(1, 1), None)
+        .unwrap();
+    let charged_back = user.transaction(1).unwrap();
+    assert_eq!(
+        (charged_back.status, charged_back.amount),
+        (TransactionStatus::Solved(true), Amount::from(10_000))
+    );
+    assert!(user.transaction(2).is_none());
+}
+
+#[test]
+fn locked_client_still_settles_prior_disputes() {
+    let mut engine = PaymentEngine::new();
+    for input in [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Dispute(2, 1),
+        TransactionInput::Chargeback(1, 1),
+    ] {
+        engine.apply(input).unwrap();
+    }
+    assert!(engine.get(1).unwrap().locked);
+
+    assert_eq!(
+        engine.apply(TransactionInput::Resolve(2, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(3, 1, 1_000))
+            .unwrap(),
+        TxOutcome::IgnoredLocked
+    );
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (5_000, 0, 5_000)
+    );
+}
+
+#[test]
+fn atomic_batch_rolls_back_on_failure() {
+    let mut engine = PaymentEngine::new().with_strict();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    let batch = vec![
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Deposit(3, 2, 5_000),
+        TransactionInput::Withdrawal(4, 1, 50_000),
+    ];
+    assert!(matches!(
+        engine.apply_batch(batch, true),
+        Err(AppError::InvalidRecord(_))
+    ));
+
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.available, balance.total), (10_000, 10_000));
+    assert!(!engine.get(1).unwrap().transactions.contains_key(&2));
+    assert!(engine.get(2).is_none());
+}
+
+#[test]
+fn atomic_batch_rolls_back_the_owner_of_a_misfiled_dispute() {
+    let mut engine = PaymentEngine::new().with_strict();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    // the dispute is filed under client 2, but holds the funds of client 1 owning tx 1
+    let batch = vec![
+        TransactionInput::Dispute(1, 2),
+        TransactionInput::Withdrawal(2, 2, 5_000),
+    ];
+    assert!(engine.apply_batch(batch, true).is_err());
+
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.available, balance.held), (10_000, 0));
+    assert_eq!(
+        engine.get(1).unwrap().transaction_status(1),
+        Some(TransactionStatus::Normal)
+    );
+}
+
+#[test]
+fn non_atomic_batch_keeps_inputs_before_failure() {
+    let mut engine = PaymentEngine::new().with_strict();
+    let batch = vec![
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Withdrawal(2, 1, 50_000),
+        TransactionInput::Deposit(3, 1, 5_000),
+    ];
+    assert!(engine.apply_batch(batch, false).is_err());
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!(balance.available, 10_000);
+
+    let outcomes = engine
+        .apply_batch(vec![TransactionInput::Deposit(3, 1, 5_000)], false)
+        .unwrap();
+    assert_eq!(outcomes, vec![TxOutcome::Applied]);
+}
+
+#[test]
+fn lock_hook_fires_once_per_lock() {
+    let locked = Rc::new(RefCell::new(Vec::new()));
+    let seen = Rc::clone(&locked);
+    let mut engine = PaymentEngine::new().on_lock(move |user| seen.borrow_mut().push(user.id));
+    for input in [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Deposit(3, 2, 5_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Dispute(2, 1),
+        TransactionInput::Dispute(3, 2),
+        TransactionInput::Chargeback(1, 1),
+        // already locked, so no second event for client 1
+        TransactionInput::Chargeback(2, 1),
+        TransactionInput::Resolve(3, 2),
+    ] {
+        engine.apply(input).unwrap();
+    }
+    assert_eq!(*locked.borrow(), vec![1]);
+}
+
+#[test]
+fn strict_mode_reports_negative_available() {
+    let inputs = [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Withdrawal(2, 1, 8_000),
+        TransactionInput::Dispute(1, 1),
+    ];
+
+    let mut lenient = PaymentEngine::new();
+    for input in inputs {
+        lenient.apply(input).unwrap();
+    }
+    assert_eq!(lenient.get(1).unwrap().balance().available, 0);
+
+    let mut strict = PaymentEngine::new().with_strict();
+    strict.apply(inputs[0]).unwrap();
+    strict.apply(inputs[1]).unwrap();
+    match strict.apply(inputs[2]) {
+        Err(AppError::InvalidRecord(reason)) => assert!(reason.starts_with("client 1:")),
+        other => panic!("expected a negative balance error, got {:?}", other),
+    }
+}
+
+#[test]
+fn tx_ids_are_unique_across_clients() {
+    let mut engine = PaymentEngine::new();
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(1, 1, 10_000))
+            .unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(1, 2, 5_000))
+            .unwrap(),
+        TxOutcome::IgnoredConflictingDuplicate
+    );
+    assert_eq!(engine.get(2).unwrap().balance().total, 0);
+
+    // an id only counts as used once its transaction is applied
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Withdrawal(2, 2, 5_000))
+            .unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(2, 1, 5_000))
+            .unwrap(),
+        TxOutcome::Applied
+    );
+}
+
+#[test]
+fn rolled_back_batch_releases_its_tx_ids() {
+    let mut engine = PaymentEngine::new().with_strict();
+    let batch = vec![
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Withdrawal(2, 1, 50_000),
+    ];
+    assert!(engine.apply_batch(batch, true).is_err());
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(1, 2, 10_000))
+            .unwrap(),
+        TxOutcome::Applied
+    );
+}
+
+#[test]
+fn dispute_is_routed_to_the_tx_owner() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    engine
+        .apply_at_line(TransactionInput::Dispute(1, 2), 3)
+        .unwrap();
+
+    let owner = engine.get(1).unwrap().balance();
+    assert_eq!((owner.available, owner.held), (0, 10_000));
+    assert!(engine.get(2).is_none());
+    let mismatches = engine.client_mismatches();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(
+        mismatches[0].to_string(),
+        "line 3: tx 1 filed for client 2 belongs to client 1"
+    );
+}
+
+#[test]
+fn resolved_deposit_can_be_disputed_again_when_allowed() {
+    let inputs = [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Resolve(1, 1),
+        TransactionInput::Dispute(1, 1),
+    ];
+    let mut default_rules = PaymentEngine::new();
+    let outcomes: Vec<TxOutcome> = inputs
+        .iter()
+        .map(|input| default_rules.apply(*input).unwrap())
+        .collect();
+    assert_eq!(outcomes[3], TxOutcome::IgnoredInvalidTransition);
+
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        allow_redispute: true,
+        ..TxRules::default()
+    });
+    for input in inputs {
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::Applied);
+    }
+    let held = engine.get(1).unwrap().balance();
+    assert_eq!((held.available, held.held), (0, 10_000));
+
+    assert_eq!(
+        engine.apply(TransactionInput::Chargeback(1, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    let charged_back = engine.get(1).unwrap().balance();
+    assert_eq!((charged_back.total, charged_back.locked), (0, true));
+}
+
+#[test]
+fn chargeback_overrides_resolve_when_allowed() {
+    let inputs = [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Resolve(1, 1),
+        TransactionInput::Chargeback(1, 1),
+    ];
+    let mut default_rules = PaymentEngine::new();
+    let outcomes: Vec<TxOutcome> = inputs
+        .iter()
+        .map(|input| default_rules.apply(*input).unwrap())
+        .collect();
+    assert_eq!(outcomes[4], TxOutcome::IgnoredInvalidTransition);
+    let user = default_rules.get(1).unwrap();
+    assert_eq!(
+        user.transactions[&1].status,
+        TransactionStatus::Solved(false)
+    );
+    let resolved = user.balance();
+    assert_eq!((resolved.available, resolved.locked), (15_000, false));
+
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        chargeback_overrides_resolve: true,
+        ..TxRules::default()
+    });
+    for input in inputs {
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::Applied);
+    }
+    let user = engine.get(1).unwrap();
+    assert_eq!(
+        user.transactions[&1].status,
+        TransactionStatus::Solved(true)
+    );
+    let charged_back = user.balance();
+    assert_eq!(
+        (
+            charged_back.available,
+            charged_back.total,
+            charged_back.locked
+        ),
+        (5_000, 5_000, true)
+    );
+    assert_eq!(user.recompute_balances(), charged_back);
+}
+
+fn apply_after_chargeback(freeze_policy: FreezePolicy) -> (PaymentEngine, Vec<TxOutcome>) {
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        freeze_policy,
+        ..TxRules::default()
+    });
+    for input in [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Deposit(2, 1, 5_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Chargeback(1, 1),
+    ] {
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::Applied);
+    }
+    let outcomes = [
+        TransactionInput::Deposit(3, 1, 1_000),
+        TransactionInput::Withdrawal(4, 1, 500),
+    ]
+    .into_iter()
+    .map(|input| engine.apply(input).unwrap())
+    .collect();
+    (engine, outcomes)
+}
+
+#[test]
+fn freeze_all_rejects_deposits_after_chargeback() {
+    let (engine, outcomes) = apply_after_chargeback(FreezePolicy::FreezeAll);
+    assert_eq!(
+        outcomes,
+        [TxOutcome::IgnoredLocked, TxOutcome::IgnoredLocked]
+    );
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.total, balance.locked), (5_000, true));
+}
+
+#[test]
+fn freeze_withdrawals_only_accepts_deposits_after_chargeback() {
+    let (engine, outcomes) = apply_after_chargeback(FreezePolicy::FreezeWithdrawalsOnly);
+    assert_eq!(outcomes, [TxOutcome::Applied, TxOutcome::IgnoredLocked]);
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.total, balance.locked), (6_000, true));
+}
+
+#[test]
+fn flag_only_accepts_everything_after_chargeback() {
+    let (engine, outcomes) = apply_after_chargeback(FreezePolicy::FlagOnly);
+    assert_eq!(outcomes, [TxOutcome::Applied, TxOutcome::Applied]);
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.total, balance.locked), (5_500, true));
+}
+
+#[test]
+fn withdrawals_can_not_cross_the_min_balance() {
+    // leaving 1 tick above, exactly at, and 1 tick below the 1.0000 floor
+    for (withdrawn, outcome) in [
+        (8_999, TxOutcome::Applied),
+        (9_000, TxOutcome::Applied),
+        (9_001, TxOutcome::IgnoredInsufficientFunds),
+    ] {
+        let mut engine = PaymentEngine::new().with_rules(TxRules {
+            min_balance: Amount::from_ticks(10_000),
+            ..TxRules::default()
+        });
+        engine
+            .apply(TransactionInput::Deposit(1, 1, 19_000))
+            .unwrap();
+        assert_eq!(
+            engine
+                .apply(TransactionInput::Withdrawal(2, 1, withdrawn))
+                .unwrap(),
+            outcome,
+            "{}",
+            withdrawn
+        );
+    }
+}
+
+#[test]
+fn constructors_build_every_variant() {
+    let inputs = [
+        TransactionInput::deposit(1, 1, 10_000).unwrap(),
+        TransactionInput::deposit(2, 1, 5_000).unwrap(),
+        TransactionInput::withdrawal(3, 1, 2_500).unwrap(),
+        TransactionInput::dispute(1, 1),
+        TransactionInput::resolve(1, 1),
+        TransactionInput::dispute(2, 1),
+        TransactionInput::chargeback(2, 1),
+    ];
+    assert_eq!(inputs[0], TransactionInput::Deposit(1, 1, 10_000));
+    assert_eq!(inputs[2], TransactionInput::Withdrawal(3, 1, 2_500));
+    assert_eq!(inputs[6], TransactionInput::Chargeback(2, 1));
+
+    let mut engine = PaymentEngine::new();
+    for input in inputs {
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::Applied);
+    }
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!(
+        (
+            balance.available,
+            balance.held,
+            balance.total,
+            balance.locked
+        ),
+        (7_500, 0, 7_500, true)
+    );
+}
+
+#[test]
+fn constructors_reject_non_positive_amounts() {
+    for result in [
+        TransactionInput::deposit(1, 1, 0),
+        TransactionInput::withdrawal(1, 1, -10_000),
+    ] {
+        assert!(matches!(result, Err(AppError::InvalidRecord(_))));
+    }
+}
+
+/// xorshift, so every failing sequence can be replayed from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// random but valid input: new ids for deposits and withdrawals, existing ones for the rest
+fn random_input(rng: &mut Rng, next_tx: &mut u32, deposits: &[(u32, u16)]) -> TransactionInput {
+    let client = rng.below(3) as u16 + 1;
+    let amount = rng.below(50_000) as i32 + 1;
+    let referenced =
+        (!deposits.is_empty()).then(|| deposits[rng.below(deposits.len() as u64) as usize]);
+    match (rng.below(10), referenced) {
+        (0..=3, _) | (_, None) => {
+            *next_tx += 1;
+            TransactionInput::Deposit(*next_tx, client, amount)
+        }
+        (4..=5, _) => {
+            *next_tx += 1;
+            TransactionInput::Withdrawal(*next_tx, client, amount)
+        }
+        (6..=7, Some((tx, client))) => TransactionInput::Dispute(tx, client),
+        (8, Some((tx, client))) => TransactionInput::Resolve(tx, client),
+        (_, Some((tx, client))) => TransactionInput::Chargeback(tx, client),
+    }
+}
+
+/// Random sequences from a hand-written xorshift loop rather than a `proptest` suite, which isn't
+/// a dependency, so failures aren't shrunk but replay from their seed.
+///
+/// Disputes of deposits already spent are skipped: they hold funds the client no longer has,
+/// taking available funds below zero, which is the known dispute-after-withdrawal case.
+#[test]
+fn balances_keep_their_invariants_under_random_inputs() {
+    for seed in 1..=200u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let mut engine = PaymentEngine::new();
+        let mut next_tx = 0;
+        let mut deposits: Vec<(u32, u16)> = Vec::new();
+        let mut amounts: HashMap<u32, i128> = HashMap::new();
+        // net flows of every client, as the model the engine is checked against
+        let mut flows: HashMap<u16, i128> = HashMap::new();
+
+        for step in 0..200 {
+            let input = random_input(&mut rng, &mut next_tx, &deposits);
+            if let TransactionInput::Dispute(tx, client) = input
+                && engine.get(client).unwrap().balance().available < amounts[&tx]
+            {
+                continue;
+            }
+            let outcome = engine.apply(input).unwrap();
+            if outcome == TxOutcome::Applied {
+                match input {
+                    TransactionInput::Deposit(tx, client, amount) => {
+                        deposits.push((tx, client));
+                        amounts.insert(tx, amount.into());
+                        *flows.entry(client).or_default() += i128::from(amount);
+                    }
+                    TransactionInput::Withdrawal(_, client, amount) => {
+                        *flows.entry(client).or_default() -= i128::from(amount);
+                    }
+                    TransactionInput::Chargeback(tx, client) => {
+                        *flows.entry(client).or_default() -= amounts[&tx];
+                    }
+                    TransactionInput::Dispute(..) | TransactionInput::Resolve(..) => {}
+                    TransactionInput::Authorization(..) | TransactionInput::Capture(..) => {
+                        unreachable!("random inputs are single phase")
+                    }
+                }
+            }
+
+            for user in engine.users() {
+                let balance = user.balance();
+                let context = format!("seed {}, step {}, client {}", seed, step, user.id);
+                assert!(user.check_available().is_ok(), "{}", context);
+                assert!(balance.held >= 0, "{}", context);
+                assert!(balance.total >= 0, "{}", context);
+                assert_eq!(
+                    balance.available + balance.held,
+                    balance.total,
+                    "{}",
+                    context
+                );
+                assert_eq!(
+                    balance.total,
+                    flows.get(&user.id).copied().unwrap_or_default(),
+                    "{}",
+                    context
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn cached_balances_match_replayed_transactions() {
+    let mut engine = PaymentEngine::new();
+    for input in [
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Deposit(2, 2, 5_000),
+        TransactionInput::Withdrawal(3, 1, 2_500),
+        TransactionInput::Dispute(2, 2),
+        TransactionInput::Deposit(4, 1, 1_000),
+        TransactionInput::Dispute(4, 1),
+        TransactionInput::Resolve(4, 1),
+        TransactionInput::Chargeback(2, 2),
+    ] {
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::Applied);
+    }
+    engine.verify().unwrap();
+    for user in engine.users() {
+        assert_eq!(user.recompute_balances(), user.balance());
+    }
+}
+
+#[test]
+fn corrupted_balance_cache_fails_verification() {
+    let mut user = User::new(1);
+    user.process_tx_input(TransactionInput::Deposit(1, 1, 10_000), None)
+        .unwrap();
+    user.verify().unwrap();
+
+    // changed behind the cache's back, without `sync_balances`
+    user.transactions.get_mut(&1).unwrap().amount = Amount::from(7_500);
+    assert_eq!(
+        user.verify().unwrap_err().to_string(),
+        "Balance mismatch for client 1: cached 1,1.0000,0.0000,1.0000,false, recomputed 1,0.7500,0.0000,0.7500,false"
+    );
+    user.sync_balances();
+    user.verify().unwrap();
+}
+
+#[test]
+fn transactions_iterate_by_ascending_id() {
+    let mut engine = PaymentEngine::new();
+    for tx in [42, 7, 1_000, 3, 19] {
+        engine
+            .apply(TransactionInput::Deposit(tx, 1, 10_000))
+            .unwrap();
+    }
+    let ids: Vec<u32> = engine
+        .get(1)
+        .unwrap()
+        .transactions_sorted()
+        .map(|tx| tx.id)
+        .collect();
+    assert_eq!(ids, [3, 7, 19, 42, 1_000]);
+}
+
+fn apply_past_tx_limit(
+    on_tx_limit: ViolationPolicy,
+) -> (PaymentEngine, Result<TxOutcome, AppError>) {
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        max_tx_per_client: Some(3),
+        on_tx_limit,
+        ..TxRules::default()
+    });
+    for tx in 1..=3 {
+        assert_eq!(
+            engine
+                .apply(TransactionInput::Deposit(tx, 1, 10_000))
+                .unwrap(),
+            TxOutcome::Applied
+        );
+    }
+    let result = engine.apply_at_line(TransactionInput::Deposit(4, 1, 10_000), 5);
+    (engine, result)
+}
+
+#[test]
+fn tx_limit_errors_by_default() {
+    let (engine, result) = apply_past_tx_limit(TxRules::default().on_tx_limit);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Invalid record for creating transaction: client 1: tx 4 exceeds the transactions per client limit"
+    );
+    assert_eq!(engine.get(1).unwrap().transactions.len(), 3);
+}
+
+#[test]
+fn tx_limit_warns_when_asked() {
+    let (mut engine, result) = apply_past_tx_limit(ViolationPolicy::Warn);
+    assert_eq!(result.unwrap(), TxOutcome::IgnoredTxLimit);
+    assert_eq!(
+        engine.warnings()[0].to_string(),
+        "line 5: tx 4 of client 1 exceeds the transactions per client limit, ignored"
+    );
+    // other clients and disputes of existing transactions are unaffected
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Deposit(5, 2, 10_000))
+            .unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(
+        engine.apply(TransactionInput::Dispute(1, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(engine.get(1).unwrap().transactions.len(), 3);
+}
+
+#[test]
+fn withdrawal_dispute_transitions_are_exhaustive() {
+    use TransactionStatus::*;
+    use TxOutcome::*;
+
+    let dispute = TransactionInput::Dispute(2, 1);
+    let resolve = TransactionInput::Resolve(2, 1);
+    let chargeback = TransactionInput::Chargeback(2, 1);
+    // inputs reaching each status of withdrawal 2, from 1.0000 deposited and 0.4000 withdrawn
+    let setups: [(TransactionStatus, &[TransactionInput]); 4] = [
+        (Normal, &[]),
+        (Disputed, &[dispute]),
+        (Solved(false), &[dispute, resolve]),
+        (Solved(true), &[dispute, chargeback]),
+    ];
+    // (from, input, allow_redispute) -> (outcome, to, available, held)
+    let matrix = [
+        (Normal, dispute, false, (Applied, Disputed, 6_000, 4_000)),
+        (
+            Normal,
+            resolve,
+            false,
+            (IgnoredInvalidTransition, Normal, 6_000, 0),
+        ),
+        (
+            Normal,
+            chargeback,
+            false,
+            (IgnoredInvalidTransition, Normal, 6_000, 0),
+        ),
+        (
+            Disputed,
+            dispute,
+            false,
+            (IgnoredInvalidTransition, Disputed, 6_000, 4_000),
+        ),
+        (Disputed, resolve, false, (Applied, Solved(false), 6_000, 0)),
+        (
+            Disputed,
+            chargeback,
+            false,
+            (Applied, Solved(true), 10_000, 0),
+        ),
+        (
+            Solved(false),
+            dispute,
+            false,
+            (IgnoredInvalidTransition, Solved(false), 6_000, 0),
+        ),
+        (
+            Solved(false),
+            dispute,
+            true,
+            (Applied, Disputed, 6_000, 4_000),
+        ),
+        (
+            Solved(false),
+            resolve,
+            false,
+            (IgnoredInvalidTransition, Solved(false), 6_000, 0),
+        ),
+        (
+            Solved(false),
+            chargeback,
+            false,
+            (IgnoredInvalidTransition, Solved(false), 6_000, 0),
+        ),
+        (
+            Solved(true),
+            dispute,
+            false,
+            (IgnoredLocked, Solved(true), 10_000, 0),
+        ),
+        (
+            Solved(true),
+            dispute,
+            true,
+            (IgnoredLocked, Solved(true), 10_000, 0),
+        ),
+        (
+            Solved(true),
+            resolve,
+            false,
+            (IgnoredInvalidTransition, Solved(true), 10_000, 0),
+        ),
+        (
+            Solved(true),
+            chargeback,
+            false,
+            (IgnoredInvalidTransition, Solved(true), 10_000, 0),
+        ),
+    ];
+
+    for (from, input, allow_redispute, (outcome, to, available, held)) in matrix {
+        let mut engine = PaymentEngine::new().with_rules(TxRules {
+            dispute_withdrawals: true,
+            allow_redispute,
+            ..TxRules::default()
+        });
+        engine
+            .apply(TransactionInput::Deposit(1, 1, 10_000))
+            .unwrap();
+        engine
+            .apply(TransactionInput::Withdrawal(2, 1, 4_000))
+            .unwrap();
+        let (_, setup) = setups.iter().find(|(status, _)| *status == from).unwrap();
+        for step in *setup {
+            assert_eq!(engine.apply(*step).unwrap(), Applied);
+        }
+
+        let context = format!(
+            "{:?} from {:?}, allow_redispute {}",
+            input, from, allow_redispute
+        );
+        assert_eq!(engine.apply(input).unwrap(), outcome, "{}", context);
+        let user = engine.get(1).unwrap();
+        assert_eq!(user.transactions[&2].status, to, "{}", context);
+        let balance = user.balance();
+        assert_eq!(
+            (balance.available, balance.held),
+            (available, held),
+            "{}",
+            context
+        );
+        assert_eq!(
+            engine.grand_total_ticks(),
+            engine.net_flow_ticks(),
+            "{}",
+            context
+        );
+        engine.verify().unwrap();
+    }
+}
+
+#[test]
+fn withdrawals_are_not_disputable_by_default() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    engine
+        .apply(TransactionInput::Withdrawal(2, 1, 4_000))
+        .unwrap();
+    assert_eq!(
+        engine.apply(TransactionInput::Dispute(2, 1)).unwrap(),
+        TxOutcome::IgnoredInvalidTransition
+    );
+}
+
+#[test]
+fn withdrawal_disputed_and_resolved_twice_releases_held_once() {
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        dispute_withdrawals: true,
+        ..TxRules::default()
+    });
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    engine
+        .apply(TransactionInput::Withdrawal(2, 1, 4_000))
+        .unwrap();
+
+    let expected = [TxOutcome::Applied, TxOutcome::IgnoredInvalidTransition];
+    for outcome in expected {
+        assert_eq!(
+            engine.apply(TransactionInput::Dispute(2, 1)).unwrap(),
+            outcome
+        );
+        assert_eq!(
+            engine.apply(TransactionInput::Resolve(2, 1)).unwrap(),
+            outcome
+        );
+        let balance = engine.get(1).unwrap().balance();
+        assert_eq!((balance.available, balance.held), (6_000, 0));
+    }
+    engine.verify().unwrap();
+}
+
+#[test]
+fn held_funds_are_never_withdrawable() {
+    let rules = TxRules {
+        dispute_withdrawals: true,
+        allow_redispute: true,
+        ..TxRules::default()
+    };
+    // deposits 1 and 2 and withdrawal 3, then each dispute state -> (available, held)
+    let states: [(&[TransactionInput], (i128, i128)); 5] = [
+        (&[TransactionInput::Dispute(1, 1)], (1_000, 10_000)),
+        (&[TransactionInput::Dispute(3, 1)], (11_000, 4_000)),
+        (
+            &[
+                TransactionInput::Dispute(1, 1),
+                TransactionInput::Dispute(3, 1),
+            ],
+            (1_000, 14_000),
+        ),
+        (
+            &[
+                TransactionInput::Dispute(1, 1),
+                TransactionInput::Resolve(1, 1),
+                TransactionInput::Dispute(1, 1),
+            ],
+            (1_000, 10_000),
+        ),
+        (
+            &[
+                TransactionInput::Dispute(3, 1),
+                TransactionInput::Resolve(3, 1),
+                TransactionInput::Dispute(3, 1),
+            ],
+            (11_000, 4_000),
+        ),
+    ];
+    for (disputes, (available, held)) in states {
+        let mut engine = PaymentEngine::new().with_rules(rules);
+        for input in [
+            TransactionInput::Deposit(1, 1, 10_000),
+            TransactionInput::Deposit(2, 1, 5_000),
+            TransactionInput::Withdrawal(3, 1, 4_000),
+        ]
+        .iter()
+        .chain(disputes)
+        {
+            assert_eq!(engine.apply(*input).unwrap(), TxOutcome::Applied);
+        }
+        let balance = engine.get(1).unwrap().balance();
+        assert_eq!((balance.available, balance.held), (available, held));
+
+        // total would cover it, available doesn't
+        assert_eq!(
+            engine
+                .apply(TransactionInput::Withdrawal(4, 1, available as i32 + 1))
+                .unwrap(),
+            TxOutcome::IgnoredInsufficientFunds,
+            "{:?}",
+            disputes
+        );
+        assert_eq!(
+            engine
+                .apply(TransactionInput::Withdrawal(5, 1, available as i32))
+                .unwrap(),
+            TxOutcome::Applied
+        );
+        assert_eq!(
+            engine.apply(TransactionInput::Withdrawal(6, 1, 1)).unwrap(),
+            TxOutcome::IgnoredInsufficientFunds
+        );
+        let balance = engine.get(1).unwrap().balance();
+        assert_eq!(
+            (balance.available, balance.held),
+            (0, held),
+            "{:?}",
+            disputes
+        );
+        engine.verify().unwrap();
+    }
+}
+
+#[test]
+fn withdrawal_of_disputed_deposit_is_rejected_despite_total() {
+    let mut user = User::new(1);
+    user.process_tx_input(TransactionInput::Deposit(1, 1, 1_000_000), None)
+        .unwrap();
+    user.process_tx_input(TransactionInput::Dispute(1, 1), None)
+        .unwrap();
+    let balance = user.balance();
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (0, 1_000_000, 1_000_000)
+    );
+
+    // 50 fits in the total of 100, but nothing is available
+    assert_eq!(
+        user.process_tx_input(TransactionInput::Withdrawal(2, 1, 500_000), None)
+            .unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    let balance = user.balance();
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (0, 1_000_000, 1_000_000)
+    );
+    // the cached balances agree with the replayed ones
+    user.verify().unwrap();
+}
+
+#[test]
+fn engine_is_built_from_an_in_memory_csv_body() {
+    let engine = PaymentEngine::from_csv_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 2, 2, 2.0
+withdrawal, 1, 3, 0.5
+dispute, 2, 2,
+",
+    )
+    .unwrap();
+    let balances: Vec<_> = engine.users().map(|user| user.balance()).collect();
+    assert_eq!(
+        balances
+            .iter()
+            .map(|balance| (balance.client, balance.available, balance.held))
+            .collect::<Vec<_>>(),
+        [(1, 10_000, 0), (2, 0, 20_000)]
+    );
+
+    // headerless and semicolon delimited bodies are sniffed as files are
+    let engine = PaymentEngine::from_csv_str("deposit;3;1;2.5\n").unwrap();
+    assert_eq!(engine.get(3).unwrap().balance().available, 25_000);
+
+    let Err(err) = PaymentEngine::from_csv_str("type,client,tx,amount\ndeposit,1,1,abc\n") else {
+        panic!("invalid amounts fail");
+    };
+    assert!(err.to_string().starts_with("Line 2: "), "{}", err);
+}
+
+fn balances_of(engine: &PaymentEngine) -> Vec<(u16, i128, i128, bool)> {
+    engine
+        .balances()
+        .map(|balance| {
+            (
+                balance.client,
+                balance.available,
+                balance.held,
+                balance.locked,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn merging_disjoint_shards_matches_a_single_run() {
+    let first = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+withdrawal,1,3,1.0
+dispute,2,2,
+chargeback,2,2,
+";
+    let second = "type,client,tx,amount
+deposit,3,4,2.0
+deposit,3,5,1.0
+dispute,3,4,
+dispute,3,5,
+resolve,3,5,
+";
+    let whole = format!("{}{}", first, second.split_once('\n').unwrap().1);
+    let mut engine = PaymentEngine::from_csv_str(first).unwrap();
+    engine
+        .merge(PaymentEngine::from_csv_str(second).unwrap())
+        .unwrap();
+    assert_eq!(
+        balances_of(&engine),
+        balances_of(&PaymentEngine::from_csv_str(&whole).unwrap())
+    );
+    engine.verify().unwrap();
+
+    // clients only known to the other engine are moved in as they are
+    let moved = engine.get(3).unwrap();
+    assert_eq!(
+        (moved.first_seen_line(), moved.last_seen_line()),
+        (Some(2), Some(6))
+    );
+}
+
+#[test]
+fn merging_replays_shared_clients_by_tx_id() {
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 100_000))
+        .unwrap();
+    let mut other = PaymentEngine::new();
+    for tx in [
+        TransactionInput::Deposit(2, 1, 30_000),
+        TransactionInput::Withdrawal(3, 1, 10_000),
+        TransactionInput::Deposit(4, 1, 50_000),
+        TransactionInput::Dispute(4, 1),
+        TransactionInput::Deposit(5, 1, 20_000),
+        TransactionInput::Dispute(5, 1),
+        TransactionInput::Chargeback(5, 1),
+    ] {
+        other.apply(tx).unwrap();
+    }
+    engine.merge(other).unwrap();
+    assert_eq!(balances_of(&engine), [(1, 120_000, 50_000, true)]);
+    let statuses: Vec<_> = engine
+        .get(1)
+        .unwrap()
+        .transactions_sorted()
+        .map(|tx| (tx.id, tx.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            (1, TransactionStatus::Normal),
+            (2, TransactionStatus::Normal),
+            (3, TransactionStatus::Normal),
+            (4, TransactionStatus::Disputed),
+            (5, TransactionStatus::Solved(true)),
+        ]
+    );
+    engine.verify().unwrap();
+}
+
+#[test]
+fn merging_keeps_this_engine_on_shared_tx_ids() {
+    let merged = |rules: TxRules| {
+        let mut engine = PaymentEngine::new().with_rules(rules);
+        engine
+            .apply(TransactionInput::Deposit(1, 1, 10_000))
+            .unwrap();
+        let mut other = PaymentEngine::new();
+        other
+            .apply(TransactionInput::Deposit(1, 2, 20_000))
+            .unwrap();
+        other
+            .apply(TransactionInput::Deposit(2, 2, 30_000))
+            .unwrap();
+        other.apply(TransactionInput::Dispute(1, 2)).unwrap();
+        let result = engine.merge(other);
+        (engine, result)
+    };
+
+    // client 2 shares tx 1 with client 1, so it's replayed, its copy of tx 1 and the dispute
+    // of it being ignored
+    let (engine, result) = merged(TxRules {
+        on_conflicting_duplicate: ViolationPolicy::Warn,
+        ..TxRules::default()
+    });
+    result.unwrap();
+    assert_eq!(
+        balances_of(&engine),
+        [(1, 10_000, 0, false), (2, 30_000, 0, false)]
+    );
+    assert_eq!(engine.warnings().len(), 1);
+    assert_eq!(engine.warnings()[0].tx_id, 1);
+
+    // failing on duplicates leaves the engine untouched
+    let (engine, result) = merged(TxRules {
+        on_conflicting_duplicate: ViolationPolicy::Error,
+        ..TxRules::default()
+    });
+    assert!(matches!(result, Err(AppError::InvalidRecord(_))));
+    assert_eq!(balances_of(&engine), [(1, 10_000, 0, false)]);
+}
+
+#[test]
+fn rejected_withdrawals_report_their_shortfall() {
+    let mut engine = PaymentEngine::new()
+        .with_rules(TxRules {
+            min_balance: Amount::from_ticks(5_000),
+            ..TxRules::default()
+        })
+        .with_withdrawal_report();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 30_000))
+        .unwrap();
+    engine.apply(TransactionInput::Dispute(1, 1)).unwrap();
+    engine
+        .apply(TransactionInput::Deposit(2, 1, 20_000))
+        .unwrap();
+    // 2.0 available, of which 0.5 must stay, the disputed 3.0 being held
+    assert_eq!(
+        engine
+            .apply_at_line(TransactionInput::Withdrawal(3, 1, 22_500), 4)
+            .unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Withdrawal(4, 1, 15_000))
+            .unwrap(),
+        TxOutcome::Applied
+    );
+    assert_eq!(
+        engine.apply(TransactionInput::Withdrawal(5, 1, 1)).unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    let rejected: Vec<String> = engine
+        .rejected_withdrawals()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        rejected,
+        [
+            "line 4: withdrawal tx 3 of client 1 short by 0.7500, rejected",
+            "line -: withdrawal tx 5 of client 1 short by 0.0001, rejected",
+        ]
+    );
+    assert_eq!(
+        engine.rejected_withdrawals()[0].shortfall,
+        Amount::from_ticks(7_500)
+    );
+
+    // rolled back along with their batch
+    let mut strict = PaymentEngine::new().with_strict().with_withdrawal_report();
+    let batch = vec![
+        TransactionInput::Deposit(1, 1, 10_000),
+        TransactionInput::Withdrawal(2, 1, 20_000),
+    ];
+    assert!(strict.apply_batch(batch, true).is_err());
+    assert!(strict.rejected_withdrawals().is_empty());
+
+    // nothing is recorded unless asked
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(TransactionInput::Withdrawal(1, 1, 10_000))
+        .unwrap();
+    assert!(engine.rejected_withdrawals().is_empty());
+}
+
+#[test]
+fn global_dedupe_makes_reprocessing_idempotent() {
+    let file = [
+        TransactionInput::Deposit(1, 1, 10_000),
+        // rejected, then retried once tx 3 funds it
+        TransactionInput::Withdrawal(2, 1, 50_000),
+        TransactionInput::Deposit(3, 1, 100_000),
+        TransactionInput::Dispute(1, 1),
+        TransactionInput::Resolve(1, 1),
+        TransactionInput::Deposit(4, 2, 20_000),
+        TransactionInput::Dispute(4, 2),
+    ];
+    let rules = TxRules {
+        allow_redispute: true,
+        ..TxRules::default()
+    };
+    let run = |mut engine: PaymentEngine, passes: usize| {
+        for tx in file.iter().cycle().take(file.len() * passes) {
+            engine.apply(*tx).unwrap();
+        }
+        engine.verify().unwrap();
+        engine.balances().collect::<Vec<_>>()
+    };
+
+    let once = run(PaymentEngine::new().with_rules(rules), 1);
+    assert_eq!(run(PaymentEngine::new().with_rules(rules), 1), once);
+    // by default, file + file retries the withdrawal and disputes tx 1 again
+    assert_ne!(run(PaymentEngine::new().with_rules(rules), 2), once);
+    let deduped = || PaymentEngine::new().with_rules(rules).with_global_dedupe();
+    assert_eq!(run(deduped(), 2), once);
+
+    let mut engine = deduped();
+    for tx in file {
+        engine.apply(tx).unwrap();
+    }
+    for tx in file {
+        assert_eq!(engine.apply(tx).unwrap(), TxOutcome::IgnoredDuplicate);
+    }
+    assert!(engine.warnings().is_empty());
+}
+
+#[test]
+fn captured_authorization_settles_as_a_withdrawal() {
+    let mut engine = PaymentEngine::from_csv_str(
+        "type,client,tx,amount
+deposit,1,1,10.0
+authorization,1,2,4.0
+",
+    )
+    .unwrap();
+    let balance = engine.get(1).unwrap().balance();
+    // the authorized funds are held, out of reach of withdrawals
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (60_000, 40_000, 100_000)
+    );
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Withdrawal(3, 1, 70_000))
+            .unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    assert_eq!(engine.net_flow_ticks(), engine.grand_total_ticks());
+
+    assert_eq!(
+        engine.apply(TransactionInput::Capture(2, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (60_000, 0, 60_000)
+    );
+    assert_eq!(
+        engine.get(1).unwrap().transactions[&2].status,
+        TransactionStatus::Normal
+    );
+    // captured once
+    assert_eq!(
+        engine.apply(TransactionInput::Capture(2, 1)).unwrap(),
+        TxOutcome::IgnoredInvalidTransition
+    );
+    engine.verify().unwrap();
+    assert_eq!(engine.net_flow_ticks(), engine.grand_total_ticks());
+}
+
+#[test]
+fn uncaptured_authorization_expires() {
+    let rules = TxRules {
+        authorization_expiry: Some(2),
+        ..TxRules::default()
+    };
+    let mut engine = PaymentEngine::new().with_rules(rules);
+    for tx in [
+        TransactionInput::Deposit(1, 1, 100_000),
+        TransactionInput::Authorization(2, 1, 40_000),
+        TransactionInput::Authorization(3, 1, 10_000),
+        // within the 2 inputs following it, so tx 3 is captured in time
+        TransactionInput::Capture(3, 1),
+    ] {
+        assert_eq!(engine.apply(tx).unwrap(), TxOutcome::Applied);
+    }
+    assert_eq!(
+        engine.get(1).unwrap().transactions[&2].status,
+        TransactionStatus::Authorized
+    );
+
+    // 2 inputs after it, tx 2 expires ahead of its late capture
+    assert_eq!(
+        engine.apply(TransactionInput::Capture(2, 1)).unwrap(),
+        TxOutcome::IgnoredInvalidTransition
+    );
+    let user = engine.get(1).unwrap();
+    assert_eq!(user.transactions[&2].status, TransactionStatus::Expired);
+    let balance = user.balance();
+    assert_eq!(
+        (balance.available, balance.held, balance.total),
+        (90_000, 0, 90_000)
+    );
+    engine.verify().unwrap();
+    assert_eq!(engine.net_flow_ticks(), engine.grand_total_ticks());
+}
+
+#[test]
+fn atomic_batch_rolls_back_expiries_outside_it() {
+    let rules = TxRules {
+        authorization_expiry: Some(1),
+        ..TxRules::default()
+    };
+    let mut engine = PaymentEngine::new().with_rules(rules).with_strict();
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 100_000))
+        .unwrap();
+    engine
+        .apply(TransactionInput::Authorization(2, 1, 40_000))
+        .unwrap();
+    // client 1 isn't part of the batch, but tx 2 expires while it's applied
+    let batch = vec![
+        TransactionInput::Deposit(3, 2, 5_000),
+        TransactionInput::Withdrawal(4, 2, 50_000),
+    ];
+    assert!(engine.apply_batch(batch, true).is_err());
+
+    let user = engine.get(1).unwrap();
+    assert_eq!(
+        user.transaction_status(2),
+        Some(TransactionStatus::Authorized)
+    );
+    let balance = user.balance();
+    assert_eq!((balance.available, balance.held), (60_000, 40_000));
+    // still expiring once the clock moves past its deadline again
+    for tx in [
+        TransactionInput::Deposit(3, 2, 5_000),
+        TransactionInput::Deposit(4, 2, 5_000),
+    ] {
+        engine.apply(tx).unwrap();
+    }
+    assert_eq!(
+        engine.get(1).unwrap().transaction_status(2),
+        Some(TransactionStatus::Expired)
+    );
+}
+
+/// engine restored from a snapshot of client 1 holding `txs`, as `tx,<id>,1,<side>,<status>,<amount>`
+fn corrupted_engine(txs: &[&str]) -> PaymentEngine {
+    let mut snapshot = "snapshot,1,0\nuser,1,false\n".to_string();
+    for tx in txs {
+        snapshot.push_str(&format!("tx,{}\n", tx));
+    }
+    PaymentEngine::load_snapshot(snapshot.as_bytes()).unwrap().0
+}
+
+#[test]
+fn consistent_ledger_verifies_its_invariants() {
+    let engine = PaymentEngine::from_csv_str(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         withdrawal,1,2,4.0\n\
+         deposit,2,3,5.0\n\
+         dispute,2,3,\n",
+    )
+    .unwrap();
+    assert!(engine.invariant_violations().is_empty());
+    engine.verify_invariants().unwrap();
+}
+
+#[test]
+fn negative_total_violates_its_invariant() {
+    let engine = corrupted_engine(&["1,1,deposit,normal,100000", "2,1,withdrawal,normal,150000"]);
+    assert_eq!(
+        engine.invariant_violations(),
+        [InvariantViolation::NegativeTotal {
+            client: 1,
+            total: Amount::from_ticks(-50_000),
+        }]
+    );
+    let err = engine.verify_invariants().unwrap_err();
+    assert_eq!(err.exit_code(), 5);
+    assert!(
+        err.to_string()
+            .contains("client 1: total funds are negative")
+    );
+}
+
+#[test]
+fn negative_held_violates_its_invariant() {
+    let engine = corrupted_engine(&["1,1,deposit,normal,100000", "2,1,deposit,disputed,-30000"]);
+    assert_eq!(
+        engine.invariant_violations(),
+        [InvariantViolation::NegativeHeld {
+            client: 1,
+            held: Amount::from_ticks(-30_000),
+        }]
+    );
+}
+
+#[test]
+fn drifted_balances_violate_their_invariant() {
+    let mut user = User::new(1);
+    user.process_tx_input(TransactionInput::Deposit(1, 1, 100_000), None)
+        .unwrap();
+    // disputed behind the cached balances' back
+    user.transactions.get_mut(&1).unwrap().status = TransactionStatus::Disputed;
+    let violations = user.invariant_violations();
+    assert!(matches!(
+        violations.as_slice(),
+        [InvariantViolation::BalanceMismatch { cached, recomputed }]
+            if (cached.available, cached.held) == (100_000, 0)
+                && (recomputed.available, recomputed.held) == (0, 100_000)
+    ));
+}
+
+#[test]
+fn grand_total_off_the_net_flows_violates_its_invariant() {
+    // deposits are never authorized, which replays as held but flows in as a deposit
+    let engine = corrupted_engine(&["1,1,deposit,normal,100000", "2,1,deposit,authorized,30000"]);
+    assert_eq!(
+        engine.invariant_violations(),
+        [InvariantViolation::NetFlowMismatch {
+            grand_total: Amount::from_ticks(100_000),
+            net_flows: Amount::from_ticks(130_000),
+        }]
+    );
+}
@@ -0,0 +1,107 @@
+use std::io;
+
+use csv::{ReaderBuilder, StringRecord};
+use csv_ledger::{Amount, AppError, ClientBalance, Config, InvariantViolation, TransactionInput};
+
+#[test]
+fn out_of_range_amount_surfaces_range_error() {
+    // 300_000.0 is 3_000_000_000 ticks, which does not fit an i32
+    let record = StringRecord::from(vec!["deposit", "1", "1", "300000.0"]);
+    let result = TransactionInput::try_from_string_record(record);
+    let err = result.unwrap_err();
+    assert!(matches!(err, AppError::RangeError(_)), "{}", err);
+    assert_eq!(
+        err.to_string(),
+        r#"Range error: amount "300000.0" must be within -214748.3648 and 214748.3647"#
+    );
+}
+
+/// exit code of `err`'s category, matching every variant so a new one can't go unlisted
+fn category_exit_code(err: &AppError) -> i32 {
+    match err {
+        AppError::IoError(_) => 1,
+        AppError::CsvError(err) if err.is_io_error() => 1,
+        AppError::MissingArgument | AppError::InvalidArgument(_) => 2,
+        AppError::FileNotFound(_) => 3,
+        AppError::InvalidFormat(_)
+        | AppError::InvalidRecord(_)
+        | AppError::InvalidTxType(_)
+        | AppError::CsvError(_)
+        | AppError::ParseInt(_)
+        | AppError::ParseFloat(_)
+        | AppError::RangeError(_)
+        | AppError::TooManyErrors(_)
+        | AppError::SkippedRecords(_) => 4,
+        AppError::BalanceMismatch { .. } | AppError::InvariantViolations(_) => 5,
+        AppError::Interrupted(_) => 130,
+        AppError::AtLine { source, .. } => category_exit_code(source),
+    }
+}
+
+#[test]
+fn every_error_maps_to_the_exit_code_of_its_category() {
+    let balance = ClientBalance {
+        client: 1,
+        available: 0,
+        held: 0,
+        total: 0,
+        locked: false,
+        held_tx_ids: None,
+        open_disputes: None,
+        currency: None,
+    };
+    let ragged = ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader("a,b\nc\n".as_bytes())
+        .records()
+        .find_map(Result::err)
+        .unwrap();
+    let cases = [
+        (AppError::IoError(io::Error::other("disk full")), 1),
+        (AppError::CsvError(io::Error::other("disk full").into()), 1),
+        (AppError::MissingArgument, 2),
+        (AppError::InvalidArgument("--format".to_string()), 2),
+        (AppError::FileNotFound("in.csv".to_string()), 3),
+        (AppError::InvalidFormat("header".to_string()), 4),
+        (AppError::InvalidRecord("deposit".to_string()), 4),
+        (AppError::InvalidTxType("deposits".to_string()), 4),
+        (AppError::CsvError(ragged), 4),
+        (AppError::ParseInt("x".parse::<u8>().unwrap_err()), 4),
+        (AppError::ParseFloat("x".parse::<f64>().unwrap_err()), 4),
+        (AppError::from(u8::try_from(300).unwrap_err()), 4),
+        (AppError::TooManyErrors(3), 4),
+        (AppError::SkippedRecords(3), 4),
+        (
+            AppError::BalanceMismatch {
+                cached: Box::new(balance.clone()),
+                recomputed: Box::new(balance),
+            },
+            5,
+        ),
+        (
+            AppError::InvariantViolations(vec![InvariantViolation::NegativeHeld {
+                client: 1,
+                held: Amount::from(-1),
+            }]),
+            5,
+        ),
+        (AppError::Interrupted(3), 130),
+        (AppError::InvalidTxType("x".to_string()).with_line(7), 4),
+        (AppError::FileNotFound("in.csv".to_string()).with_line(7), 3),
+    ];
+    for (err, code) in cases {
+        assert_eq!(category_exit_code(&err), code, "{:?}", err);
+        assert_eq!(err.exit_code(), code, "{:?}", err);
+    }
+}
+
+#[test]
+fn unparsable_flag_values_are_usage_errors() {
+    for args in [
+        ["csv_ledger", "--max-errors", "x", "in.csv"],
+        ["csv_ledger", "--min-balance", "1.5", "in.csv"],
+    ] {
+        let err = Config::from_args(args.map(String::from)).unwrap_err();
+        assert_eq!(err.exit_code(), 2, "{}", err);
+    }
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use common::run_with_args;
+use csv_ledger::{GenerateOptions, TxMix, generate};
+
+fn options(args: &[&str]) -> GenerateOptions {
+    GenerateOptions::from_args(["generate"].iter().chain(args).map(|arg| arg.to_string())).unwrap()
+}
+
+fn generated(options: &GenerateOptions) -> String {
+    let mut output = Vec::new();
+    generate(&mut output, options).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn fixed_seed_generates_byte_stable_output() {
+    let options = options(&[
+        "--clients",
+        "3",
+        "--rows",
+        "16",
+        "--seed",
+        "42",
+        "--mix",
+        "40,20,20,10,10",
+    ]);
+    assert_eq!(
+        generated(&options),
+        "type,client,tx,amount
+deposit,2,1,32.0955
+deposit,1,2,27.5428
+dispute,1,2,
+deposit,1,4,41.8484
+deposit,2,5,99.8512
+dispute,1,4,
+deposit,2,7,53.4357
+deposit,1,8,85.3730
+deposit,3,9,23.5347
+deposit,1,10,18.8860
+withdrawal,3,11,23.5347
+deposit,3,12,90.3505
+resolve,1,4,
+deposit,2,14,60.2598
+dispute,3,12,
+deposit,2,16,45.4437
+"
+    );
+    assert_ne!(
+        generated(&GenerateOptions {
+            seed: 43,
+            ..options.clone()
+        }),
+        generated(&options)
+    );
+}
+
+#[test]
+fn generated_output_replays_strictly() {
+    let options = options(&[
+        "--clients",
+        "20",
+        "--rows",
+        "5000",
+        "--seed",
+        "7",
+        "--mix",
+        "40,30,15,8,7",
+    ]);
+    let input = generated(&options);
+    assert_eq!(input.lines().count(), 5001);
+    assert!(input.contains("chargeback"));
+
+    let output = run_with_args("generated_replay", &input, &["--strict"]).unwrap();
+    assert_eq!(output.lines().count(), 21);
+    // one client is always left unlocked
+    assert!(output.lines().any(|line| line.ends_with(",false")));
+}
+
+#[test]
+fn invalid_generate_options_are_rejected() {
+    for args in [
+        &["--clients", "0"][..],
+        &["--mix", "1,2,3"],
+        &["--mix", "0,0,0,0,0"],
+        &["--rows"],
+        &["--unknown"],
+    ] {
+        assert!(
+            GenerateOptions::from_args(["generate"].iter().chain(args).map(|arg| arg.to_string()))
+                .is_err(),
+            "{args:?}"
+        );
+    }
+    assert_eq!(
+        "1,0,0,0,2".parse::<TxMix>().unwrap(),
+        TxMix {
+            deposits: 1,
+            withdrawals: 0,
+            disputes: 0,
+            resolves: 0,
+            chargebacks: 2,
+        }
+    );
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use std::fs;
+use std::io::Read;
+
+use common::{run_with_args, write_temp};
+use csv_ledger::{AppError, validate_buff};
+
+#[test]
+fn require_header_rejects_headerless_file() {
+    let input = "deposit,1,1,1.0\n";
+    let result = run_with_args("require_header", input, &["--require-header"]);
+    assert!(matches!(result, Err(AppError::InvalidFormat(reason)) if reason == "missing header"));
+}
+
+#[test]
+fn headerless_file_is_processed_positionally_by_default() {
+    let input = "deposit,1,1,1.0\n";
+    let output = run_with_args("headerless_default", input, &[]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn reordered_header_is_read_by_name() {
+    let input = "client,type,tx,amount
+1,deposit,1,2.0
+1,deposit,3,1.0
+1,withdrawal,2,0.5
+1,dispute,3,
+";
+    let output = run_with_args("reordered_header", input, &["--require-header"]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,1.5000,1.0000,2.5000,false\n"
+    );
+}
+
+#[test]
+fn extra_header_column_is_ignored() {
+    let input = "type,client,tx,amount,timestamp
+deposit,1,1,2.0,1700000000
+withdrawal,1,2,0.5,1700000001
+";
+    let output = run_with_args("extra_header_column", input, &["--require-header"]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+}
+
+#[test]
+fn empty_and_header_only_files_print_only_the_header() {
+    for (name, input) in [
+        ("empty", ""),
+        ("header_only", "type,client,tx,amount\n"),
+        ("header_only_no_newline", "type,client,tx,amount"),
+        ("blank_line", "\n"),
+    ] {
+        let output = run_with_args(name, input, &[]).unwrap();
+        assert_eq!(output, "client,available,held,total,locked\n", "{}", name);
+    }
+}
+
+#[test]
+fn dispute_rows_may_omit_the_amount_column() {
+    let input = "type,client,tx,amount
+deposit,1,5,10.0
+dispute,1,5
+resolve,1,5,
+dispute,1,5
+";
+    let output = run_with_args("omitted_amount", input, &["--allow-redispute"]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n"
+    );
+}
+
+#[test]
+fn buffer_info_reports_header_delimiter_and_bom() {
+    let path = write_temp(
+        "buffer_info_bom",
+        "\u{feff}type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(),
+    );
+    let mut buffer = validate_buff(path.to_str().unwrap()).unwrap();
+    assert!(buffer.has_headers);
+    assert_eq!(buffer.delimiter, b',');
+    assert!(buffer.had_bom);
+    let mut contents = String::new();
+    buffer.file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "type,client,tx,amount\ndeposit,1,1,1.0\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn semicolon_delimited_file_is_sniffed() {
+    let input = "type;client;tx;amount\ndeposit;1;1;1.5\nwithdrawal;1;2;0.5\n";
+    let output = run_with_args("semicolon_delimited", input, &[]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn crlf_and_lone_cr_headers_are_detected() {
+    for (name, input) in [
+        (
+            "crlf_header",
+            "type,client,tx,amount\r\ndeposit,1,1,1.5\r\n",
+        ),
+        ("lone_cr_header", "type,client,tx,amount\rdeposit,1,1,1.5\r"),
+        (
+            "mixed_endings_header",
+            "type,client,tx,amount\r\ndeposit,1,1,1.0\ndeposit,1,2,0.5\r",
+        ),
+    ] {
+        let path = write_temp(name, input.as_bytes());
+        let buffer = validate_buff(path.to_str().unwrap()).unwrap();
+        assert!(buffer.has_headers, "{name}");
+        assert_eq!(buffer.delimiter, b',', "{name}");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            run_with_args(name, input, &["--require-header"]).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n",
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn tab_padded_header_is_detected_with_its_delimiter() {
+    let path = write_temp(
+        "tab_padded_header",
+        b"type,\tclient,\ttx,\tamount\t\ndeposit,1,1,1.5\n",
+    );
+    let buffer = validate_buff(path.to_str().unwrap()).unwrap();
+    assert!(buffer.has_headers);
+    assert_eq!(buffer.delimiter, b',');
+    fs::remove_file(&path).unwrap();
+
+    let path = write_temp("tab_delimited_header", b"type\tclient\ttx\tamount\n");
+    let buffer = validate_buff(path.to_str().unwrap()).unwrap();
+    assert!(buffer.has_headers);
+    assert_eq!(buffer.delimiter, b'\t');
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn ragged_row_is_an_invalid_record_with_its_line() {
+    let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,1,000.50\n";
+    let err = run_with_args("ragged_row", input, &[]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 3: Invalid record for creating transaction: deposit,1,2,1,000.50: expected at most 4 fields, got 5"
+    );
+
+    let output = run_with_args("ragged_row_skipped", input, &["--max-errors", "2"]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,5.0000,0.0000,5.0000,false\n"
+    );
+}
+
+#[test]
+fn misspelled_header_suggests_the_expected_one() {
+    for (name, input, expected) in [
+        (
+            "typo_header",
+            "typ,client,tx,amount\ndeposit,1,1,1.0\n",
+            r#"header "typ,client,tx,amount" looks misspelled, expected "type,client,tx,amount""#,
+        ),
+        (
+            "typo_reordered_header",
+            "Client;Type;txn;amout\n1;deposit;1;1.0\n",
+            r#"header "Client;Type;txn;amout" looks misspelled, expected "client;type;tx;amount""#,
+        ),
+    ] {
+        let result = run_with_args(name, input, &[]);
+        assert!(
+            matches!(&result, Err(AppError::InvalidFormat(reason)) if reason == expected),
+            "{:?}",
+            result
+        );
+    }
+}
+
+#[test]
+fn data_rows_are_not_taken_for_misspelled_headers() {
+    let input = "deposit,1,1,1.0\nwithdrawal,1,2,0.5\n";
+    let output = run_with_args("not_a_typo", input, &[]).unwrap();
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.5000,0.0000,0.5000,false\n"
+    );
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use csv::ReaderBuilder;
+use std::fs;
+
+use common::write_temp;
+use csv_ledger::{
+    Config, PaymentEngine, Phase, ProcessOptions, Profile, TransactionType, TxOutcome,
+    process_records, run_with_diagnostics,
+};
+
+#[test]
+fn metrics_count_every_type_and_outcome() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,2,3,50.0
+withdrawal,1,4,1.0
+dispute,2,2,
+dispute,1,9,
+resolve,2,2,
+dispute,2,2,
+dispute,1,1,
+chargeback,1,1,
+chargeback,1,1,
+begin,,,
+deposit,1,5,1.0
+commit,,,
+begin,,,
+withdrawal,2,6,1.0
+rollback,,,
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new();
+    let metrics = process_records(&mut reader, &mut engine, &ProcessOptions::default())
+        .unwrap()
+        .metrics;
+
+    use TransactionType::*;
+    use TxOutcome::*;
+    assert_eq!(metrics.seen(Deposit), 4);
+    assert_eq!(metrics.outcome(Deposit, Applied), 2);
+    assert_eq!(metrics.outcome(Deposit, IgnoredDuplicate), 1);
+    assert_eq!(metrics.outcome(Deposit, IgnoredLocked), 1);
+    // the rolled back withdrawal is seen, but never applied
+    assert_eq!(metrics.seen(Withdrawal), 3);
+    assert_eq!(metrics.outcome(Withdrawal, Applied), 1);
+    assert_eq!(metrics.outcome(Withdrawal, IgnoredInsufficientFunds), 1);
+    assert_eq!(metrics.seen(Dispute), 4);
+    assert_eq!(metrics.outcome(Dispute, Applied), 2);
+    assert_eq!(metrics.outcome(Dispute, IgnoredMissingTx), 1);
+    assert_eq!(metrics.outcome(Dispute, IgnoredInvalidTransition), 1);
+    assert_eq!(metrics.seen(Resolve), 1);
+    assert_eq!(metrics.outcome(Resolve, Applied), 1);
+    assert_eq!(metrics.seen(Chargeback), 2);
+    assert_eq!(metrics.outcome(Chargeback, Applied), 1);
+    assert_eq!(metrics.outcome(Chargeback, IgnoredInvalidTransition), 1);
+}
+
+#[test]
+fn profile_reports_every_phase() {
+    let path = write_temp(
+        "profile.csv",
+        b"type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n",
+    );
+    let run = |options: &[&str]| {
+        let mut args = vec!["csv_ledger".to_string()];
+        args.extend(options.iter().map(|option| option.to_string()));
+        args.push(path.to_string_lossy().into_owned());
+        let config = Config::from_args(args).unwrap();
+        let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+        run_with_diagnostics(&config, &mut output, &mut diagnostics).unwrap();
+        String::from_utf8(diagnostics).unwrap()
+    };
+    let diagnostics = run(&["--profile"]);
+    let phases: Vec<&str> = diagnostics
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    assert_eq!(
+        phases,
+        ["profile reading", "profile parsing", "profile applying"]
+    );
+    assert_eq!(run(&[]), "");
+    fs::remove_file(&path).unwrap();
+
+    let mut profile = Profile::default();
+    assert_eq!(Profile::time(Some(&mut profile), Phase::Parsing, || 7), 7);
+    assert_eq!(Profile::time(None, Phase::Applying, || 8), 8);
+    assert_eq!(profile.elapsed(Phase::Applying), Default::default());
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::run_with_args;
+use csv_ledger::{AppError, parse_ndjson_line};
+
+#[test]
+fn ndjson_matches_equivalent_csv() {
+    let csv = "type,client,tx,amount
+deposit,1,1,1.5
+deposit,2,2,2.0
+withdrawal,1,3,0.5
+dispute,2,2,
+chargeback,2,2,
+";
+    let ndjson = r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}
+{"type":"deposit","client":2,"tx":2,"amount":"2.0"}
+
+{"tx":3,"client":1,"type":"withdrawal","amount":"0.5","note":"extra"}
+{"type":"dispute","client":2,"tx":2,"amount":null}
+{"type":"chargeback","client":2,"tx":2}
+"#;
+    let expected = run_with_args("ndjson_equivalent.csv", csv, &[]).unwrap();
+    let output = run_with_args(
+        "ndjson_equivalent.ndjson",
+        ndjson,
+        &["--input-format", "ndjson"],
+    )
+    .unwrap();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn ndjson_line_maps_to_positional_record() {
+    let record =
+        parse_ndjson_line(r#" { "amount" : 0.00005 , "type" : "deposit", "client": 7, "tx": 9 } "#)
+            .unwrap();
+    assert_eq!(
+        record.iter().collect::<Vec<_>>(),
+        ["deposit", "7", "9", "0.00005"]
+    );
+}
+
+#[test]
+fn malformed_ndjson_is_an_invalid_record() {
+    for line in [
+        r#"{"type":"deposit","client":1"#,
+        r#"{"type":"deposit","type":"withdrawal"}"#,
+        r#"{"type":deposit}"#,
+        r#"{"type":"deposit"} trailing"#,
+    ] {
+        assert!(
+            matches!(parse_ndjson_line(line), Err(AppError::InvalidRecord(_))),
+            "{}",
+            line
+        );
+    }
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs;
+
+use common::{run_with_args, write_temp};
+use csv_ledger::{Amount, AppError, PaymentEngine, TransactionInput, TxOutcome};
+
+fn opened(balances: &str) -> PaymentEngine {
+    let mut engine = PaymentEngine::new();
+    engine.load_opening_balances(balances.as_bytes()).unwrap();
+    engine
+}
+
+#[test]
+fn opening_balance_is_withdrawable() {
+    let balances = write_temp("opening_balances.csv", b"client,available\n1,500\n2, 1.5\n");
+    let output = run_with_args(
+        "opening_withdrawal",
+        "type,client,tx,amount\nwithdrawal,1,1,300\ndeposit,3,2,1.0\n",
+        &["--opening-balances", balances.to_str().unwrap()],
+    );
+    fs::remove_file(&balances).unwrap();
+    assert_eq!(
+        output.unwrap(),
+        "client,available,held,total,locked
+1,200.0000,0.0000,200.0000,false
+2,1.5000,0.0000,1.5000,false
+3,1.0000,0.0000,1.0000,false
+"
+    );
+}
+
+#[test]
+fn opening_balance_is_not_disputable() {
+    // columns are matched by name
+    let mut engine = opened("available,client\n5.0,1\n");
+    assert_eq!(
+        engine.get(1).unwrap().opening_balance(),
+        Amount::from_ticks(50_000)
+    );
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    assert_eq!(
+        engine
+            .apply(TransactionInput::Withdrawal(2, 1, 70_000))
+            .unwrap(),
+        TxOutcome::IgnoredInsufficientFunds
+    );
+    engine.apply(TransactionInput::Dispute(1, 1)).unwrap();
+    engine.apply(TransactionInput::Chargeback(1, 1)).unwrap();
+
+    // only the deposit is charged back, the opening funds staying available
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.available, balance.held), (50_000, 0));
+    engine.verify().unwrap();
+    assert_eq!(engine.net_flow_ticks(), engine.grand_total_ticks());
+}
+
+#[test]
+fn opening_balances_survive_snapshots_and_merges() {
+    let mut engine = opened("client,available\n1,2.0\n");
+    engine
+        .apply(TransactionInput::Withdrawal(1, 1, 5_000))
+        .unwrap();
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot, 1).unwrap();
+    let (restored, _) = PaymentEngine::load_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get(1).unwrap().balance().available, 15_000);
+    assert_eq!(
+        restored.get(1).unwrap().opening_balance(),
+        Amount::from_ticks(20_000)
+    );
+
+    // version 1 snapshots have no opening balances
+    let (legacy, _) = PaymentEngine::load_snapshot(
+        "snapshot,1,1\nuser,1,false\ntx,1,1,deposit,normal,10000\n".as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(legacy.get(1).unwrap().balance().available, 10_000);
+
+    // client 1 is replayed, its opening balances adding up ahead of its withdrawal
+    let mut merged = opened("client,available\n1,1.0\n");
+    merged.merge(restored).unwrap();
+    let mut other = opened("client,available\n2,3.0\n");
+    other
+        .apply(TransactionInput::Withdrawal(2, 2, 10_000))
+        .unwrap();
+    merged.merge(other).unwrap();
+    let balances: Vec<_> = merged
+        .balances()
+        .map(|balance| (balance.client, balance.available))
+        .collect();
+    assert_eq!(balances, [(1, 25_000), (2, 20_000)]);
+    merged.verify().unwrap();
+    assert_eq!(merged.net_flow_ticks(), merged.grand_total_ticks());
+}
+
+#[test]
+fn invalid_opening_balances_fail_with_their_line() {
+    for (balances, expected) in [
+        ("client,available\n1,1.0\n1,2.0\n", "Line 3: "),
+        ("client,available\n1,-1.0\n", "Line 2: "),
+        ("client,available\nx,1.0\n", "Line 2: "),
+    ] {
+        let mut engine = PaymentEngine::new();
+        let err = engine
+            .load_opening_balances(balances.as_bytes())
+            .unwrap_err();
+        assert!(err.to_string().starts_with(expected), "{}", err);
+    }
+    let mut engine = PaymentEngine::new();
+    let err = engine
+        .load_opening_balances("client,balance\n1,1.0\n".as_bytes())
+        .unwrap_err();
+    assert!(matches!(err, AppError::InvalidFormat(_)), "{}", err);
+
+    let err = run_with_args(
+        "opening_with_resume",
+        "type,client,tx,amount\n",
+        &[
+            "--opening-balances",
+            "balances.csv",
+            "--resume",
+            "state.snapshot",
+        ],
+    )
+    .unwrap_err();
+    assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+}
@@ -0,0 +1,362 @@
+mod common;
+
+use std::fs;
+
+use common::{run_with_args, write_temp};
+use csv_ledger::{AppError, Config, run_to_file};
+
+fn config(args: &[&str]) -> Config {
+    Config::from_args(["csv_ledger"].iter().chain(args).map(|arg| arg.to_string())).unwrap()
+}
+
+#[test]
+fn output_is_written_into_the_given_path() {
+    let input = write_temp(
+        "output_input.csv",
+        b"type,client,tx,amount\ndeposit,1,1,1.5\n",
+    );
+    let output = std::env::temp_dir().join(format!("csv_ledger_{}_output.csv", std::process::id()));
+    let config = config(&["-o", &output.to_string_lossy(), &input.to_string_lossy()]);
+    run_to_file(&config, config.output.as_deref().unwrap()).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+    // only the renamed output is left behind
+    let leftovers = fs::read_dir(output.parent().unwrap())
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with(&*output.file_name().unwrap().to_string_lossy())
+                && name.ends_with(".tmp")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+    fs::remove_file(&input).unwrap();
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn failed_run_keeps_the_previous_output() {
+    let input = write_temp(
+        "output_bad_input.csv",
+        b"type,client,tx,amount\ndeposit,1,1,abc\n",
+    );
+    let output = write_temp("output_previous.csv", b"previous");
+    let config = config(&[
+        "--output",
+        &output.to_string_lossy(),
+        &input.to_string_lossy(),
+    ]);
+    assert!(run_to_file(&config, config.output.as_deref().unwrap()).is_err());
+
+    assert_eq!(fs::read_to_string(&output).unwrap(), "previous");
+    fs::remove_file(&input).unwrap();
+    fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn locked_only_keeps_locked_clients_in_order() {
+    let input = "type,client,tx,amount
+deposit,3,1,1.0
+deposit,1,2,2.0
+deposit,2,3,3.0
+deposit,4,4,4.0
+dispute,3,1,
+chargeback,3,1,
+dispute,1,2,
+chargeback,1,2,
+";
+    assert_eq!(
+        run_with_args("locked_only_csv", input, &["--locked-only"]).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,true\n3,0.0000,0.0000,0.0000,true\n"
+    );
+    assert_eq!(
+        run_with_args(
+            "locked_only_json",
+            input,
+            &["--locked-only", "--format", "json"]
+        )
+        .unwrap(),
+        concat!(
+            r#"[{"client":1,"available":0.0000,"held":0.0000,"total":0.0000,"locked":true},"#,
+            r#"{"client":3,"available":0.0000,"held":0.0000,"total":0.0000,"locked":true}]"#,
+            "\n"
+        )
+    );
+}
+
+#[test]
+fn clients_filter_only_processes_the_given_clients() {
+    let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+deposit,5,3,3.0
+withdrawal,5,4,1.0
+dispute,9,2,
+deposit,9,5,4.0
+";
+    assert_eq!(
+        run_with_args("clients_filter", input, &["--clients", "5, 9,7"]).unwrap(),
+        "client,available,held,total,locked\n5,2.0000,0.0000,2.0000,false\n9,4.0000,0.0000,4.0000,false\n"
+    );
+
+    for clients in ["", "1,,2", "1,x", "70000"] {
+        let err =
+            run_with_args("clients_filter_invalid", input, &["--clients", clients]).unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
+
+#[test]
+fn denied_chargebacks_leave_accounts_unlocked() {
+    let input = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+dispute,1,1,
+chargeback,1,1,
+dispute,2,2,
+chargeback,2,2,
+";
+    let expected = "client,available,held,total,locked\n1,0.0000,5.0000,5.0000,false\n2,0.0000,3.0000,3.0000,false\n";
+    assert_eq!(
+        run_with_args("deny_types", input, &["--deny-types", "chargeback"]).unwrap(),
+        expected
+    );
+    assert_eq!(
+        run_with_args("allow_types", input, &["--allow-types", "deposit, dispute"]).unwrap(),
+        expected
+    );
+    assert_eq!(
+        run_with_args(
+            "deny_types_summary",
+            input,
+            &["--deny-types", "chargeback", "--summary-only"]
+        )
+        .unwrap(),
+        "clients,locked,total,held,filtered\n2,0,8.0000,8.0000,2\n"
+    );
+
+    for options in [
+        ["--deny-types", "refund", "--deny-types", "chargeback"],
+        ["--allow-types", "deposit", "--deny-types", "chargeback"],
+    ] {
+        let err = run_with_args("types_invalid", input, &options).unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
+
+#[test]
+fn open_disputes_counts_unresolved_disputes() {
+    let input = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,3.0
+deposit,2,3,1.0
+dispute,1,1,
+dispute,1,2,
+resolve,1,2,
+";
+    assert_eq!(
+        run_with_args("open_disputes_csv", input, &["--open-disputes"]).unwrap(),
+        "client,available,held,total,locked,open_disputes\n1,3.0000,5.0000,8.0000,false,1\n2,1.0000,0.0000,1.0000,false,0\n"
+    );
+    assert_eq!(
+        run_with_args(
+            "open_disputes_json",
+            input,
+            &["--open-disputes", "--format", "json", "--clients", "1"]
+        )
+        .unwrap(),
+        "[{\"client\":1,\"available\":3.0000,\"held\":5.0000,\"total\":8.0000,\"locked\":false,\"open_disputes\":1}]\n"
+    );
+
+    let err = run_with_args(
+        "open_disputes_summary",
+        input,
+        &["--open-disputes", "--summary-only"],
+    )
+    .unwrap_err();
+    assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+}
+
+#[test]
+fn units_render_money_as_decimals_or_ticks() {
+    let input = "type,client,tx,amount
+deposit,1,1,12.3456
+deposit,1,2,0.0001
+dispute,1,2,
+withdrawal,1,3,2.0
+";
+    assert_eq!(
+        run_with_args("units_default", input, &[]).unwrap(),
+        run_with_args("units_currency", input, &["--units", "currency"]).unwrap()
+    );
+    assert_eq!(
+        run_with_args("units_currency", input, &["--units", "currency"]).unwrap(),
+        "client,available,held,total,locked\n1,10.3456,0.0001,10.3457,false\n"
+    );
+    assert_eq!(
+        run_with_args("units_ticks", input, &["--units", "ticks"]).unwrap(),
+        "client,available,held,total,locked\n1,103456,1,103457,false\n"
+    );
+    assert_eq!(
+        run_with_args(
+            "units_ticks_json",
+            input,
+            &["--units", "ticks", "--format", "json"]
+        )
+        .unwrap(),
+        "[{\"client\":1,\"available\":103456,\"held\":1,\"total\":103457,\"locked\":false}]\n"
+    );
+    assert_eq!(
+        run_with_args(
+            "units_ticks_summary",
+            input,
+            &["--units", "ticks", "--summary-only"]
+        )
+        .unwrap(),
+        "clients,locked,total,held\n1,0,103457,1\n"
+    );
+
+    let err = run_with_args("units_invalid", input, &["--units", "cents"]).unwrap_err();
+    assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+}
+
+#[test]
+fn client_range_fills_gaps_with_zeroed_rows() {
+    let input = "type,client,tx,amount
+deposit,4,1,4.0
+deposit,2,2,2.0
+deposit,7,3,7.0
+";
+    assert_eq!(
+        run_with_args("client_range", input, &["--client-range", "1..5"]).unwrap(),
+        "client,available,held,total,locked
+1,0.0000,0.0000,0.0000,false
+2,2.0000,0.0000,2.0000,false
+3,0.0000,0.0000,0.0000,false
+4,4.0000,0.0000,4.0000,false
+5,0.0000,0.0000,0.0000,false
+7,7.0000,0.0000,7.0000,false
+"
+    );
+    assert_eq!(
+        run_with_args(
+            "client_range_json",
+            input,
+            &[
+                "--client-range",
+                "3..3",
+                "--format",
+                "json",
+                "--held-tx-ids"
+            ]
+        )
+        .unwrap(),
+        concat!(
+            r#"[{"client":2,"available":2.0000,"held":0.0000,"total":2.0000,"locked":false,"held_tx_ids":[]},"#,
+            r#"{"client":3,"available":0.0000,"held":0.0000,"total":0.0000,"locked":false,"held_tx_ids":[]},"#,
+            r#"{"client":4,"available":4.0000,"held":0.0000,"total":4.0000,"locked":false,"held_tx_ids":[]},"#,
+            r#"{"client":7,"available":7.0000,"held":0.0000,"total":7.0000,"locked":false,"held_tx_ids":[]}]"#,
+            "\n"
+        )
+    );
+    assert_eq!(
+        run_with_args(
+            "client_range_locked_only",
+            input,
+            &["--client-range", "1..5", "--locked-only"]
+        )
+        .unwrap(),
+        "client,available,held,total,locked\n"
+    );
+
+    for range in ["5..1", "1-5", "1..", "0..70000"] {
+        let err =
+            run_with_args("client_range_invalid", input, &["--client-range", range]).unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
+
+#[test]
+fn summary_only_prints_aggregates_of_every_client() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.5
+withdrawal,2,3,1.5
+deposit,3,4,2.0
+dispute,3,4,
+chargeback,3,4,
+deposit,4,5,3.25
+dispute,4,5,
+";
+    assert_eq!(
+        run_with_args("summary_only_csv", input, &["--summary-only"]).unwrap(),
+        "clients,locked,total,held\n4,1,17.2500,3.2500\n"
+    );
+    assert_eq!(
+        run_with_args(
+            "summary_only_json",
+            input,
+            &["--summary-only", "--format", "json"]
+        )
+        .unwrap(),
+        "{\"clients\":4,\"locked\":1,\"total\":17.2500,\"held\":3.2500}\n"
+    );
+}
+
+#[test]
+fn currency_precision_renders_each_client_in_its_currency() {
+    let input = "type,client,tx,amount,currency
+deposit,1,1,1500,JPY
+withdrawal,1,2,200,jpy
+deposit,2,3,10.1234,USD
+dispute,2,3,,
+";
+    let args = ["--currency-precision", "JPY=0,USD=4"];
+    assert_eq!(
+        run_with_args("currency_precision", input, &args).unwrap(),
+        "client,available,held,total,locked,currency
+1,1300,0,1300,false,JPY
+2,0.0000,10.1234,10.1234,false,USD
+"
+    );
+    assert_eq!(
+        run_with_args(
+            "currency_precision_json",
+            "type,client,tx,amount,currency\ndeposit,1,1,7,JPY\n",
+            &[args[0], args[1], "--format", "json"]
+        )
+        .unwrap(),
+        concat!(
+            r#"[{"client":1,"available":7,"held":0,"total":7,"locked":false,"currency":"JPY"}]"#,
+            "\n"
+        )
+    );
+
+    for (input, name) in [
+        // yen have no decimals
+        ("deposit,1,1,1.5,JPY\n", "currency_precision_decimals"),
+        ("deposit,1,1,1.0,EUR\n", "currency_precision_unknown"),
+        ("deposit,1,1,1.0,\n", "currency_precision_missing"),
+        (
+            "deposit,1,1,1,JPY\ndeposit,1,2,1.0,USD\n",
+            "currency_precision_mixed",
+        ),
+    ] {
+        let input = format!("type,client,tx,amount,currency\n{}", input);
+        let err = run_with_args(name, &input, &args).unwrap_err();
+        assert!(err.to_string().starts_with("Line "), "{}", err);
+    }
+    for precision in ["JPY", "JPY=5", "=2", "JPY=x"] {
+        let err = run_with_args(
+            "currency_precision_invalid",
+            input,
+            &["--currency-precision", precision],
+        )
+        .unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
@@ -0,0 +1,460 @@
+use csv::StringRecord;
+use csv_ledger::{
+    Amount, AmountUnits, AppError, ParseOptions, PaymentEngine, RoundingMode, TransactionInput,
+    TransactionSide, TransactionType, TxOutcome, format_ticks, parse_amount, parse_tick_tolerance,
+};
+
+fn parse(fields: &[&str]) -> Result<TransactionInput, AppError> {
+    TransactionInput::try_from_string_record(StringRecord::from(fields.to_vec()))
+}
+
+fn invalid_record_reason(fields: &[&str]) -> String {
+    match parse(fields) {
+        Err(AppError::InvalidRecord(reason)) => reason,
+        Err(err) => panic!("expected InvalidRecord, got {:?}", err),
+        Ok(_) => panic!("expected InvalidRecord for {:?}", fields),
+    }
+}
+
+#[test]
+fn thousands_separator_is_reported() {
+    let reason = invalid_record_reason(&["deposit", "1", "1", "1,000.50"]);
+    assert_eq!(
+        reason,
+        r#"amount "1,000.50" contains a thousands separator"#
+    );
+}
+
+#[test]
+fn currency_symbol_is_reported() {
+    let reason = invalid_record_reason(&["deposit", "1", "1", "$5"]);
+    assert_eq!(reason, r#"amount "$5" contains a currency symbol"#);
+}
+
+#[test]
+fn parenthesized_negative_is_reported() {
+    let reason = invalid_record_reason(&["withdrawal", "1", "1", "(10)"]);
+    assert_eq!(
+        reason,
+        r#"amount "(10)" uses parentheses for a negative amount"#
+    );
+}
+
+#[test]
+fn dispute_amount_column_is_optional() {
+    for fields in [&["dispute", "1", "5"][..], &["dispute", "1", "5", ""]] {
+        assert!(matches!(parse(fields), Ok(TransactionInput::Dispute(5, 1))));
+    }
+    let reason = invalid_record_reason(&["dispute", "1", "5", "10"]);
+    assert!(reason.contains("can't have an amount"), "{}", reason);
+    assert!(matches!(
+        parse(&["deposit", "1", "5"]),
+        Err(AppError::InvalidRecord(_))
+    ));
+}
+
+#[test]
+fn settlement_amounts_must_match_their_transaction() {
+    for settlement in ["resolve", "chargeback"] {
+        let body = |amount: &str| {
+            format!(
+                "type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,1,\n{},1,1,{}\n",
+                settlement, amount
+            )
+        };
+        let engine = PaymentEngine::from_csv_str(&body("10.0000")).unwrap();
+        let balance = engine.get(1).unwrap().balance();
+        assert_eq!(balance.held, 0, "{}", settlement);
+
+        let Err(err) = PaymentEngine::from_csv_str(&body("9.5")) else {
+            panic!("{} of a mismatching amount is rejected", settlement);
+        };
+        assert!(
+            err.to_string()
+                .ends_with("of tx 1 claims 9.5000, but the transaction is of 10.0000"),
+            "{}",
+            err
+        );
+        assert!(matches!(err.inner(), AppError::InvalidRecord(_)));
+    }
+
+    // the amount is parsed as usual, and only checked for transactions that exist
+    let (input, claimed) = TransactionInput::try_from_string_record_claimed(
+        StringRecord::from(vec!["resolve", "1", "7", " 1.50 "]),
+        &ParseOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(input, TransactionInput::Resolve(7, 1));
+    assert_eq!(claimed, Some(Amount::from_ticks(15_000)));
+    let engine = PaymentEngine::from_csv_str("resolve,1,7,1.5\n").unwrap();
+    assert_eq!(engine.get(1).unwrap().balance().total, 0);
+}
+
+#[test]
+fn settlement_amounts_are_checked_within_batches() {
+    let Err(err) = PaymentEngine::from_csv_str(
+        "type,client,tx,amount
+begin,,,
+deposit,1,1,10.0
+dispute,1,1,
+resolve,1,1,1.0
+commit,,,
+",
+    ) else {
+        panic!("resolve of a mismatching amount is rejected");
+    };
+    // failing batches are reported at their commit
+    assert!(err.to_string().starts_with("Line 6: "), "{}", err);
+}
+
+#[test]
+fn dispute_amount_is_ignored_when_allowed() {
+    let options = ParseOptions {
+        ignore_dispute_amount: true,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(parse(&["deposit", "1", "1", "10.0"]).unwrap())
+        .unwrap();
+    let dispute = TransactionInput::try_from_string_record_with(
+        StringRecord::from(vec!["dispute", "1", "1", "50.0"]),
+        &options,
+    )
+    .unwrap();
+    assert!(matches!(dispute, TransactionInput::Dispute(1, 1)));
+    assert_eq!(engine.apply(dispute).unwrap(), TxOutcome::Applied);
+    let (resolve, claimed) = TransactionInput::try_from_string_record_claimed(
+        StringRecord::from(vec!["resolve", "1", "1", "50.0"]),
+        &options,
+    )
+    .unwrap();
+    assert_eq!((resolve, claimed), (TransactionInput::Resolve(1, 1), None));
+
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.available, balance.held), (0, 100_000));
+}
+
+#[test]
+fn excess_precision_is_truncated_by_default() {
+    let input = parse(&["deposit", "1", "1", "1.00005"]).unwrap();
+    assert!(matches!(input, TransactionInput::Deposit(1, 1, 10_000)));
+}
+
+#[test]
+fn excess_precision_is_rejected_when_strict() {
+    let options = ParseOptions {
+        reject_excess_precision: true,
+        ..Default::default()
+    };
+    let record = StringRecord::from(vec!["deposit", "1", "1", "1.00005"]);
+    let result = TransactionInput::try_from_string_record_with(record, &options);
+    assert!(
+        matches!(result, Err(AppError::InvalidRecord(reason)) if reason == r#"amount "1.00005" has more than 4 decimal places"#)
+    );
+
+    // trailing zeros carry no information, so they're not rejected
+    let record = StringRecord::from(vec!["deposit", "1", "1", "1.000500"]);
+    let input = TransactionInput::try_from_string_record_with(record, &options).unwrap();
+    assert!(matches!(input, TransactionInput::Deposit(1, 1, 10_005)));
+}
+
+#[test]
+fn minor_units_are_read_as_ticks() {
+    let options = ParseOptions {
+        amount_units: AmountUnits::Minor,
+        ..Default::default()
+    };
+    let record = StringRecord::from(vec!["deposit", "1", "1", "12345"]);
+    let input = TransactionInput::try_from_string_record_with(record, &options).unwrap();
+    assert!(matches!(input, TransactionInput::Deposit(1, 1, 12_345)));
+    assert_eq!(format_ticks(12_345), "1.2345");
+
+    let record = StringRecord::from(vec!["deposit", "1", "1", "1.5"]);
+    let result = TransactionInput::try_from_string_record_with(record, &options);
+    assert!(
+        matches!(result, Err(AppError::InvalidRecord(reason)) if reason == r#"amount "1.5" has a decimal point, while minor units are integer ticks"#)
+    );
+}
+
+#[test]
+fn rounding_modes_pin_tick_results() {
+    let cases = [
+        (RoundingMode::Truncate, 12_345, 12_346),
+        (RoundingMode::HalfUp, 12_346, 12_347),
+        (RoundingMode::HalfEven, 12_346, 12_346),
+        (RoundingMode::Ceil, 12_346, 12_347),
+        (RoundingMode::Floor, 12_345, 12_346),
+    ];
+    for (rounding, first, second) in cases {
+        let options = ParseOptions {
+            rounding,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_amount("1.23455", &options).unwrap(),
+            first,
+            "{:?}",
+            rounding
+        );
+        assert_eq!(
+            parse_amount("1.23465", &options).unwrap(),
+            second,
+            "{:?}",
+            rounding
+        );
+    }
+}
+
+#[test]
+fn rounding_is_sign_aware() {
+    let floor = ParseOptions {
+        rounding: RoundingMode::Floor,
+        ..Default::default()
+    };
+    assert_eq!(parse_amount("-1.23451", &floor).unwrap(), -12_346);
+    let ceil = ParseOptions {
+        rounding: RoundingMode::Ceil,
+        ..Default::default()
+    };
+    assert_eq!(parse_amount("-1.23459", &ceil).unwrap(), -12_345);
+}
+
+#[test]
+fn no_trim_requires_exact_fields() {
+    let fields = vec![" deposit", "1", "1", "1.0"];
+    assert!(parse(&fields).is_ok());
+
+    let options = ParseOptions {
+        no_trim: true,
+        ..Default::default()
+    };
+    let result =
+        TransactionInput::try_from_string_record_with(StringRecord::from(fields), &options);
+    assert!(matches!(result, Err(AppError::InvalidTxType(tx_type)) if tx_type == " deposit"));
+}
+
+#[test]
+fn strict_types_rejects_mixed_case_types() {
+    let strict = ParseOptions {
+        strict_types: true,
+        ..Default::default()
+    };
+    for tx_type in ["Deposit", "DEPOSIT", "dEpOsIt"] {
+        let fields = vec![tx_type, "1", "1", "1.0"];
+        assert_eq!(
+            parse(&fields).unwrap(),
+            TransactionInput::Deposit(1, 1, 10_000)
+        );
+        let result =
+            TransactionInput::try_from_string_record_with(StringRecord::from(fields), &strict);
+        assert!(matches!(result, Err(AppError::InvalidTxType(found)) if found == tx_type));
+    }
+
+    // still trimmed
+    let fields = vec![" chargeback ", "1", "1", ""];
+    assert_eq!(
+        TransactionInput::try_from_string_record_with(StringRecord::from(fields), &strict).unwrap(),
+        TransactionInput::Chargeback(1, 1)
+    );
+}
+
+#[test]
+fn out_of_range_ids_name_their_limit() {
+    assert_eq!(
+        invalid_record_reason(&["deposit", "70000", "1", "1.0"]),
+        "client id 70000 exceeds u16 max"
+    );
+    assert_eq!(
+        invalid_record_reason(&["dispute", "1", "4294967296", ""]),
+        "tx id 4294967296 exceeds u32 max"
+    );
+    assert!(matches!(
+        parse(&["deposit", "-1", "1", "1.0"]),
+        Err(AppError::ParseInt(_))
+    ));
+}
+
+#[test]
+fn lenient_amount_forms_are_accepted() {
+    let options = ParseOptions::default();
+    for (raw, ticks) in [
+        ("+5.0", 50_000),
+        ("+5", 50_000),
+        (".5", 5_000),
+        ("+.5", 5_000),
+        ("-.5", -5_000),
+        ("5.", 50_000),
+    ] {
+        assert_eq!(parse_amount(raw, &options).unwrap(), ticks, "{}", raw);
+    }
+    assert_eq!(
+        parse(&["deposit", "1", "1", "+.25"]).unwrap(),
+        TransactionInput::Deposit(1, 1, 2_500)
+    );
+}
+
+#[test]
+fn malformed_amounts_are_still_rejected() {
+    let options = ParseOptions::default();
+    for raw in [
+        ".", "+", "-", "+.", "1.2.3", "..5", "5..", "++5", "+-5", "-+5", "5a", "a5", "1e3", " 5",
+    ] {
+        assert!(
+            matches!(parse_amount(raw, &options), Err(AppError::InvalidRecord(_))),
+            "{}",
+            raw
+        );
+    }
+}
+
+#[test]
+fn non_finite_amounts_are_reported() {
+    for (raw, token) in [("inf", "inf"), ("-Infinity", "-infinity"), ("NaN", "nan")] {
+        assert_eq!(
+            invalid_record_reason(&["deposit", "1", "1", raw]),
+            format!(r#"amount "{}" is not a finite number"#, token)
+        );
+    }
+}
+
+#[test]
+fn scientific_notation_is_reported() {
+    let reason = invalid_record_reason(&["deposit", "1", "1", "1e309"]);
+    assert_eq!(reason, r#"amount "1e309" uses scientific notation"#);
+    let reason = invalid_record_reason(&["deposit", "1", "1", "2.5E3"]);
+    assert_eq!(reason, r#"amount "2.5e3" uses scientific notation"#);
+}
+
+#[test]
+fn float_noise_snaps_to_the_nearest_tick() {
+    for rounding in [
+        RoundingMode::Truncate,
+        RoundingMode::Ceil,
+        RoundingMode::Floor,
+    ] {
+        let options = ParseOptions {
+            rounding,
+            ..Default::default()
+        };
+        for raw in ["0.30000001", "0.29999999", "0.3"] {
+            assert_eq!(
+                parse_amount(raw, &options).unwrap(),
+                3_000,
+                "{} {:?}",
+                raw,
+                rounding
+            );
+        }
+        assert_eq!(parse_amount("-0.29999999", &options).unwrap(), -3_000);
+    }
+}
+
+#[test]
+fn amounts_past_the_tolerance_are_rounded() {
+    let options = ParseOptions::default();
+    assert_eq!(parse_amount("0.2999999", &options).unwrap(), 2_999);
+    let exact = ParseOptions {
+        tick_tolerance: 0,
+        ..Default::default()
+    };
+    assert_eq!(parse_amount("0.29999999", &exact).unwrap(), 2_999);
+}
+
+#[test]
+fn tick_tolerance_is_parsed_in_billionths_of_a_tick() {
+    assert_eq!(parse_tick_tolerance("0.00000001").unwrap(), 100_000);
+    assert_eq!(parse_tick_tolerance(".000001").unwrap(), 10_000_000);
+    assert_eq!(parse_tick_tolerance("0").unwrap(), 0);
+    for raw in ["0.00005", "1", "-0.00000001", "1e-8"] {
+        assert!(
+            matches!(parse_tick_tolerance(raw), Err(AppError::InvalidArgument(_))),
+            "{}",
+            raw
+        );
+    }
+}
+
+#[test]
+fn transaction_types_map_to_their_side() {
+    let cases = [
+        (TransactionType::Deposit, Some(TransactionSide::Deposit)),
+        (
+            TransactionType::Withdrawal,
+            Some(TransactionSide::Withdrawal),
+        ),
+        (TransactionType::Dispute, None),
+        (TransactionType::Resolve, None),
+        (TransactionType::Chargeback, None),
+    ];
+    for (tx_type, side) in cases {
+        assert_eq!(tx_type.side(), side, "{:?}", tx_type);
+        assert_eq!(tx_type.is_monetary(), side.is_some(), "{:?}", tx_type);
+    }
+}
+
+#[test]
+fn funds_are_only_carried_by_monetary_inputs() {
+    let cases = [
+        (
+            TransactionInput::Deposit(1, 1, 500),
+            Some((TransactionSide::Deposit, 500)),
+        ),
+        (
+            TransactionInput::Withdrawal(2, 1, 300),
+            Some((TransactionSide::Withdrawal, 300)),
+        ),
+        (TransactionInput::Dispute(1, 1), None),
+        (TransactionInput::Resolve(1, 1), None),
+        (TransactionInput::Chargeback(1, 1), None),
+    ];
+    for (input, funds) in cases {
+        assert_eq!(input.funds(), funds, "{:?}", input);
+    }
+    assert_eq!(
+        TransactionInput::monetary(TransactionSide::Withdrawal, 3, 2, 100).unwrap(),
+        TransactionInput::Withdrawal(3, 2, 100)
+    );
+    assert!(TransactionInput::monetary(TransactionSide::Deposit, 3, 2, 0).is_err());
+}
+
+#[test]
+fn zero_tx_id_is_reserved_when_rejected() {
+    let options = ParseOptions {
+        reject_zero_tx_id: true,
+        ..Default::default()
+    };
+    let parse_with = |fields: Vec<&str>| {
+        TransactionInput::try_from_string_record_with(StringRecord::from(fields), &options)
+    };
+    for fields in [
+        vec!["deposit", "1", "0", "1.0"],
+        vec!["withdrawal", "1", "0", "1.0"],
+    ] {
+        assert!(
+            matches!(parse_with(fields.clone()), Err(AppError::InvalidRecord(reason)) if reason.contains("reserved tx id 0")),
+            "{:?}",
+            fields
+        );
+    }
+    // accepted by default
+    assert_eq!(
+        parse(&["deposit", "1", "0", "1.0"]).unwrap(),
+        TransactionInput::Deposit(0, 1, 10_000)
+    );
+
+    let mut engine = PaymentEngine::new();
+    engine
+        .apply(parse_with(vec!["deposit", "1", "1", "1.0"]).unwrap())
+        .unwrap();
+    for fields in [
+        vec!["dispute", "1", "0"],
+        vec!["resolve", "1", "0"],
+        vec!["chargeback", "1", "0"],
+    ] {
+        let input = parse_with(fields).unwrap();
+        assert_eq!(engine.apply(input).unwrap(), TxOutcome::IgnoredMissingTx);
+    }
+    let balance = engine.get(1).unwrap().balance();
+    assert_eq!((balance.available, balance.held), (10_000, 0));
+}
@@ -0,0 +1,559 @@
+mod common;
+
+use common::{fixture, run_with_args, write_temp};
+use csv::ReaderBuilder;
+use csv_ledger::{
+    AppError, Config, OutputFormat, PaymentEngine, ProcessOptions, Progress, TransactionInput,
+    TxRules, Units, ViolationPolicy, parse_records, process_records, process_records_from,
+    run_with_diagnostics, write_balances_with,
+};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const MOSTLY_CORRUPT: &str = "type,client,tx,amount
+deposit,1,1,1.0
+garbage,1,2,1.0
+deposit,x,3,1.0
+deposit,1,y,1.0
+deposit,1,4,z
+deposit,1,5,1.0
+";
+
+fn process(max_errors: Option<usize>) -> (Result<(), AppError>, Progress, PaymentEngine) {
+    let mut reader = ReaderBuilder::new().from_reader(MOSTLY_CORRUPT.as_bytes());
+    let mut engine = PaymentEngine::new();
+    let mut progress = Progress::default();
+    let options = ProcessOptions {
+        max_errors,
+        ..Default::default()
+    };
+    let result = process_records_from(&mut reader, &mut engine, &options, &mut progress);
+    (result, progress, engine)
+}
+
+#[test]
+fn parse_errors_report_their_line() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..41 {
+        input.push_str(&format!("deposit,1,{},1.0\n", tx));
+    }
+    input.push_str("deposit,1,41,1.x\n");
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let result = process_records(&mut reader, &mut PaymentEngine::new(), &Default::default());
+    let err = result.unwrap_err();
+    assert!(matches!(err, AppError::AtLine { line: 42, .. }));
+    assert!(err.to_string().contains("42"), "{}", err);
+}
+
+#[test]
+fn aborts_once_max_errors_accumulate() {
+    let (result, progress, engine) = process(Some(2));
+    assert!(matches!(result, Err(AppError::TooManyErrors(2))));
+    let lines: Vec<usize> = progress.errors.iter().map(|(line, _)| *line).collect();
+    assert_eq!(lines, vec![3, 4]);
+    // the trailing valid deposit is never reached
+    assert_eq!(engine.get(1).unwrap().balance().total, 10_000);
+}
+
+#[test]
+fn skips_errors_below_the_limit() {
+    let (result, progress, engine) = process(Some(10));
+    assert!(result.is_ok());
+    assert_eq!(progress.errors.len(), 4);
+    assert_eq!(engine.get(1).unwrap().balance().total, 20_000);
+}
+
+#[test]
+fn aborts_on_first_error_without_limit() {
+    let (result, progress, _) = process(None);
+    let err = result.unwrap_err();
+    assert!(matches!(err.inner(), AppError::InvalidTxType(_)));
+    assert!(err.to_string().starts_with("Line 3: "));
+    assert!(progress.errors.is_empty());
+}
+
+#[test]
+fn csv_and_parse_errors_are_annotated_alike() {
+    let first_error = |input: &str| {
+        let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+        let options = ProcessOptions {
+            max_errors: Some(10),
+            ..Default::default()
+        };
+        let progress = process_records(&mut reader, &mut PaymentEngine::new(), &options).unwrap();
+        let (line, err) = progress.errors.into_iter().next().unwrap();
+        (line, err.to_string())
+    };
+
+    let (line, csv_error) = first_error("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1\n");
+    assert_eq!(line, 3);
+    assert!(
+        csv_error.starts_with("Line 3: CSV error: "),
+        "{}",
+        csv_error
+    );
+    let (line, parse_error) =
+        first_error("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,z\n");
+    assert_eq!(line, 3);
+    assert!(parse_error.starts_with("Line 3: "), "{}", parse_error);
+    assert!(!parse_error.contains("Line 3: Line 3"), "{}", parse_error);
+}
+
+#[test]
+fn grand_total_reconciles_with_net_flows() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.5
+withdrawal,1,3,2.25
+deposit,3,4,7.0
+dispute,3,4,
+chargeback,3,4,
+withdrawal,2,5,9.0
+deposit,2,6,1.0
+dispute,2,6,
+";
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new();
+    process_records(&mut reader, &mut engine, &ProcessOptions::default()).unwrap();
+    assert_eq!(engine.grand_total_ticks(), 142_500);
+    assert_eq!(engine.grand_total_ticks(), engine.net_flow_ticks());
+
+    let mut reader = ReaderBuilder::new()
+        .from_path(fixture("transactions.csv"))
+        .unwrap();
+    let mut engine = PaymentEngine::new();
+    process_records(&mut reader, &mut engine, &ProcessOptions::default()).unwrap();
+    assert_eq!(engine.grand_total_ticks(), engine.net_flow_ticks());
+}
+
+const REPLAYED_DEPOSIT: &str = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+deposit,1,1,10.0
+";
+
+fn process_with_duplicates(
+    on_duplicate: ViolationPolicy,
+) -> (Result<Progress, AppError>, PaymentEngine) {
+    let mut reader = ReaderBuilder::new().from_reader(REPLAYED_DEPOSIT.as_bytes());
+    let mut engine = PaymentEngine::new().with_rules(TxRules {
+        on_duplicate,
+        ..TxRules::default()
+    });
+    let result = process_records(&mut reader, &mut engine, &ProcessOptions::default());
+    (result, engine)
+}
+
+#[test]
+fn duplicated_deposit_is_ignored_by_default() {
+    let (result, engine) = process_with_duplicates(ViolationPolicy::Ignore);
+    result.unwrap();
+    assert!(engine.warnings().is_empty());
+    assert_eq!(engine.get(1).unwrap().balance().total, 100_000);
+}
+
+#[test]
+fn duplicated_deposit_errors_when_asked() {
+    let (result, engine) = process_with_duplicates(ViolationPolicy::Error);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Line 4: Invalid record for creating transaction: client 1: tx 1 reuses an existing tx id"
+    );
+    assert_eq!(engine.get(1).unwrap().balance().total, 100_000);
+}
+
+#[test]
+fn duplicated_deposit_warns_when_asked() {
+    let (result, engine) = process_with_duplicates(ViolationPolicy::Warn);
+    result.unwrap();
+    let warnings: Vec<String> = engine.warnings().iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        warnings,
+        ["line 4: tx 1 of client 1 reuses an existing tx id, ignored"]
+    );
+    assert_eq!(engine.get(1).unwrap().balance().total, 100_000);
+}
+
+#[test]
+fn conflicting_duplicates_are_told_apart_from_exact_ones() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,1,10.0
+deposit,1,1,12.0
+withdrawal,1,1,10.0
+deposit,2,1,10.0
+";
+    let mut engine = PaymentEngine::new();
+    process_records(
+        &mut ReaderBuilder::new().from_reader(input.as_bytes()),
+        &mut engine,
+        &ProcessOptions::default(),
+    )
+    .unwrap();
+    // the exact duplicate on line 3 is silently ignored
+    let warnings: Vec<String> = engine.warnings().iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        warnings,
+        [
+            "line 4: tx 1 of client 1 reuses the tx id of a different transaction, ignored",
+            "line 5: tx 1 of client 1 reuses the tx id of a different transaction, ignored",
+            "line 6: tx 1 of client 2 reuses the tx id of a different transaction, ignored",
+        ]
+    );
+    assert_eq!(engine.get(1).unwrap().balance().total, 100_000);
+
+    let err = run_with_args(
+        "conflicting_duplicate_error",
+        input,
+        &["--on-conflicting-duplicate", "error"],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 4: Invalid record for creating transaction: client 1: tx 1 reuses the tx id of a different transaction"
+    );
+    // exact duplicates stay under --on-duplicate
+    let err = run_with_args(
+        "exact_duplicate_error",
+        input,
+        &[
+            "--on-duplicate",
+            "error",
+            "--on-conflicting-duplicate",
+            "ignore",
+        ],
+    )
+    .unwrap_err();
+    assert!(err.to_string().starts_with("Line 3: "), "{}", err);
+}
+
+#[test]
+fn parse_records_yields_inputs_lazily() {
+    let input = "client;type;tx;amount
+1;deposit;1;1.5
+2;deposit;2;2.0
+;begin;;
+1;withdrawal;3;0.5
+;commit;;
+2;dispute;2;
+1;deposit;x;1.0
+2;chargeback;2;
+";
+    let parsed: Vec<_> = parse_records(input.as_bytes()).collect();
+    assert_eq!(parsed.len(), 6);
+    assert_eq!(
+        parsed[..4]
+            .iter()
+            .map(|result| *result.as_ref().unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            TransactionInput::Deposit(1, 1, 15_000),
+            TransactionInput::Deposit(2, 2, 20_000),
+            TransactionInput::Withdrawal(3, 1, 5_000),
+            TransactionInput::Dispute(2, 2),
+        ]
+    );
+    assert!(matches!(parsed[4], Err(AppError::AtLine { line: 8, .. })));
+    assert_eq!(
+        parsed[5].as_ref().unwrap(),
+        &TransactionInput::Chargeback(2, 2)
+    );
+
+    let client_two = parse_records(input.as_bytes())
+        .filter_map(Result::ok)
+        .filter(|input| input.client_id() == 2)
+        .count();
+    assert_eq!(client_two, 3);
+}
+
+#[test]
+fn continue_on_error_reports_every_bad_row_after_processing() {
+    let input = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,abc
+deposit,2,3,5.0
+withdrawal,x,4,1.0
+withdrawal,1,5,2.5
+refund,2,6,1.0
+deposit,2,7,1.0
+";
+    let path = write_temp("continue_on_error.csv", input.as_bytes());
+    let config = Config::from_args(
+        ["csv_ledger", "--continue-on-error", &path.to_string_lossy()]
+            .iter()
+            .map(|arg| arg.to_string()),
+    )
+    .unwrap();
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    let result = run_with_diagnostics(&config, &mut output, &mut diagnostics);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(result, Err(AppError::SkippedRecords(3))));
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,7.5000,0.0000,7.5000,false\n2,6.0000,0.0000,6.0000,false\n"
+    );
+    let diagnostics = String::from_utf8(diagnostics).unwrap();
+    let lines: Vec<&str> = diagnostics
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(lines, vec!["Line 3", "Line 5", "Line 7"]);
+}
+
+#[test]
+fn oversized_line_aborts_even_when_skipping() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=200 {
+        input.push_str(&format!("deposit,1,{},1.0\n", tx));
+    }
+    input.push_str(&format!("deposit,1,201,{}\n", "1".repeat(10_000)));
+    for options in [
+        &["--max-line-length", "1024"][..],
+        &["--max-line-length", "1024", "--max-errors", "100"],
+    ] {
+        let err = run_with_args("oversized_line", &input, options).unwrap_err();
+        assert!(
+            matches!(err.inner(), AppError::InvalidFormat(reason) if reason.contains("1024 bytes")),
+            "{:?}: {}",
+            options,
+            err
+        );
+    }
+
+    let mut ndjson = String::new();
+    for tx in 1..=200 {
+        ndjson.push_str(&format!(
+            "{{\"type\":\"deposit\",\"client\":1,\"tx\":{},\"amount\":\"1.0\"}}\n",
+            tx
+        ));
+    }
+    ndjson.push_str(&format!("{{\"type\":\"{}\"}}\n", "a".repeat(10_000)));
+    let err = run_with_args(
+        "oversized_ndjson_line",
+        &ndjson,
+        &[
+            "--max-line-length",
+            "1024",
+            "--input-format",
+            "ndjson",
+            "--max-errors",
+            "100",
+        ],
+    )
+    .unwrap_err();
+    assert!(matches!(err.inner(), AppError::InvalidFormat(_)), "{}", err);
+
+    // lines within the limit are processed as usual
+    let output = run_with_args("oversized_line_allowed", &input, &["--max-errors", "2"]);
+    assert!(output.is_ok());
+    let within = run_with_args(
+        "within_line_length",
+        "type,client,tx,amount\ndeposit,1,1,1.0\n",
+        &["--max-line-length", "24"],
+    );
+    assert_eq!(
+        within.unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn input_without_line_breaks_is_guarded_from_the_sniff_on() {
+    let input = "a".repeat(1 << 20);
+    let err = run_with_args("no_line_breaks", &input, &["--max-line-length", "4096"]).unwrap_err();
+    assert!(matches!(err.inner(), AppError::InvalidFormat(_)), "{}", err);
+
+    let err =
+        Config::from_args(["csv_ledger", "--max-line-length", "0", "in.csv"].map(String::from))
+            .unwrap_err();
+    assert!(matches!(err, AppError::InvalidArgument(_)));
+}
+
+const DISPUTES_FIRST: &str = "type,client,tx,amount
+dispute,1,5,
+deposit,1,4,1.0
+dispute,1,6,
+resolve,1,6,
+deposit,1,5,2.0
+deposit,1,6,3.0
+";
+
+fn process_buffering(input: &str, buffer_disputes: Option<usize>) -> PaymentEngine {
+    let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+    let mut engine = PaymentEngine::new();
+    let options = ProcessOptions {
+        buffer_disputes,
+        ..Default::default()
+    };
+    process_records(&mut reader, &mut engine, &options).unwrap();
+    engine
+}
+
+fn held_of(engine: &PaymentEngine) -> (i128, i128) {
+    let balance = engine.get(1).unwrap().balance();
+    (balance.available, balance.held)
+}
+
+#[test]
+fn disputes_ahead_of_their_deposit_are_replayed_once_buffered() {
+    // without buffering, both disputes reference transactions that don't exist yet
+    assert_eq!(
+        held_of(&process_buffering(DISPUTES_FIRST, None)),
+        (60_000, 0)
+    );
+
+    // the dispute of tx 5 lands right after its deposit, while tx 6 is disputed and resolved
+    let engine = process_buffering(DISPUTES_FIRST, Some(10));
+    assert_eq!(held_of(&engine), (40_000, 20_000));
+    assert_eq!(engine.get(1).unwrap().held_transaction_ids(), [5]);
+}
+
+#[test]
+fn buffered_disputes_past_capacity_are_applied_as_they_are() {
+    // holding a single row, the dispute of tx 5 is pushed out by the one of tx 6
+    let engine = process_buffering(DISPUTES_FIRST, Some(1));
+    assert_eq!(held_of(&engine), (60_000, 0));
+
+    // rows still held at the end of the input are applied then, and ignored
+    let engine = process_buffering(
+        "type,client,tx,amount\ndispute,1,9,\ndeposit,1,1,1.0\n",
+        Some(10),
+    );
+    assert_eq!(held_of(&engine), (10_000, 0));
+    let err = run_with_args(
+        "buffered_dispute_never_settled",
+        "type,client,tx,amount\ndispute,1,9,\ndeposit,1,1,1.0\n",
+        &["--buffer-disputes", "10", "--strict"],
+    )
+    .unwrap_err();
+    assert!(err.to_string().starts_with("Line 2: "), "{}", err);
+
+    for options in [
+        &["--buffer-disputes", "0"][..],
+        &["--buffer-disputes", "10", "--snapshot", "state.snapshot"],
+    ] {
+        let err = run_with_args("invalid_buffer_disputes", DISPUTES_FIRST, options).unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// sets `STOP` on its second read, as a SIGINT landing mid-stream would
+struct InterruptedReader<R> {
+    inner: R,
+    reads: usize,
+}
+
+impl<R: Read> Read for InterruptedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        if self.reads == 2 {
+            STOP.store(true, Ordering::Relaxed);
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn interruption_keeps_the_balances_computed_so_far() {
+    // larger than the buffer of the csv reader, so it reads twice
+    let rows = 5_000;
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=rows {
+        input.push_str(&format!("deposit,{},{},1.0\n", tx % 7, tx));
+    }
+    let mut reader = ReaderBuilder::new().from_reader(InterruptedReader {
+        inner: input.as_bytes(),
+        reads: 0,
+    });
+    let mut engine = PaymentEngine::new();
+    let options = ProcessOptions {
+        interrupt: Some(&STOP),
+        ..Default::default()
+    };
+    let progress = process_records(&mut reader, &mut engine, &options).unwrap();
+    assert!(progress.interrupted);
+    assert!(
+        progress.cursor > 0 && progress.cursor < rows,
+        "{}",
+        progress.cursor
+    );
+
+    // every processed deposit, and only them, is in the partial balances, which are valid csv
+    let mut output = Vec::new();
+    write_balances_with(
+        &mut output,
+        engine.balances(),
+        OutputFormat::Csv,
+        Units::Ticks,
+    )
+    .unwrap();
+    let mut partial = ReaderBuilder::new().from_reader(output.as_slice());
+    let total: i64 = partial
+        .records()
+        .map(|record| record.unwrap()[3].parse::<i64>().unwrap())
+        .sum();
+    assert_eq!(total, progress.cursor as i64 * 10_000);
+    engine.verify().unwrap();
+
+    let err = AppError::Interrupted(progress.cursor);
+    assert_eq!(err.exit_code(), 130);
+}
+
+static STOPPED: AtomicBool = AtomicBool::new(true);
+
+#[test]
+fn run_stops_on_the_first_read_once_interrupted() {
+    let path = write_temp(
+        "interrupted_run",
+        b"type,client,tx,amount\ndeposit,1,1,1.0\n",
+    );
+    let mut config = Config::from_args([
+        "csv_ledger".to_string(),
+        path.to_string_lossy().into_owned(),
+    ])
+    .unwrap();
+    config.processing.interrupt = Some(&STOPPED);
+    let mut output = Vec::new();
+    let err = run_with_diagnostics(&config, &mut output, &mut io::sink()).unwrap_err();
+    assert!(matches!(err, AppError::Interrupted(0)), "{}", err);
+    // no record was read, yet the output is still a valid, empty csv
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn dash_reads_the_input_from_stdin() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n";
+    let mut child = Command::new(env!("CARGO_BIN_EXE_csv_ledger"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        run_with_args("stdin_as_file", input, &[]).unwrap()
+    );
+
+    // stdin can't be read ahead of processing
+    for flag in ["--audit-control-flow", "--echo-normalized"] {
+        let err = Config::from_args(["csv_ledger", flag, "-"].iter().map(|arg| arg.to_string()))
+            .unwrap_err();
+        assert!(matches!(err, AppError::InvalidArgument(_)), "{}", err);
+    }
+}
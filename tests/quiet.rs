@@ -0,0 +1,79 @@
+mod common;
+
+use std::fs;
+
+use common::{run_with_args, write_temp};
+use csv_ledger::{Config, run_with_diagnostics};
+
+/// a malformed row, a mismatched client and a duplicated deposit, each reported on stderr
+const ANOMALIES: &str = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,x,2,1.0
+dispute,2,1,
+deposit,1,1,10.0
+";
+
+fn run_capturing(name: &str, options: &[&str]) -> (String, String) {
+    let path = write_temp(name, ANOMALIES.as_bytes());
+    let mut args = vec!["csv_ledger".to_string()];
+    args.extend(options.iter().map(|option| option.to_string()));
+    args.push(path.to_string_lossy().into_owned());
+    let config = Config::from_args(args).unwrap();
+    let (mut output, mut diagnostics) = (Vec::new(), Vec::new());
+    run_with_diagnostics(&config, &mut output, &mut diagnostics).unwrap();
+    fs::remove_file(&path).unwrap();
+    (
+        String::from_utf8(output).unwrap(),
+        String::from_utf8(diagnostics).unwrap(),
+    )
+}
+
+const OPTIONS: [&str; 7] = [
+    "--max-errors",
+    "5",
+    "--on-duplicate",
+    "warn",
+    "--metrics",
+    "--audit",
+    "--echo-normalized",
+];
+
+#[test]
+fn diagnostics_are_reported_by_default() {
+    let (output, diagnostics) = run_capturing("diagnostics_loud", &OPTIONS);
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n"
+    );
+    for expected in [
+        "deposit,1,1,10.0000\n",
+        "Line 3: ",
+        "line 4: tx 1 filed for client 2 belongs to client 1\n",
+        "line 5: tx 1 of client 1 reuses an existing tx id, ignored\n",
+        "Deposit seen 2\n",
+        "client 1, ",
+    ] {
+        assert!(diagnostics.contains(expected), "{}", expected);
+    }
+}
+
+#[test]
+fn quiet_silences_every_diagnostic() {
+    let mut options = OPTIONS.to_vec();
+    options.push("--quiet");
+    let (output, diagnostics) = run_capturing("diagnostics_quiet", &options);
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n"
+    );
+    assert_eq!(diagnostics, "");
+}
+
+#[test]
+fn quiet_runs_still_fail() {
+    let result = run_with_args("quiet_failure", ANOMALIES, &["--quiet"]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Line 3: Parse int error invalid digit found in string"
+    );
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{run_with_args, write_temp};
+use csv::ReaderBuilder;
+use csv_ledger::{
+    PaymentEngine, ProcessOptions, Progress, process_records, process_records_from, write_balances,
+};
+use std::fs;
+
+const INPUT: &str = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+dispute,1,1,
+deposit,1,3,2.5
+withdrawal,2,4,1.0
+deposit,3,5,3.0
+dispute,3,5,
+chargeback,3,5,
+resolve,1,1,
+";
+
+fn output_of(engine: &PaymentEngine) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_balances(&mut output, engine.balances(), Default::default()).unwrap();
+    output
+}
+
+#[test]
+fn restored_snapshot_matches_uninterrupted_run() {
+    let mut full = PaymentEngine::new();
+    process_records(
+        &mut ReaderBuilder::new().from_reader(INPUT.as_bytes()),
+        &mut full,
+        &ProcessOptions::default(),
+    )
+    .unwrap();
+
+    // first half of the records only
+    let half: String = INPUT
+        .lines()
+        .take(5)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let mut partial = PaymentEngine::new();
+    let options = ProcessOptions::default();
+    let mut reader = ReaderBuilder::new().from_reader(half.as_bytes());
+    let progress = process_records(&mut reader, &mut partial, &options).unwrap();
+    assert_eq!(progress.cursor, 4);
+
+    let mut snapshot = Vec::new();
+    partial
+        .save_snapshot(&mut snapshot, progress.cursor)
+        .unwrap();
+    let (mut restored, cursor) = PaymentEngine::load_snapshot(snapshot.as_slice()).unwrap();
+    let mut progress = Progress {
+        cursor,
+        ..Default::default()
+    };
+    let mut reader = ReaderBuilder::new().from_reader(INPUT.as_bytes());
+    process_records_from(&mut reader, &mut restored, &options, &mut progress).unwrap();
+
+    assert_eq!(progress.cursor, 9);
+    assert_eq!(output_of(&restored), output_of(&full));
+}
+
+#[test]
+fn resume_continues_after_a_failed_run() {
+    let snapshot_path = write_temp("resume_snapshot", b"");
+    let snapshot = snapshot_path.to_string_lossy().into_owned();
+    let broken = INPUT.replace("deposit,3,5,3.0", "deposit,3,5,oops");
+    assert!(run_with_args("resume_broken", &broken, &["--snapshot", &snapshot]).is_err());
+
+    let resumed = run_with_args("resume_fixed", INPUT, &["--resume", &snapshot]).unwrap();
+    let uninterrupted = run_with_args("resume_full", INPUT, &[]).unwrap();
+    assert_eq!(resumed, uninterrupted);
+    fs::remove_file(snapshot_path).unwrap();
+}
+
+#[test]
+fn verify_invariants_rejects_a_corrupted_snapshot() {
+    // held funds of client 1 went negative
+    let snapshot_path = write_temp(
+        "corrupted_snapshot",
+        b"snapshot,1,0\nuser,1,false\ntx,1,1,deposit,normal,100000\ntx,2,1,deposit,disputed,-30000\n",
+    );
+    let snapshot = snapshot_path.to_string_lossy().into_owned();
+    let input = "type,client,tx,amount\ndeposit,2,3,1.0\n";
+    assert!(run_with_args("corrupted_unchecked", input, &["--resume", &snapshot]).is_ok());
+
+    let err = run_with_args(
+        "corrupted_checked",
+        input,
+        &["--resume", &snapshot, "--verify-invariants"],
+    )
+    .unwrap_err();
+    assert_eq!(err.exit_code(), 5);
+    assert_eq!(
+        err.to_string(),
+        "1 ledger invariants violated:\n  client 1: held funds are negative (-3.0000)"
+    );
+    fs::remove_file(snapshot_path).unwrap();
+}
+
+#[test]
+fn resume_restores_the_currency_precision_of_the_snapshot() {
+    let input = "type,client,tx,amount,currency
+deposit,1,1,1500,JPY
+deposit,2,2,10.1234,USD
+withdrawal,1,3,200,JPY
+dispute,2,2,,
+";
+    let precision = ["--currency-precision", "JPY=0,USD=4"];
+    let snapshot_path = write_temp("currency_snapshot", b"");
+    let snapshot = snapshot_path.to_string_lossy().into_owned();
+    let broken = input.replace("200,JPY", "oops,JPY");
+    assert!(
+        run_with_args(
+            "currency_broken",
+            &broken,
+            &[precision[0], precision[1], "--snapshot", &snapshot]
+        )
+        .is_err()
+    );
+
+    let resumed = run_with_args("currency_resumed", input, &["--resume", &snapshot]).unwrap();
+    let uninterrupted = run_with_args("currency_full", input, &precision).unwrap();
+    assert_eq!(resumed, uninterrupted);
+
+    let err = run_with_args(
+        "currency_conflicting",
+        input,
+        &["--resume", &snapshot, "--currency-precision", "JPY=2,USD=4"],
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid argument: --currency-precision gives JPY 2 decimals, the resumed snapshot 0"
+    );
+    fs::remove_file(snapshot_path).unwrap();
+}
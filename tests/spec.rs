@@ -0,0 +1,12 @@
+mod common;
+
+use common::{fixture, run_file};
+use std::fs;
+
+/// golden reference: any refactor must keep producing the committed expected output
+#[test]
+fn spec_example_matches_expected_output() {
+    let output = run_file(&fixture("spec_example.csv"), &[]).unwrap();
+    let expected = fs::read_to_string(fixture("spec_example_expected.csv")).unwrap();
+    assert_eq!(output, expected);
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use csv_ledger::{
+    ClientBalance, FileTxStore, PaymentEngine, Transaction, TransactionInput, TransactionSide,
+    TxOutcome, TxRules, TxStore,
+};
+
+/// a mix of every transition over a few clients, interleaved so stores switch between them
+fn scenario() -> Vec<TransactionInput> {
+    let mut inputs = Vec::new();
+    for tx in 1..=300u32 {
+        let client = (tx % 4) as u16 + 1;
+        let amount = (tx as i32 * 7_919) % 50_000 + 1;
+        inputs.push(match tx % 5 {
+            0..=2 => TransactionInput::Deposit(tx, client, amount),
+            _ => TransactionInput::Withdrawal(tx, client, amount / 2),
+        });
+        if tx > 30 {
+            let target = tx - 20;
+            let owner = (target % 4) as u16 + 1;
+            inputs.push(match tx % 6 {
+                0 | 3 => TransactionInput::Dispute(target, owner),
+                1 => TransactionInput::Resolve(target - 3, ((target - 3) % 4) as u16 + 1),
+                2 => TransactionInput::Chargeback(target - 6, ((target - 6) % 4) as u16 + 1),
+                _ => TransactionInput::Dispute(target - 3, ((target - 3) % 4) as u16 + 1),
+            });
+        }
+    }
+    inputs
+}
+
+fn run<S: TxStore>(
+    mut engine: PaymentEngine<S>,
+) -> (Vec<TxOutcome>, Vec<ClientBalance>, Vec<Vec<u32>>, i128) {
+    let mut outcomes = Vec::new();
+    for input in scenario() {
+        // disputes of deposits already spent take available funds below zero, which balances
+        // clamp, so they'd no longer add up to the net flows
+        if let TransactionInput::Dispute(tx, client) = input
+            && let Some(user) = engine.get(client)
+            && let Some(found) = user.transaction(tx)
+            && found.side == TransactionSide::Deposit
+            && user.balance().available < found.amount.widen().to_ticks()
+        {
+            continue;
+        }
+        outcomes.push(engine.apply(input).unwrap());
+    }
+    engine.verify().unwrap();
+    assert_eq!(engine.grand_total_ticks(), engine.net_flow_ticks());
+    let balances = engine.balances().collect();
+    let held = engine
+        .users()
+        .map(|user| user.held_transaction_ids())
+        .collect();
+    (outcomes, balances, held, engine.net_flow_ticks())
+}
+
+#[test]
+fn file_store_matches_the_in_memory_one() {
+    let rules = TxRules {
+        dispute_withdrawals: true,
+        allow_redispute: true,
+        ..TxRules::default()
+    };
+    let path = std::env::temp_dir().join(format!("csv_ledger_{}_store.bin", std::process::id()));
+
+    let in_memory = run(PaymentEngine::new().with_rules(rules));
+    let on_disk =
+        run(PaymentEngine::with_store(FileTxStore::factory(&path).unwrap()).with_rules(rules));
+
+    assert!(in_memory.0.contains(&TxOutcome::Applied));
+    assert!(in_memory.1.iter().any(|balance| balance.locked));
+    assert_eq!(in_memory, on_disk);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn tick_widths_agree_on_balances() {
+    let rules = TxRules {
+        dispute_withdrawals: true,
+        ..TxRules::default()
+    };
+    let narrow = run(
+        PaymentEngine::with_store(|_| HashMap::<u32, Transaction<i64>>::new()).with_rules(rules),
+    );
+    let wide = run(
+        PaymentEngine::with_store(|_| HashMap::<u32, Transaction<i128>>::new()).with_rules(rules),
+    );
+
+    assert!(narrow.1.iter().any(|balance| balance.held > 0));
+    assert_eq!(narrow, wide);
+    assert_eq!(narrow, run(PaymentEngine::new().with_rules(rules)));
+}
+
+#[test]
+fn file_store_writes_back_modified_transactions() {
+    let path = std::env::temp_dir().join(format!(
+        "csv_ledger_{}_store_write_back.bin",
+        std::process::id()
+    ));
+    let mut engine = PaymentEngine::with_store(FileTxStore::factory(&path).unwrap());
+    engine
+        .apply(TransactionInput::Deposit(1, 1, 10_000))
+        .unwrap();
+    engine
+        .apply(TransactionInput::Deposit(2, 1, 5_000))
+        .unwrap();
+    engine.apply(TransactionInput::Dispute(1, 1)).unwrap();
+    // touching another transaction flushes the disputed one to the file
+    engine.apply(TransactionInput::Dispute(2, 1)).unwrap();
+    engine.apply(TransactionInput::Resolve(2, 1)).unwrap();
+
+    assert_eq!(engine.get(1).unwrap().held_transaction_ids(), vec![1]);
+    assert_eq!(
+        engine.apply(TransactionInput::Chargeback(1, 1)).unwrap(),
+        TxOutcome::Applied
+    );
+    engine.verify().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
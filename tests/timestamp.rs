@@ -0,0 +1,97 @@
+mod common;
+
+use common::run_with_args;
+use csv_ledger::{AppError, parse_timestamp};
+
+#[test]
+fn timestamps_parse_as_epoch_millis_or_rfc3339() {
+    assert_eq!(parse_timestamp("1700000000000").unwrap(), 1_700_000_000_000);
+    assert_eq!(
+        parse_timestamp("2023-11-14T22:13:20Z").unwrap(),
+        1_700_000_000_000
+    );
+    assert_eq!(
+        parse_timestamp("2023-11-14T23:13:20.25+01:00").unwrap(),
+        1_700_000_000_250
+    );
+    assert_eq!(parse_timestamp("1969-12-31T23:59:59.999Z").unwrap(), -1);
+    assert_eq!(
+        parse_timestamp("2024-02-29T00:00:00Z").unwrap(),
+        1_709_164_800_000
+    );
+}
+
+#[test]
+fn malformed_timestamps_are_invalid_records() {
+    for raw in [
+        "",
+        "yesterday",
+        "2023-11-14",
+        "2023-11-14T22:13:20",
+        "2023-02-29T00:00:00Z",
+        "2023-11-14T24:00:00Z",
+        "2023-11-14T22:13:20.Z",
+        "2023-11-14T22:13:20+0100",
+    ] {
+        assert!(
+            matches!(parse_timestamp(raw), Err(AppError::InvalidRecord(_))),
+            "{}",
+            raw
+        );
+    }
+}
+
+#[test]
+fn shuffled_timestamps_apply_like_sorted_input() {
+    let sorted = "type,client,tx,amount,timestamp
+deposit,1,1,10.0,2024-01-01T00:00:00Z
+withdrawal,1,2,4.0,2024-01-01T00:00:01Z
+dispute,1,1,,2024-01-01T00:00:02Z
+deposit,2,3,3.0,1704067203000
+withdrawal,2,4,1.0,2024-01-01T00:00:04Z
+";
+    let shuffled = "timestamp,amount,tx,client,type
+2024-01-01T00:00:04Z,1.0,4,2,withdrawal
+2024-01-01T00:00:02Z,,1,1,dispute
+1704067203000,3.0,3,2,deposit
+2024-01-01T01:00:01+01:00,4.0,2,1,withdrawal
+2024-01-01T00:00:00Z,10.0,1,1,deposit
+";
+    let expected = run_with_args("timestamp_sorted", sorted, &[]).unwrap();
+    let output = run_with_args("timestamp_shuffled", shuffled, &["--sort-by-timestamp"]).unwrap();
+    assert_eq!(output, expected);
+    // in file order, the withdrawals and dispute find no funds yet
+    assert_ne!(
+        run_with_args("timestamp_unsorted", shuffled, &[]).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn rows_without_timestamps_follow_the_row_before_them() {
+    let input = "type,client,tx,amount,timestamp
+withdrawal,1,2,4.0,2024-01-01T00:00:05Z
+dispute,1,3,,
+deposit,1,1,10.0,2024-01-01T00:00:00Z
+deposit,1,3,1.0,
+";
+    let output = run_with_args("timestamp_partial", input, &["--sort-by-timestamp"]).unwrap();
+    // deposits first, then the withdrawal and the dispute that followed it
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,6.0000,1.0000,7.0000,false\n"
+    );
+}
+
+#[test]
+fn invalid_timestamps_abort_with_their_line() {
+    let input = "type,client,tx,amount,timestamp
+deposit,1,1,10.0,2024-01-01T00:00:00Z
+deposit,1,2,1.0,soon
+";
+    let err = run_with_args("timestamp_invalid", input, &["--sort-by-timestamp"]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 3: Invalid record for creating transaction: invalid timestamp \"soon\""
+    );
+}